@@ -0,0 +1,89 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+const ADC_FILENAME: &str = "application_default_credentials.json";
+const AUTHORIZED_USER: &str = "authorized_user";
+
+/// An `authorized_user` Application Default Credentials file, as written by
+/// `gcloud auth application-default login` and read by Google client libraries.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthorizedUser {
+    #[serde(rename = "type")]
+    pub cred_type: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub refresh_token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota_project_id: Option<String>,
+}
+
+impl AuthorizedUser {
+    pub fn new(client_id: &str, client_secret: &str, refresh_token: &str) -> Self {
+        AuthorizedUser {
+            cred_type: AUTHORIZED_USER.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            refresh_token: refresh_token.to_string(),
+            quota_project_id: None,
+        }
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let value: serde_json::Value = serde_json::from_str(&contents)
+            .with_context(|| format!("{} is not valid JSON", path.display()))?;
+        let cred_type = value.get("type").and_then(|t| t.as_str()).unwrap_or("");
+        if cred_type != AUTHORIZED_USER {
+            anyhow::bail!(
+                "{} has type '{}'; only '{}' credentials can be imported",
+                path.display(),
+                cred_type,
+                AUTHORIZED_USER
+            );
+        }
+        serde_json::from_value(value).with_context(|| {
+            format!(
+                "{} is missing client or refresh token fields",
+                path.display()
+            )
+        })
+    }
+
+    /// Write the file with owner-only permissions, replacing it atomically.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&tmp, fs::Permissions::from_mode(0o600))?;
+        }
+        fs::rename(&tmp, path).with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Where gcloud and the client libraries look for ADC: `$CLOUDSDK_CONFIG`,
+/// else `~/.config/gcloud` (`%APPDATA%\gcloud` on Windows).
+pub fn default_path() -> Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("CLOUDSDK_CONFIG") {
+        return Ok(PathBuf::from(dir).join(ADC_FILENAME));
+    }
+    #[cfg(target_os = "windows")]
+    let dir = dirs::config_dir().context("Could not determine %APPDATA%")?;
+    #[cfg(not(target_os = "windows"))]
+    let dir = dirs::home_dir()
+        .context("Could not determine home directory")?
+        .join(".config");
+    Ok(dir.join("gcloud").join(ADC_FILENAME))
+}
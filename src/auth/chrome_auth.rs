@@ -1,10 +1,17 @@
+use std::ffi::OsStr;
 use std::path::PathBuf;
 use std::time::Duration;
 
 use anyhow::{Context, Result};
-use headless_chrome::{Browser, LaunchOptions};
+use headless_chrome::{Browser, LaunchOptions, Tab};
 use serde::{Deserialize, Serialize};
 
+use super::login_script::LoginScript;
+use super::ReauthRequired;
+use crate::storage::ChromeConfig;
+
+const SLACK_CLIENT_URL: &str = "https://app.slack.com/client";
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SlackCredentials {
     pub token: String,
@@ -14,10 +21,42 @@ pub struct SlackCredentials {
     pub user_id: String,
 }
 
+/// Build launch options from the account's Chrome settings.
+fn launch_options(
+    chrome: &ChromeConfig,
+    profile_dir: PathBuf,
+    headless: bool,
+) -> LaunchOptions<'_> {
+    let env = (!chrome.env.is_empty()).then(|| chrome.env.clone());
+
+    LaunchOptions {
+        headless,
+        window_size: Some((1200, 900)),
+        user_data_dir: Some(profile_dir),
+        path: chrome.executable.clone(),
+        args: chrome.args.iter().map(OsStr::new).collect(),
+        proxy_server: chrome.proxy.as_deref(),
+        idle_browser_timeout: Duration::from_secs(chrome.idle_timeout_secs.unwrap_or(30)),
+        process_envs: env,
+        ..Default::default()
+    }
+}
+
+/// Apply per-tab overrides that can't be passed as launch flags.
+fn prepare_tab(tab: &Tab, chrome: &ChromeConfig) -> Result<()> {
+    if let Some(ua) = &chrome.user_agent {
+        tab.set_user_agent(ua, None, None)
+            .context("Failed to set user agent")?;
+    }
+    Ok(())
+}
+
 pub fn extract_credentials_with_chrome(
     profile_dir: PathBuf,
     existing_session: bool,
     headless: bool,
+    chrome: &ChromeConfig,
+    mut login: Option<&LoginScript>,
 ) -> Result<SlackCredentials> {
     if headless {
         eprintln!("Refreshing credentials (headless)...");
@@ -35,19 +74,15 @@ pub fn extract_credentials_with_chrome(
 
     std::fs::create_dir_all(&profile_dir)?;
 
-    let options = LaunchOptions {
-        headless,
-        window_size: Some((1200, 900)),
-        user_data_dir: Some(profile_dir),
-        ..Default::default()
-    };
+    let options = launch_options(chrome, profile_dir, headless);
 
     let browser =
         Browser::new(options).context("Failed to launch Chrome. Is Chrome/Chromium installed?")?;
 
     let tab = browser.new_tab().context("Failed to create browser tab")?;
+    prepare_tab(&tab, chrome)?;
 
-    tab.navigate_to("https://app.slack.com/client")
+    tab.navigate_to(SLACK_CLIENT_URL)
         .context("Failed to navigate to Slack")?;
 
     if existing_session {
@@ -172,25 +207,48 @@ pub fn extract_credentials_with_chrome(
                 }
             }
         }
+
+        // Headless refresh can't get past a sign-in page on its own; replay
+        // the login script once if there is one, otherwise give up early.
+        if headless && is_sign_in_page(&tab.get_url()) {
+            match login.take() {
+                Some(script) => {
+                    eprintln!("Session expired -- running login script...");
+                    script.run(&tab)?;
+                    tab.navigate_to(SLACK_CLIENT_URL)
+                        .context("Failed to navigate to Slack")?;
+                }
+                None => {
+                    return Err(ReauthRequired(
+                        "Slack session expired (redirected to sign-in page)".to_string(),
+                    )
+                    .into())
+                }
+            }
+        }
     }
 }
 
-pub fn extract_all_workspaces_with_chrome(profile_dir: PathBuf) -> Result<Vec<SlackCredentials>> {
+fn is_sign_in_page(url: &str) -> bool {
+    let url = url.to_ascii_lowercase();
+    url.contains("slack.com") && (url.contains("signin") || url.contains("sign_in"))
+}
+
+pub fn extract_all_workspaces_with_chrome(
+    profile_dir: PathBuf,
+    chrome: &ChromeConfig,
+) -> Result<Vec<SlackCredentials>> {
     eprintln!("Launching Chrome to extract all workspaces...");
 
     std::fs::create_dir_all(&profile_dir)?;
 
-    let options = LaunchOptions {
-        headless: false,
-        window_size: Some((1200, 900)),
-        user_data_dir: Some(profile_dir),
-        ..Default::default()
-    };
+    let options = launch_options(chrome, profile_dir, false);
 
     let browser = Browser::new(options)?;
     let tab = browser.new_tab()?;
+    prepare_tab(&tab, chrome)?;
 
-    tab.navigate_to("https://app.slack.com/client")?;
+    tab.navigate_to(SLACK_CLIENT_URL)?;
 
     eprintln!("Waiting for page load...");
     std::thread::sleep(Duration::from_secs(3));
@@ -2,20 +2,111 @@ use anyhow::{Context, Result};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::mpsc;
 use tiny_http::{Response, Server};
 use url::Url;
 
+use super::id_token::{self, GOOGLE_JWKS_URL};
 use super::pkce::PkceChallenge;
+use super::ReauthRequired;
+use crate::storage::IdentityClaims;
 
 // Thunderbird's public OAuth credentials for Google
 // Source: https://hg.mozilla.org/comm-central/file/tip/mailnews/base/src/OAuth2Providers.jsm
-const GOOGLE_CLIENT_ID: &str = "406964657835-aq8lmia8j95dhl1a2bvharmfk3t1hgof.apps.googleusercontent.com";
+const GOOGLE_CLIENT_ID: &str =
+    "406964657835-aq8lmia8j95dhl1a2bvharmfk3t1hgof.apps.googleusercontent.com";
 const GOOGLE_CLIENT_SECRET: &str = "kSmqreRr0qwBWJgbf5Y-PjSU";
 
 const REDIRECT_URI: &str = "http://localhost:8484/callback";
 const AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
 const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const REVOKE_URL: &str = "https://oauth2.googleapis.com/revoke";
+/// Google access tokens last an hour; used if a response omits expires_in.
+const DEFAULT_EXPIRES_IN: u64 = 3600;
+
+/// An OAuth client (GCP project credentials) used to obtain and refresh tokens.
+#[derive(Debug, Clone)]
+pub struct OAuthClient {
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    pub auth_url: String,
+    pub token_url: String,
+    pub revoke_url: String,
+    /// Google's signing keys, for verifying id_tokens.
+    pub jwks_url: String,
+}
+
+impl OAuthClient {
+    /// Thunderbird's public desktop client -- the default when nothing is configured.
+    pub fn thunderbird() -> Self {
+        Self::new(GOOGLE_CLIENT_ID, GOOGLE_CLIENT_SECRET)
+    }
+
+    pub fn new(client_id: &str, client_secret: &str) -> Self {
+        Self {
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            redirect_uri: REDIRECT_URI.to_string(),
+            auth_url: AUTH_URL.to_string(),
+            token_url: TOKEN_URL.to_string(),
+            revoke_url: REVOKE_URL.to_string(),
+            jwks_url: GOOGLE_JWKS_URL.to_string(),
+        }
+    }
+
+    /// Load a `client_secret.json` downloaded from the Google Cloud console
+    /// ("installed" or "web" application).
+    pub fn from_secret_file(path: &Path) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct SecretFile {
+            installed: Option<SecretEntry>,
+            web: Option<SecretEntry>,
+        }
+        #[derive(Deserialize)]
+        struct SecretEntry {
+            client_id: String,
+            client_secret: String,
+            auth_uri: Option<String>,
+            token_uri: Option<String>,
+        }
+
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let file: SecretFile = serde_json::from_str(&contents)
+            .with_context(|| format!("{} is not a Google client secret file", path.display()))?;
+        let entry = file
+            .installed
+            .or(file.web)
+            .with_context(|| format!("{} has no 'installed' or 'web' client", path.display()))?;
+
+        let mut client = Self::new(&entry.client_id, &entry.client_secret);
+        if let Some(url) = entry.auth_uri {
+            client.auth_url = url;
+        }
+        if let Some(url) = entry.token_uri {
+            client.token_url = url;
+        }
+        Ok(client)
+    }
+
+    /// Local address the callback server listens on, taken from the redirect URI.
+    fn listen_addr(&self) -> Result<String> {
+        let url = Url::parse(&self.redirect_uri).context("Invalid redirect URI")?;
+        let port = url
+            .port_or_known_default()
+            .context("Redirect URI has no port")?;
+        match url.host_str() {
+            Some("localhost") | Some("127.0.0.1") => Ok(format!("127.0.0.1:{}", port)),
+            Some("[::1]") => Ok(format!("[::1]:{}", port)),
+            _ => anyhow::bail!(
+                "Redirect URI must point at localhost: {}",
+                self.redirect_uri
+            ),
+        }
+    }
+}
 
 // All scopes we request - grab everything useful for third-party clients
 pub const SCOPE_GMAIL: &str = "https://mail.google.com/";
@@ -26,6 +117,9 @@ pub const SCOPE_DRIVE: &str = "https://www.googleapis.com/auth/drive";
 pub const SCOPE_YOUTUBE: &str = "https://www.googleapis.com/auth/youtube";
 pub const SCOPE_PHOTOS: &str = "https://www.googleapis.com/auth/photoslibrary";
 pub const SCOPE_USERINFO: &str = "https://www.googleapis.com/auth/userinfo.email";
+/// Always requested alongside the caller's scopes so the token response
+/// carries an id_token identifying the user.
+const IDENTITY_SCOPES: &str = "openid email profile";
 
 pub const ALL_SCOPES: &[&str] = &[
     SCOPE_GMAIL,
@@ -37,6 +131,50 @@ pub const ALL_SCOPES: &[&str] = &[
     SCOPE_PHOTOS,
 ];
 
+/// Friendly names accepted by `--scopes`, mapped to scope URLs.
+pub const SCOPE_ALIASES: &[(&str, &str)] = &[
+    ("gmail", SCOPE_GMAIL),
+    ("calendar", SCOPE_CALENDAR),
+    ("contacts", SCOPE_CONTACTS),
+    ("tasks", SCOPE_TASKS),
+    ("drive", SCOPE_DRIVE),
+    ("youtube", SCOPE_YOUTUBE),
+    ("photos", SCOPE_PHOTOS),
+    ("email", SCOPE_USERINFO),
+];
+
+const SCOPE_URL_PREFIX: &str = "https://www.googleapis.com/auth/";
+
+/// Expand an alias to a scope URL. Full URLs and `openid` pass through;
+/// other names map under googleapis.com/auth/ (e.g. "calendar.readonly").
+pub fn resolve_scope(name: &str) -> String {
+    let name = name.trim();
+    if let Some((_, url)) = SCOPE_ALIASES.iter().find(|(alias, _)| *alias == name) {
+        return url.to_string();
+    }
+    if name.contains("://") || name == "openid" {
+        return name.to_string();
+    }
+    format!("{}{}", SCOPE_URL_PREFIX, name)
+}
+
+/// Short display name for a scope URL (the reverse of `resolve_scope`).
+pub fn scope_alias(url: &str) -> &str {
+    if let Some((alias, _)) = SCOPE_ALIASES.iter().find(|(_, u)| *u == url) {
+        return alias;
+    }
+    url.strip_prefix(SCOPE_URL_PREFIX).unwrap_or(url)
+}
+
+/// Extra authorization-request parameters.
+#[derive(Debug, Default)]
+pub struct AuthorizeOptions {
+    /// Incremental authorization: keep previously granted scopes.
+    pub include_granted_scopes: bool,
+    /// Preselect the Google account in the consent screen.
+    pub login_hint: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GoogleCredentials {
     pub access_token: String,
@@ -44,15 +182,19 @@ pub struct GoogleCredentials {
     pub email: String,
     pub scopes: Vec<String>,
     pub expires_at: u64,
+    pub id_token: Option<String>,
+    /// Verified claims from `id_token`.
+    pub claims: Option<IdentityClaims>,
 }
 
 #[derive(Debug, Deserialize)]
 struct TokenResponse {
-    access_token: String,
+    // Absent in error responses, so optional until `error` has been checked
+    access_token: Option<String>,
     refresh_token: Option<String>,
-    expires_in: u64,
+    expires_in: Option<u64>,
     scope: Option<String>,
-    token_type: String,
+    id_token: Option<String>,
     #[serde(default)]
     error: Option<String>,
     #[serde(default)]
@@ -64,7 +206,14 @@ struct UserInfo {
     email: String,
 }
 
-pub fn authenticate(scopes: &[&str]) -> Result<GoogleCredentials> {
+/// Run the browser consent flow. `jwks_cache` is where Google's signing
+/// keys are cached for verifying the returned id_token.
+pub fn authenticate(
+    client: &OAuthClient,
+    scopes: &[String],
+    options: &AuthorizeOptions,
+    jwks_cache: &Path,
+) -> Result<GoogleCredentials> {
     let pkce = PkceChallenge::generate();
     let state: String = rand::thread_rng()
         .sample_iter(&rand::distributions::Alphanumeric)
@@ -74,26 +223,34 @@ pub fn authenticate(scopes: &[&str]) -> Result<GoogleCredentials> {
 
     let scope_str = scopes.join(" ");
 
-    let mut auth_url = Url::parse(AUTH_URL)?;
+    let mut auth_url = Url::parse(&client.auth_url)?;
     auth_url
         .query_pairs_mut()
-        .append_pair("client_id", GOOGLE_CLIENT_ID)
-        .append_pair("redirect_uri", REDIRECT_URI)
+        .append_pair("client_id", &client.client_id)
+        .append_pair("redirect_uri", &client.redirect_uri)
         .append_pair("response_type", "code")
-        .append_pair("scope", &format!("{} {}", scope_str, SCOPE_USERINFO))
+        .append_pair("scope", &format!("{} {}", scope_str, IDENTITY_SCOPES))
         .append_pair("state", &state)
         .append_pair("code_challenge", &pkce.challenge)
         .append_pair("code_challenge_method", "S256")
         .append_pair("access_type", "offline")
         .append_pair("prompt", "consent"); // Force consent to get refresh_token
+    if options.include_granted_scopes {
+        auth_url
+            .query_pairs_mut()
+            .append_pair("include_granted_scopes", "true");
+    }
+    if let Some(hint) = &options.login_hint {
+        auth_url.query_pairs_mut().append_pair("login_hint", hint);
+    }
 
     eprintln!("Opening browser for Google authentication...");
     eprintln!("If browser doesn't open, visit:\n{}", auth_url);
 
-    open_browser(&auth_url.to_string())?;
+    open_browser(auth_url.as_ref())?;
 
     let (tx, rx) = mpsc::channel();
-    let server = Server::http("127.0.0.1:8484")
+    let server = Server::http(client.listen_addr()?)
         .map_err(|e| anyhow::anyhow!("Failed to start local callback server: {}", e))?;
 
     eprintln!("Waiting for authorization...");
@@ -119,77 +276,164 @@ pub fn authenticate(scopes: &[&str]) -> Result<GoogleCredentials> {
             tx.send(code.to_string())?;
             break;
         } else if let Some(error) = params.get("error") {
-            let desc = params.get("error_description").map(|s| s.to_string()).unwrap_or_default();
-            let response = Response::from_string(format!("Authorization failed: {} - {}", error, desc));
+            let desc = params
+                .get("error_description")
+                .map(|s| s.to_string())
+                .unwrap_or_default();
+            let response =
+                Response::from_string(format!("Authorization failed: {} - {}", error, desc));
             let _ = request.respond(response);
             anyhow::bail!("Authorization failed: {} - {}", error, desc);
         }
     }
 
     let code = rx.recv()?;
-    let tokens = exchange_code_for_token(&code, &pkce.verifier)?;
+    let tokens = exchange_code_for_token(client, &code, &pkce.verifier)?;
 
-    // Get user email
-    let email = get_user_email(&tokens.access_token)?;
+    let access_token = tokens
+        .access_token
+        .context("Token response has no access_token")?;
+
+    let claims = match &tokens.id_token {
+        Some(token) => Some(verify_id_token(client, token, jwks_cache)?),
+        None => None,
+    };
+    let email = match claims.as_ref().and_then(|c| c.email.clone()) {
+        Some(email) => email,
+        None => get_user_email(&access_token)?,
+    };
 
     let expires_at = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)?
         .as_secs()
-        + tokens.expires_in;
+        + tokens.expires_in.unwrap_or(DEFAULT_EXPIRES_IN);
 
     Ok(GoogleCredentials {
-        access_token: tokens.access_token,
+        access_token,
         refresh_token: tokens.refresh_token.context("No refresh token received. Try revoking app access at https://myaccount.google.com/permissions and re-authenticating.")?,
         email,
-        scopes: scopes.iter().map(|s| s.to_string()).collect(),
+        // What the user actually granted, which can be less than requested
+        scopes: match &tokens.scope {
+            Some(granted) => granted.split_whitespace().map(str::to_string).collect(),
+            None => scopes.to_vec(),
+        },
         expires_at,
+        id_token: tokens.id_token,
+        claims,
     })
 }
 
-pub fn refresh_token(refresh_token: &str) -> Result<(String, u64)> {
-    let client = reqwest::blocking::Client::new();
+/// Verify an id_token issued to `client` and return its claims.
+pub fn verify_id_token(
+    client: &OAuthClient,
+    token: &str,
+    jwks_cache: &Path,
+) -> Result<IdentityClaims> {
+    id_token::verify(token, &client.client_id, &client.jwks_url, jwks_cache)
+}
+
+/// A new access token from a refresh-token grant.
+#[derive(Debug, Clone)]
+pub struct RefreshedToken {
+    pub access_token: String,
+    pub expires_at: u64,
+    /// Scopes the new token carries, when Google reports them.
+    pub scopes: Option<Vec<String>>,
+    /// A fresh id_token, returned when the grant includes `openid`.
+    pub id_token: Option<String>,
+}
+
+/// Exchange a refresh token for a new access token. With `scopes`, the token
+/// is limited to that subset of the originally granted scopes.
+pub fn refresh_token(
+    client: &OAuthClient,
+    refresh_token: &str,
+    scopes: Option<&[String]>,
+) -> Result<RefreshedToken> {
+    let http = reqwest::blocking::Client::new();
 
+    let scope_str = scopes.map(|s| s.join(" "));
     let mut params = HashMap::new();
-    params.insert("client_id", GOOGLE_CLIENT_ID);
-    params.insert("client_secret", GOOGLE_CLIENT_SECRET);
+    params.insert("client_id", client.client_id.as_str());
+    params.insert("client_secret", client.client_secret.as_str());
     params.insert("refresh_token", refresh_token);
     params.insert("grant_type", "refresh_token");
+    if let Some(scope) = &scope_str {
+        params.insert("scope", scope.as_str());
+    }
 
-    let response: TokenResponse = client
-        .post(TOKEN_URL)
-        .form(&params)
-        .send()?
-        .json()?;
+    let response: TokenResponse = http.post(&client.token_url).form(&params).send()?.json()?;
 
     if let Some(error) = response.error {
         let desc = response.error_description.unwrap_or_default();
+        if error == "invalid_grant" {
+            // Revoked, expired, or issued to a client we no longer use
+            return Err(ReauthRequired(format!(
+                "Google refresh token is no longer valid: {} - {}",
+                error, desc
+            ))
+            .into());
+        }
         anyhow::bail!("Token refresh failed: {} - {}", error, desc);
     }
 
+    let access_token = response
+        .access_token
+        .context("Token response has no access_token")?;
     let expires_at = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)?
         .as_secs()
-        + response.expires_in;
+        + response.expires_in.unwrap_or(DEFAULT_EXPIRES_IN);
 
-    Ok((response.access_token, expires_at))
+    Ok(RefreshedToken {
+        access_token,
+        expires_at,
+        scopes: response
+            .scope
+            .map(|s| s.split_whitespace().map(str::to_string).collect()),
+        id_token: response.id_token,
+    })
 }
 
-fn exchange_code_for_token(code: &str, verifier: &str) -> Result<TokenResponse> {
-    let client = reqwest::blocking::Client::new();
+/// Revoke a refresh (or access) token upstream. Returns false if Google
+/// reports the token as already invalid.
+pub fn revoke_token(client: &OAuthClient, token: &str) -> Result<bool> {
+    let http = reqwest::blocking::Client::new();
+    let response = http
+        .post(&client.revoke_url)
+        .form(&[("token", token)])
+        .send()
+        .context("Failed to reach Google's revoke endpoint")?;
+
+    let status = response.status();
+    if status.is_success() {
+        return Ok(true);
+    }
+
+    let body: serde_json::Value = response.json().unwrap_or_default();
+    let error = body.get("error").and_then(|v| v.as_str()).unwrap_or("");
+    if error == "invalid_token" {
+        return Ok(false);
+    }
+    anyhow::bail!("Token revocation failed ({}): {}", status, body)
+}
+
+fn exchange_code_for_token(
+    client: &OAuthClient,
+    code: &str,
+    verifier: &str,
+) -> Result<TokenResponse> {
+    let http = reqwest::blocking::Client::new();
 
     let mut params = HashMap::new();
-    params.insert("client_id", GOOGLE_CLIENT_ID);
-    params.insert("client_secret", GOOGLE_CLIENT_SECRET);
+    params.insert("client_id", client.client_id.as_str());
+    params.insert("client_secret", client.client_secret.as_str());
     params.insert("code", code);
-    params.insert("redirect_uri", REDIRECT_URI);
+    params.insert("redirect_uri", client.redirect_uri.as_str());
     params.insert("code_verifier", verifier);
     params.insert("grant_type", "authorization_code");
 
-    let response: TokenResponse = client
-        .post(TOKEN_URL)
-        .form(&params)
-        .send()?
-        .json()?;
+    let response: TokenResponse = http.post(&client.token_url).form(&params).send()?.json()?;
 
     if let Some(error) = response.error {
         let desc = response.error_description.unwrap_or_default();
@@ -199,7 +443,8 @@ fn exchange_code_for_token(code: &str, verifier: &str) -> Result<TokenResponse>
     Ok(response)
 }
 
-fn get_user_email(access_token: &str) -> Result<String> {
+/// The signed-in user's email, from the userinfo endpoint.
+pub fn get_user_email(access_token: &str) -> Result<String> {
     let client = reqwest::blocking::Client::new();
 
     let response: UserInfo = client
@@ -225,3 +470,30 @@ fn open_browser(url: &str) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::mock_http;
+
+    fn client_with(revoke_url: String) -> OAuthClient {
+        OAuthClient {
+            revoke_url,
+            ..OAuthClient::new("id", "secret")
+        }
+    }
+
+    #[test]
+    fn revoke_reports_already_invalid_tokens() {
+        let url = mock_http(vec![
+            (200, "{}".to_string()),
+            (400, r#"{"error": "invalid_token"}"#.to_string()),
+            (400, r#"{"error": "invalid_request"}"#.to_string()),
+        ]);
+        let client = client_with(url);
+        assert!(revoke_token(&client, "rt").unwrap());
+        assert!(!revoke_token(&client, "rt").unwrap());
+        assert!(revoke_token(&client, "rt").is_err());
+    }
+}
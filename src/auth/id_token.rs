@@ -0,0 +1,335 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rsa::pkcs1v15::{Signature, VerifyingKey};
+use rsa::signature::Verifier;
+use rsa::{BigUint, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::storage::IdentityClaims;
+
+pub const GOOGLE_JWKS_URL: &str = "https://www.googleapis.com/oauth2/v3/certs";
+const GOOGLE_ISSUERS: &[&str] = &["https://accounts.google.com", "accounts.google.com"];
+/// Google rotates its signing keys every few days; an unknown `kid` forces a
+/// refetch regardless.
+const JWKS_MAX_AGE_SECS: u64 = 6 * 60 * 60;
+const CLOCK_SKEW_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Jwk {
+    kid: String,
+    #[serde(default)]
+    kty: String,
+    #[serde(default)]
+    n: String,
+    #[serde(default)]
+    e: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+/// JWKS as cached on disk, tagged with where and when it was fetched.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedJwks {
+    url: String,
+    fetched_at: u64,
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Header {
+    alg: String,
+    kid: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    iss: String,
+    aud: String,
+    exp: u64,
+    sub: String,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    hd: Option<String>,
+    name: Option<String>,
+}
+
+/// Verify a Google id_token's RS256 signature against the JWKS at
+/// `jwks_url` (cached in `cache_file`) and check issuer, audience and expiry.
+pub fn verify(
+    id_token: &str,
+    client_id: &str,
+    jwks_url: &str,
+    cache_file: &Path,
+) -> Result<IdentityClaims> {
+    let mut parts = id_token.split('.');
+    let (Some(header_b64), Some(claims_b64), Some(signature_b64), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        anyhow::bail!("id_token is not a JWT");
+    };
+
+    let header: Header =
+        serde_json::from_slice(&decode(header_b64)?).context("id_token has an invalid header")?;
+    if header.alg != "RS256" {
+        anyhow::bail!("id_token uses unsupported algorithm {}", header.alg);
+    }
+    let kid = header.kid.context("id_token header has no key id")?;
+
+    let jwk = signing_key(&kid, jwks_url, cache_file)?;
+    let key = RsaPublicKey::new(
+        BigUint::from_bytes_be(&decode(&jwk.n)?),
+        BigUint::from_bytes_be(&decode(&jwk.e)?),
+    )
+    .with_context(|| format!("Signing key {} is not a valid RSA key", kid))?;
+    let signature = Signature::try_from(decode(signature_b64)?.as_slice())
+        .context("id_token has a malformed signature")?;
+    let signed = &id_token[..header_b64.len() + 1 + claims_b64.len()];
+    VerifyingKey::<Sha256>::new(key)
+        .verify(signed.as_bytes(), &signature)
+        .map_err(|_| anyhow::anyhow!("id_token signature does not verify"))?;
+
+    let claims: Claims =
+        serde_json::from_slice(&decode(claims_b64)?).context("id_token has invalid claims")?;
+    if !GOOGLE_ISSUERS.contains(&claims.iss.as_str()) {
+        anyhow::bail!("id_token was issued by {}, not Google", claims.iss);
+    }
+    if claims.aud != client_id {
+        anyhow::bail!(
+            "id_token was issued to {}, not OAuth client {}",
+            claims.aud,
+            client_id
+        );
+    }
+    if claims.exp + CLOCK_SKEW_SECS < now() {
+        anyhow::bail!("id_token has expired");
+    }
+
+    Ok(IdentityClaims {
+        sub: claims.sub,
+        email: claims.email,
+        email_verified: claims.email_verified,
+        hd: claims.hd,
+        name: claims.name,
+    })
+}
+
+/// The `exp` claim of a stored id_token, read without verifying it: the
+/// token was verified when it was stored, this only says how long it lasts.
+pub fn expires_at(id_token: &str) -> Option<u64> {
+    #[derive(Deserialize)]
+    struct Expiry {
+        exp: u64,
+    }
+    let claims = decode(id_token.split('.').nth(1)?).ok()?;
+    serde_json::from_slice::<Expiry>(&claims)
+        .ok()
+        .map(|c| c.exp)
+}
+
+/// The JWK for `kid`, from the cache while it's fresh, else refetched.
+fn signing_key(kid: &str, jwks_url: &str, cache_file: &Path) -> Result<Jwk> {
+    let cached = std::fs::read_to_string(cache_file)
+        .ok()
+        .and_then(|s| serde_json::from_str::<CachedJwks>(&s).ok())
+        .filter(|c| c.url == jwks_url && now() < c.fetched_at + JWKS_MAX_AGE_SECS);
+    if let Some(jwk) = cached.and_then(|c| find_key(c.keys, kid)) {
+        return Ok(jwk);
+    }
+
+    let set: JwkSet = reqwest::blocking::get(jwks_url)
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("Failed to fetch signing keys from {}", jwks_url))?
+        .json()
+        .with_context(|| format!("{} did not return a JWK set", jwks_url))?;
+
+    let cache = CachedJwks {
+        url: jwks_url.to_string(),
+        fetched_at: now(),
+        keys: set.keys,
+    };
+    write_cache(cache_file, &cache)?;
+
+    find_key(cache.keys, kid).with_context(|| format!("No signing key {} at {}", kid, jwks_url))
+}
+
+/// Replace the cache file atomically: concurrent verifications (e.g. proxy
+/// threads) must never read a half-written file.
+fn write_cache(cache_file: &Path, cache: &CachedJwks) -> Result<()> {
+    if let Some(dir) = cache_file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Unique per writer, so two writers never share a temporary file
+    let tmp = cache_file.with_extension(format!("{:016x}.tmp", rand::random::<u64>()));
+    std::fs::write(&tmp, serde_json::to_string(cache)?)
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, cache_file).map_err(|e| {
+        let _ = std::fs::remove_file(&tmp);
+        anyhow::anyhow!("Failed to write {}: {}", cache_file.display(), e)
+    })
+}
+
+fn find_key(keys: Vec<Jwk>, kid: &str) -> Option<Jwk> {
+    keys.into_iter().find(|k| k.kid == kid && k.kty == "RSA")
+}
+
+fn decode(part: &str) -> Result<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(part)
+        .context("id_token is not valid base64url")
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::OnceLock;
+
+    use rsa::pkcs1v15::SigningKey;
+    use rsa::signature::{SignatureEncoding, Signer};
+    use rsa::traits::PublicKeyParts;
+    use rsa::RsaPrivateKey;
+
+    use crate::testing::{mock_http, temp_dir};
+
+    const CLIENT_ID: &str = "client.apps.googleusercontent.com";
+
+    /// Two signing keys, generated once for all tests.
+    fn keys() -> &'static [RsaPrivateKey; 2] {
+        static KEYS: OnceLock<[RsaPrivateKey; 2]> = OnceLock::new();
+        KEYS.get_or_init(|| {
+            let mut rng = rand::thread_rng();
+            [
+                RsaPrivateKey::new(&mut rng, 1024).unwrap(),
+                RsaPrivateKey::new(&mut rng, 1024).unwrap(),
+            ]
+        })
+    }
+
+    /// A JWKS document publishing key `i` of `keys()` as "k<i>" for each i.
+    fn jwks(indices: &[usize]) -> String {
+        let keys: Vec<_> = indices
+            .iter()
+            .map(|&i| {
+                let public = keys()[i].to_public_key();
+                serde_json::json!({
+                    "kid": format!("k{}", i),
+                    "kty": "RSA",
+                    "n": URL_SAFE_NO_PAD.encode(public.n().to_bytes_be()),
+                    "e": URL_SAFE_NO_PAD.encode(public.e().to_bytes_be()),
+                })
+            })
+            .collect();
+        serde_json::json!({ "keys": keys }).to_string()
+    }
+
+    /// An id_token for CLIENT_ID signed with key `i`, with `overrides`
+    /// applied to otherwise valid claims.
+    fn token(i: usize, overrides: serde_json::Value) -> String {
+        let header = serde_json::json!({ "alg": "RS256", "kid": format!("k{}", i) });
+        let mut claims = serde_json::json!({
+            "iss": "https://accounts.google.com",
+            "aud": CLIENT_ID,
+            "exp": now() + 3600,
+            "sub": "1234",
+            "email": "alice@example.com",
+        });
+        for (name, value) in overrides.as_object().unwrap() {
+            claims[name] = value.clone();
+        }
+        let signed = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+        let signature = SigningKey::<Sha256>::new(keys()[i].clone()).sign(signed.as_bytes());
+        format!(
+            "{}.{}",
+            signed,
+            URL_SAFE_NO_PAD.encode(signature.to_bytes())
+        )
+    }
+
+    #[test]
+    fn verifies_and_caches_keys() {
+        let url = mock_http(vec![(200, jwks(&[0]))]);
+        let dir = temp_dir();
+        let cache = dir.join("jwks.json");
+
+        let claims = verify(&token(0, serde_json::json!({})), CLIENT_ID, &url, &cache).unwrap();
+        assert_eq!(claims.sub, "1234");
+        assert_eq!(claims.email.as_deref(), Some("alice@example.com"));
+        assert!(cache.exists());
+        // Only the cache file; no temporary files left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // The server is gone now, so this is served from the cache
+        verify(&token(0, serde_json::json!({})), CLIENT_ID, &url, &cache).unwrap();
+    }
+
+    #[test]
+    fn unknown_key_id_refetches() {
+        let url = mock_http(vec![(200, jwks(&[0])), (200, jwks(&[0, 1]))]);
+        let cache = temp_dir().join("jwks.json");
+
+        verify(&token(0, serde_json::json!({})), CLIENT_ID, &url, &cache).unwrap();
+        verify(&token(1, serde_json::json!({})), CLIENT_ID, &url, &cache).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_tokens() {
+        let url = mock_http(vec![(200, jwks(&[0]))]);
+        let cache = temp_dir().join("jwks.json");
+        let check = |token: String| verify(&token, CLIENT_ID, &url, &cache);
+
+        check(token(0, serde_json::json!({}))).unwrap();
+        assert!(check(token(0, serde_json::json!({ "aud": "other" }))).is_err());
+        assert!(check(token(
+            0,
+            serde_json::json!({ "iss": "https://evil.example" })
+        ))
+        .is_err());
+        assert!(check(token(0, serde_json::json!({ "exp": now() - 3600 }))).is_err());
+
+        // Claims swapped under a valid signature
+        let good = token(0, serde_json::json!({}));
+        let other = token(0, serde_json::json!({ "sub": "5678" }));
+        let parts: Vec<&str> = good.split('.').collect();
+        let forged = format!(
+            "{}.{}.{}",
+            parts[0],
+            other.split('.').nth(1).unwrap(),
+            parts[2]
+        );
+        assert!(check(forged).is_err());
+    }
+
+    #[test]
+    fn reads_expiry_without_verifying() {
+        let exp = now() + 600;
+        let forged = token(1, serde_json::json!({ "exp": exp, "aud": "someone-else" }));
+        assert_eq!(expires_at(&forged), Some(exp));
+        assert_eq!(expires_at("not-a-jwt"), None);
+        assert_eq!(expires_at("a.e30.c"), None);
+    }
+
+    #[test]
+    fn unreachable_jwks_fails() {
+        let url = mock_http(vec![(500, "oops".to_string())]);
+        let cache = temp_dir().join("jwks.json");
+        assert!(verify(&token(0, serde_json::json!({})), CLIENT_ID, &url, &cache).is_err());
+        assert!(!cache.exists());
+    }
+}
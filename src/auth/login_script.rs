@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use headless_chrome::Tab;
+
+use super::totp::TotpSeed;
+use crate::storage::LoginStep;
+
+const DEFAULT_WAIT_SECS: u64 = 20;
+
+/// A login script together with the secrets it may reference.
+pub struct LoginScript {
+    pub steps: Vec<LoginStep>,
+    pub secrets: HashMap<String, String>,
+    /// Seeds of totp accounts referenced by `totp` steps, keyed by label.
+    pub totp_accounts: HashMap<String, TotpSeed>,
+}
+
+impl LoginScript {
+    /// Check that every step is well-formed and every referenced secret
+    /// exists. `run` calls this once the sign-in page actually shows up, so
+    /// a broken script doesn't get in the way while the session is valid.
+    pub fn check(&self) -> Result<()> {
+        for step in &self.steps {
+            let name = match step {
+                LoginStep::Fill {
+                    value: None,
+                    secret: None,
+                    selector,
+                } => anyhow::bail!("Login step 'fill {}' needs a value or a secret", selector),
+                LoginStep::Fill {
+                    value: Some(_),
+                    secret: Some(_),
+                    selector,
+                } => anyhow::bail!(
+                    "Login step 'fill {}' has both a value and a secret; keep one",
+                    selector
+                ),
+                LoginStep::Fill {
+                    secret: Some(name), ..
+                } => name,
+                LoginStep::Totp {
+                    account: Some(label),
+                    secret: None,
+                    ..
+                } => {
+                    if !self.totp_accounts.contains_key(label) {
+                        anyhow::bail!("Login script references unknown totp account '{}'", label);
+                    }
+                    continue;
+                }
+                LoginStep::Totp {
+                    secret: Some(name),
+                    account: None,
+                    ..
+                } => name,
+                LoginStep::Totp { selector, .. } => anyhow::bail!(
+                    "Login step 'totp {}' needs exactly one of secret or account",
+                    selector
+                ),
+                _ => continue,
+            };
+            if !self.secrets.contains_key(name) {
+                anyhow::bail!(
+                    "Login script references secret '{}' which is not stored (use `tokey secret set`)",
+                    name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn run(&self, tab: &Tab) -> Result<()> {
+        self.check()?;
+
+        for (i, step) in self.steps.iter().enumerate() {
+            let n = i + 1;
+            match step {
+                LoginStep::Navigate { url } => {
+                    eprintln!("  [{}] navigate {}", n, url);
+                    tab.navigate_to(url)
+                        .and_then(|t| t.wait_until_navigated())
+                        .with_context(|| format!("Login step {}: failed to load {}", n, url))?;
+                }
+                LoginStep::Fill {
+                    selector,
+                    value,
+                    secret,
+                } => {
+                    eprintln!("  [{}] fill {}", n, selector);
+                    let text = match (value, secret) {
+                        (None, Some(name)) => &self.secrets[name],
+                        (Some(v), None) => v,
+                        _ => unreachable!("checked above"),
+                    };
+                    wait_for(tab, selector, DEFAULT_WAIT_SECS, n)?
+                        .type_into(text)
+                        .with_context(|| format!("Login step {}: failed to type", n))?;
+                }
+                LoginStep::Click { selector } => {
+                    eprintln!("  [{}] click {}", n, selector);
+                    wait_for(tab, selector, DEFAULT_WAIT_SECS, n)?
+                        .click()
+                        .with_context(|| format!("Login step {}: failed to click", n))?;
+                }
+                LoginStep::WaitFor {
+                    selector,
+                    timeout_secs,
+                } => {
+                    eprintln!("  [{}] wait for {}", n, selector);
+                    wait_for(tab, selector, timeout_secs.unwrap_or(DEFAULT_WAIT_SECS), n)?;
+                }
+                LoginStep::Totp {
+                    selector,
+                    secret,
+                    account,
+                } => {
+                    eprintln!("  [{}] totp {}", n, selector);
+                    let (code, _) = match (secret, account) {
+                        (Some(name), _) => TotpSeed::parse(&self.secrets[name])?.current_code()?,
+                        (None, Some(label)) => self.totp_accounts[label].current_code()?,
+                        (None, None) => unreachable!("checked above"),
+                    };
+                    wait_for(tab, selector, DEFAULT_WAIT_SECS, n)?
+                        .type_into(&code)
+                        .with_context(|| format!("Login step {}: failed to type", n))?;
+                }
+                LoginStep::Sleep { secs } => {
+                    eprintln!("  [{}] sleep {}s", n, secs);
+                    std::thread::sleep(Duration::from_secs(*secs));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn wait_for<'a>(
+    tab: &'a Tab,
+    selector: &str,
+    timeout_secs: u64,
+    step: usize,
+) -> Result<headless_chrome::Element<'a>> {
+    tab.wait_for_element_with_custom_timeout(selector, Duration::from_secs(timeout_secs))
+        .with_context(|| format!("Login step {}: element '{}' not found", step, selector))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(steps: Vec<LoginStep>) -> LoginScript {
+        LoginScript {
+            steps,
+            secrets: HashMap::from([("password".to_string(), "hunter2".to_string())]),
+            totp_accounts: HashMap::new(),
+        }
+    }
+
+    fn fill(value: Option<&str>, secret: Option<&str>) -> LoginStep {
+        LoginStep::Fill {
+            selector: "#pw".to_string(),
+            value: value.map(str::to_string),
+            secret: secret.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn fill_takes_exactly_one_source() {
+        assert!(script(vec![fill(Some("me"), None)]).check().is_ok());
+        assert!(script(vec![fill(None, Some("password"))]).check().is_ok());
+        assert!(script(vec![fill(None, None)]).check().is_err());
+        let both = script(vec![fill(Some("me"), Some("password"))]).check();
+        assert!(both.unwrap_err().to_string().contains("both"));
+    }
+
+    #[test]
+    fn unknown_references_fail() {
+        assert!(script(vec![fill(None, Some("missing"))]).check().is_err());
+        let totp = LoginStep::Totp {
+            selector: "#code".to_string(),
+            secret: None,
+            account: Some("github".to_string()),
+        };
+        assert!(script(vec![totp]).check().is_err());
+    }
+}
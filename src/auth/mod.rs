@@ -1,5 +1,26 @@
+pub mod adc;
 pub mod browser_auth;
 pub mod chrome_auth;
 pub mod google_oauth;
+pub mod id_token;
+pub mod login_script;
 pub mod oauth;
 pub mod pkce;
+pub mod prompt;
+pub mod sasl;
+pub mod service_account;
+pub mod totp;
+
+/// The stored grant or session is gone for good (revoked refresh token,
+/// signed-out Slack session, disabled service-account key). Refreshing
+/// again won't help; the user has to sign in with `tokey reauth`.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct ReauthRequired(pub String);
+
+impl ReauthRequired {
+    /// Whether `err` or anything in its context chain is a ReauthRequired.
+    pub fn is_cause_of(err: &anyhow::Error) -> bool {
+        err.chain().any(|e| e.is::<ReauthRequired>())
+    }
+}
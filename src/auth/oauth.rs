@@ -87,7 +87,7 @@ pub fn start_oauth_flow() -> Result<OAuthResult> {
     eprintln!("Opening browser for Slack authentication...");
     eprintln!("If browser doesn't open, visit:\n{}", auth_url);
 
-    open_browser(&auth_url.to_string())?;
+    open_browser(auth_url.as_ref())?;
 
     let (tx, rx) = mpsc::channel();
     let server = Server::http("127.0.0.1:8484")
@@ -115,7 +115,7 @@ pub fn start_oauth_flow() -> Result<OAuthResult> {
 
             tx.send(code.to_string())?;
             break;
-        } else if params.get("error").is_some() {
+        } else if params.contains_key("error") {
             let response = Response::from_string("❌ Authorization denied!");
             let _ = request.respond(response);
             anyhow::bail!("User denied authorization");
@@ -0,0 +1,57 @@
+use std::io::{BufRead, IsTerminal};
+
+use anyhow::{Context, Result};
+
+/// Read a secret value: prompt without echo on a terminal, otherwise take
+/// the first line of stdin so values can be piped in.
+pub fn read_secret(prompt: &str) -> Result<String> {
+    let value = if std::io::stdin().is_terminal() {
+        rpassword::prompt_password(prompt).context("Failed to read secret")?
+    } else {
+        let mut line = String::new();
+        std::io::stdin()
+            .lock()
+            .read_line(&mut line)
+            .context("Failed to read secret from stdin")?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    };
+
+    if value.is_empty() {
+        anyhow::bail!("Empty value");
+    }
+    Ok(value)
+}
+
+/// Read an optional non-secret value (echoed): an empty line or the end of
+/// stdin gives `default`.
+pub fn read_line_or(prompt: &str, default: &str) -> Result<String> {
+    if std::io::stdin().is_terminal() {
+        eprint!("{}", prompt);
+    }
+    let mut line = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .context("Failed to read from stdin")?;
+
+    let value = line.trim();
+    Ok(if value.is_empty() { default } else { value }.to_string())
+}
+
+/// Read a non-secret value (echoed), e.g. a file path.
+pub fn read_line(prompt: &str) -> Result<String> {
+    if std::io::stdin().is_terminal() {
+        eprint!("{}", prompt);
+    }
+    let mut line = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .context("Failed to read from stdin")?;
+
+    let value = line.trim().to_string();
+    if value.is_empty() {
+        anyhow::bail!("Empty value");
+    }
+    Ok(value)
+}
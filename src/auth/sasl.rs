@@ -0,0 +1,120 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Result;
+
+/// SASL mechanisms that log in to IMAP/SMTP with an OAuth access token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaslMechanism {
+    /// Google/Microsoft's pre-standard mechanism.
+    XOAuth2,
+    /// RFC 7628.
+    OAuthBearer,
+}
+
+impl SaslMechanism {
+    pub const NAMES: &'static [&'static str] = &["xoauth2", "oauthbearer"];
+
+    /// The client's initial response (unencoded; IMAP and SMTP send it
+    /// base64-encoded).
+    pub fn initial_response(&self, user: &str, access_token: &str) -> String {
+        match self {
+            SaslMechanism::XOAuth2 => {
+                format!("user={}\x01auth=Bearer {}\x01\x01", user, access_token)
+            }
+            SaslMechanism::OAuthBearer => {
+                format!(
+                    "n,a={},\x01auth=Bearer {}\x01\x01",
+                    gs2_escape(user),
+                    access_token
+                )
+            }
+        }
+    }
+}
+
+impl FromStr for SaslMechanism {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "xoauth2" => Ok(Self::XOAuth2),
+            "oauthbearer" => Ok(Self::OAuthBearer),
+            _ => anyhow::bail!("Unsupported SASL mechanism '{}' (xoauth2, oauthbearer)", s),
+        }
+    }
+}
+
+impl fmt::Display for SaslMechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SaslMechanism::XOAuth2 => "XOAUTH2",
+            SaslMechanism::OAuthBearer => "OAUTHBEARER",
+        })
+    }
+}
+
+/// GS2 header escaping for the authzid (RFC 5801 section 4).
+fn gs2_escape(s: &str) -> String {
+    s.replace('=', "=3D").replace(',', "=2C")
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+
+    use super::*;
+
+    #[test]
+    fn xoauth2_initial_response() {
+        let response = SaslMechanism::XOAuth2.initial_response(
+            "someuser@example.com",
+            "ya29.vF9dft4qmTc2Nvb3RlckBhdHRhdmlzdGEuY29tCg",
+        );
+        assert_eq!(
+            response.as_bytes(),
+            b"user=someuser@example.com\x01auth=Bearer ya29.vF9dft4qmTc2Nvb3RlckBhdHRhdmlzdGEuY29tCg\x01\x01"
+        );
+        // The encoded example from Google's XOAUTH2 documentation
+        assert_eq!(
+            STANDARD.encode(response),
+            "dXNlcj1zb21ldXNlckBleGFtcGxlLmNvbQFhdXRoPUJlYXJlciB5YTI5LnZGOWRmdDRxbVRjMk52YjNSbGNrQmhkSFJoZG1semRHRXVZMjl0Q2cBAQ=="
+        );
+    }
+
+    #[test]
+    fn oauthbearer_initial_response() {
+        let response =
+            SaslMechanism::OAuthBearer.initial_response("user@example.com", "vF9dft4qmTc2");
+        assert_eq!(
+            response.as_bytes(),
+            b"n,a=user@example.com,\x01auth=Bearer vF9dft4qmTc2\x01\x01"
+        );
+    }
+
+    #[test]
+    fn oauthbearer_escapes_the_authzid() {
+        let response = SaslMechanism::OAuthBearer.initial_response("a=b,c", "t");
+        assert_eq!(
+            response.as_bytes(),
+            b"n,a=a=3Db=2Cc,\x01auth=Bearer t\x01\x01"
+        );
+        // XOAUTH2 has no escaping
+        let response = SaslMechanism::XOAuth2.initial_response("a=b,c", "t");
+        assert_eq!(response.as_bytes(), b"user=a=b,c\x01auth=Bearer t\x01\x01");
+    }
+
+    #[test]
+    fn mechanism_names_round_trip() {
+        for name in SaslMechanism::NAMES {
+            let mechanism: SaslMechanism = name.parse().unwrap();
+            assert_eq!(mechanism.to_string().to_ascii_lowercase(), *name);
+        }
+        assert_eq!(
+            "XOAUTH2".parse::<SaslMechanism>().unwrap(),
+            SaslMechanism::XOAuth2
+        );
+        assert!("plain".parse::<SaslMechanism>().is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::signature::{SignatureEncoding, Signer};
+use rsa::RsaPrivateKey;
+use serde::Deserialize;
+use sha2::Sha256;
+
+use super::ReauthRequired;
+
+const JWT_BEARER_GRANT: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+pub const DEFAULT_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const ASSERTION_LIFETIME_SECS: u64 = 3600;
+
+/// The fields we use from a GCP service-account JSON key file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServiceAccountKey {
+    #[serde(rename = "type")]
+    pub key_type: String,
+    pub project_id: Option<String>,
+    pub private_key_id: Option<String>,
+    pub private_key: String,
+    pub client_email: String,
+    pub token_uri: Option<String>,
+}
+
+impl ServiceAccountKey {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let key: Self = serde_json::from_str(&contents)
+            .with_context(|| format!("{} is not a service-account key file", path.display()))?;
+        if key.key_type != "service_account" {
+            anyhow::bail!(
+                "{} has type '{}', expected 'service_account'",
+                path.display(),
+                key.key_type
+            );
+        }
+        // Fail at import time rather than on first refresh
+        RsaPrivateKey::from_pkcs8_pem(&key.private_key)
+            .context("Service-account private key is not a valid PKCS#8 RSA key")?;
+        Ok(key)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: Option<String>,
+    expires_in: Option<u64>,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
+/// Build and sign the RS256 JWT assertion for the token request.
+/// `subject` impersonates a Workspace user (domain-wide delegation).
+pub fn signed_assertion(
+    client_email: &str,
+    private_key_pem: &str,
+    private_key_id: Option<&str>,
+    scopes: &[String],
+    audience: &str,
+    subject: Option<&str>,
+) -> Result<String> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+
+    let mut header = serde_json::json!({ "alg": "RS256", "typ": "JWT" });
+    if let Some(kid) = private_key_id {
+        header["kid"] = kid.into();
+    }
+
+    let mut claims = serde_json::json!({
+        "iss": client_email,
+        "scope": scopes.join(" "),
+        "aud": audience,
+        "iat": now,
+        "exp": now + ASSERTION_LIFETIME_SECS,
+    });
+    if let Some(sub) = subject {
+        claims["sub"] = sub.into();
+    }
+
+    let signing_input = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?),
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims)?)
+    );
+
+    let key = RsaPrivateKey::from_pkcs8_pem(private_key_pem)
+        .context("Service-account private key is not a valid PKCS#8 RSA key")?;
+    let signature = SigningKey::<Sha256>::new(key).sign(signing_input.as_bytes());
+
+    Ok(format!(
+        "{}.{}",
+        signing_input,
+        URL_SAFE_NO_PAD.encode(signature.to_bytes())
+    ))
+}
+
+/// Exchange a signed assertion for an access token. Returns the token and
+/// its absolute expiry.
+pub fn fetch_token(token_url: &str, assertion: &str) -> Result<(String, u64)> {
+    let client = reqwest::blocking::Client::new();
+
+    let mut params = HashMap::new();
+    params.insert("grant_type", JWT_BEARER_GRANT);
+    params.insert("assertion", assertion);
+
+    let response: TokenResponse = client
+        .post(token_url)
+        .form(&params)
+        .send()
+        .with_context(|| format!("Failed to reach token endpoint {}", token_url))?
+        .json()?;
+
+    if let Some(error) = response.error {
+        let desc = response.error_description.unwrap_or_default();
+        if error == "invalid_grant" {
+            // Key deleted/disabled or delegation withdrawn
+            return Err(ReauthRequired(format!(
+                "Service-account key was rejected: {} - {}",
+                error, desc
+            ))
+            .into());
+        }
+        anyhow::bail!("Service-account token request failed: {} - {}", error, desc);
+    }
+
+    let access_token = response
+        .access_token
+        .context("Token response has no access_token")?;
+    let expires_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs()
+        + response.expires_in.unwrap_or(ASSERTION_LIFETIME_SECS);
+
+    Ok((access_token, expires_at))
+}
@@ -0,0 +1,349 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use url::Url;
+
+/// HMAC algorithm used to derive the one-time code.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum TotpAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl FromStr for TotpAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_uppercase().as_str() {
+            "SHA1" => Ok(Self::Sha1),
+            "SHA256" => Ok(Self::Sha256),
+            "SHA512" => Ok(Self::Sha512),
+            _ => anyhow::bail!("Unsupported TOTP algorithm '{}' (SHA1, SHA256, SHA512)", s),
+        }
+    }
+}
+
+impl fmt::Display for TotpAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sha1 => "SHA1",
+            Self::Sha256 => "SHA256",
+            Self::Sha512 => "SHA512",
+        })
+    }
+}
+
+/// RFC 6238 parameters. Defaults match Google Authenticator (SHA1, 6 digits, 30s).
+#[derive(Debug, Clone)]
+pub struct TotpParams {
+    pub digits: u32,
+    pub period: u64,
+    pub algorithm: TotpAlgorithm,
+}
+
+impl TotpParams {
+    /// Reject parameters no authenticator uses, and a zero period, which
+    /// would make every code computation divide by zero.
+    pub fn check(&self) -> Result<()> {
+        if !(6..=10).contains(&self.digits) || self.period == 0 {
+            anyhow::bail!(
+                "Unsupported TOTP parameters: {} digits, period {}s (digits 6-10, period > 0)",
+                self.digits,
+                self.period
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Default for TotpParams {
+    fn default() -> Self {
+        Self {
+            digits: 6,
+            period: 30,
+            algorithm: TotpAlgorithm::Sha1,
+        }
+    }
+}
+
+/// A decoded seed ready to generate codes.
+#[derive(Debug, Clone)]
+pub struct TotpSeed {
+    pub secret: Vec<u8>,
+    pub params: TotpParams,
+}
+
+impl TotpSeed {
+    /// Accepts either an `otpauth://totp/...` URI or a bare base32 seed
+    /// (which gets the default parameters).
+    pub fn parse(input: &str) -> Result<Self> {
+        let input = input.trim();
+        if input.starts_with("otpauth://") {
+            let uri = OtpAuthUri::parse(input)?;
+            Ok(Self {
+                secret: decode_base32(&uri.secret)?,
+                params: uri.params,
+            })
+        } else {
+            Ok(Self {
+                secret: decode_base32(input)?,
+                params: TotpParams::default(),
+            })
+        }
+    }
+
+    pub fn current_code(&self) -> Result<(String, u64)> {
+        current_code(&self.secret, &self.params)
+    }
+}
+
+/// Fields of an `otpauth://totp/Issuer:account?secret=...` provisioning URI.
+#[derive(Debug, Clone)]
+pub struct OtpAuthUri {
+    /// Base32 seed, normalised to upper case without padding.
+    pub secret: String,
+    pub issuer: Option<String>,
+    pub account: Option<String>,
+    pub params: TotpParams,
+}
+
+impl OtpAuthUri {
+    pub fn parse(uri: &str) -> Result<Self> {
+        let url = Url::parse(uri.trim()).context("Invalid otpauth:// URI")?;
+        if url.scheme() != "otpauth" {
+            anyhow::bail!("Expected an otpauth:// URI");
+        }
+        if url.host_str() != Some("totp") {
+            anyhow::bail!("Only otpauth://totp/ URIs are supported (HOTP is not)");
+        }
+
+        let label = urlencoding::decode(url.path().trim_start_matches('/'))?.into_owned();
+        let (label_issuer, account) = match label.split_once(':') {
+            Some((i, a)) => (Some(i.trim().to_string()), Some(a.trim().to_string())),
+            None if label.is_empty() => (None, None),
+            None => (None, Some(label)),
+        };
+
+        let mut secret = None;
+        let mut issuer = label_issuer;
+        let mut params = TotpParams::default();
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "secret" => secret = Some(value.to_string()),
+                "issuer" => issuer = Some(value.to_string()),
+                "digits" => params.digits = value.parse().context("Invalid 'digits'")?,
+                "period" => params.period = value.parse().context("Invalid 'period'")?,
+                "algorithm" => params.algorithm = value.parse()?,
+                _ => {}
+            }
+        }
+
+        let secret = secret.context("otpauth:// URI has no 'secret' parameter")?;
+        // Validate up front so a typo is caught at import time
+        decode_base32(&secret)?;
+        params.check()?;
+
+        Ok(Self {
+            secret: secret
+                .to_ascii_uppercase()
+                .trim_end_matches('=')
+                .to_string(),
+            issuer,
+            account,
+            params,
+        })
+    }
+}
+
+/// Decode the first QR code found in a PNG/JPEG image and return its text.
+pub fn read_qr_image(path: &Path) -> Result<String> {
+    let img = image::open(path)
+        .with_context(|| format!("Failed to open image {}", path.display()))?
+        .to_luma8();
+
+    let mut prepared = rqrr::PreparedImage::prepare(img);
+    let grid = prepared
+        .detect_grids()
+        .into_iter()
+        .next()
+        .context("No QR code found in image")?;
+    let (_, content) = grid.decode().context("Failed to decode QR code")?;
+    Ok(content)
+}
+
+/// Decode an RFC 4648 base32 seed. Case, spaces and padding are ignored.
+pub fn decode_base32(seed: &str) -> Result<Vec<u8>> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+    let mut out = Vec::new();
+    let mut buffer = 0u64;
+    let mut bits = 0u32;
+
+    for c in seed
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '=' && *c != '-')
+    {
+        let upper = c.to_ascii_uppercase() as u8;
+        let value = ALPHABET
+            .iter()
+            .position(|&a| a == upper)
+            .with_context(|| format!("Invalid base32 character '{}' in TOTP seed", c))?;
+        buffer = (buffer << 5) | value as u64;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    if out.is_empty() {
+        anyhow::bail!("TOTP seed is empty");
+    }
+    Ok(out)
+}
+
+/// Compute the code for a given unix timestamp.
+pub fn code_at(secret: &[u8], params: &TotpParams, timestamp: u64) -> Result<String> {
+    let counter = (timestamp / params.period).to_be_bytes();
+
+    let digest = match params.algorithm {
+        TotpAlgorithm::Sha1 => hmac_digest::<Hmac<Sha1>>(secret, &counter)?,
+        TotpAlgorithm::Sha256 => hmac_digest::<Hmac<Sha256>>(secret, &counter)?,
+        TotpAlgorithm::Sha512 => hmac_digest::<Hmac<Sha512>>(secret, &counter)?,
+    };
+
+    // Dynamic truncation (RFC 4226 section 5.3)
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    let code = binary as u64 % 10u64.pow(params.digits);
+    Ok(format!("{:0width$}", code, width = params.digits as usize))
+}
+
+/// Current code plus the number of seconds it stays valid.
+pub fn current_code(secret: &[u8], params: &TotpParams) -> Result<(String, u64)> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let code = code_at(secret, params, now)?;
+    let remaining = params.period - (now % params.period);
+    Ok((code, remaining))
+}
+
+fn hmac_digest<M: Mac + hmac::digest::KeyInit>(key: &[u8], msg: &[u8]) -> Result<Vec<u8>> {
+    let mut mac = <M as hmac::digest::KeyInit>::new_from_slice(key)
+        .map_err(|e| anyhow::anyhow!("Invalid TOTP key: {}", e))?;
+    mac.update(msg);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(algorithm: TotpAlgorithm, digits: u32) -> TotpParams {
+        TotpParams {
+            digits,
+            period: 30,
+            algorithm,
+        }
+    }
+
+    /// RFC 6238 Appendix B: the ASCII seed "1234567890" repeated to the
+    /// algorithm's key length, 8 digits, 30s steps.
+    #[test]
+    fn rfc6238_test_vectors() {
+        let vectors: &[(u64, &str, &str, &str)] = &[
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+        let seed = |len: usize| b"1234567890".repeat(7)[..len].to_vec();
+        for &(time, sha1, sha256, sha512) in vectors {
+            for (algorithm, key_len, expected) in [
+                (TotpAlgorithm::Sha1, 20, sha1),
+                (TotpAlgorithm::Sha256, 32, sha256),
+                (TotpAlgorithm::Sha512, 64, sha512),
+            ] {
+                let code = code_at(&seed(key_len), &params(algorithm, 8), time).unwrap();
+                assert_eq!(code, expected, "{} at {}", algorithm, time);
+            }
+        }
+        // Fewer digits keep the low-order ones, zero-padded
+        let code = code_at(&seed(20), &params(TotpAlgorithm::Sha1, 6), 1111111109);
+        assert_eq!(code.unwrap(), "081804");
+    }
+
+    #[test]
+    fn decodes_base32_leniently() {
+        assert_eq!(decode_base32("MZXW6YTBOI======").unwrap(), b"foobar");
+        assert_eq!(decode_base32("MZXW6YTBOI").unwrap(), b"foobar");
+        assert_eq!(decode_base32("mzxw 6ytb oi").unwrap(), b"foobar");
+        assert_eq!(decode_base32("MZXW-6YTB-OI").unwrap(), b"foobar");
+        assert!(decode_base32("MZXW1YTB").is_err());
+        assert!(decode_base32("MZXW6YTB!").is_err());
+        assert!(decode_base32("").is_err());
+        assert!(decode_base32("  ==").is_err());
+    }
+
+    #[test]
+    fn parses_otpauth_uris() {
+        let uri = OtpAuthUri::parse(
+            "otpauth://totp/ACME%20Co:alice@example.com?secret=jbswy3dpehpk3pxp==&digits=8&period=60&algorithm=sha256",
+        )
+        .unwrap();
+        assert_eq!(uri.secret, "JBSWY3DPEHPK3PXP");
+        assert_eq!(uri.issuer.as_deref(), Some("ACME Co"));
+        assert_eq!(uri.account.as_deref(), Some("alice@example.com"));
+        assert_eq!(uri.params.digits, 8);
+        assert_eq!(uri.params.period, 60);
+        assert_eq!(uri.params.algorithm, TotpAlgorithm::Sha256);
+
+        // The issuer parameter wins over the label's; defaults apply
+        let uri =
+            OtpAuthUri::parse("otpauth://totp/Old:bob?secret=JBSWY3DPEHPK3PXP&issuer=New").unwrap();
+        assert_eq!(uri.issuer.as_deref(), Some("New"));
+        assert_eq!(uri.account.as_deref(), Some("bob"));
+        assert_eq!((uri.params.digits, uri.params.period), (6, 30));
+
+        let uri = OtpAuthUri::parse("otpauth://totp/carol?secret=JBSWY3DPEHPK3PXP").unwrap();
+        assert_eq!(uri.issuer, None);
+        assert_eq!(uri.account.as_deref(), Some("carol"));
+    }
+
+    #[test]
+    fn rejects_bad_otpauth_uris() {
+        for uri in [
+            "otpauth://hotp/x?secret=JBSWY3DPEHPK3PXP&counter=1",
+            "https://totp/x?secret=JBSWY3DPEHPK3PXP",
+            "otpauth://totp/x",
+            "otpauth://totp/x?secret=not-base32!",
+            "otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&digits=5",
+            "otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&digits=11",
+            "otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&digits=six",
+            "otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&period=0",
+            "otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&period=-30",
+            "otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&algorithm=MD5",
+        ] {
+            assert!(OtpAuthUri::parse(uri).is_err(), "{} should not parse", uri);
+        }
+    }
+}
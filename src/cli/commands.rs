@@ -1,20 +1,185 @@
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
-
-use crate::provider;
-use crate::storage::{Account, CredentialStore};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rand::Rng;
+
+use crate::auth::adc::{self, AuthorizedUser};
+use crate::auth::{google_oauth, id_token, prompt, ReauthRequired};
+use crate::cli::credential_helper::{self, DockerCredentials, DOCKER_NOT_FOUND};
+use crate::cli::env::{self, Shell};
+use crate::cli::output::{
+    self, AccountReport, AccountsReport, DaemonReport, HealthReport, OutputFormat, RefreshOutcome,
+    RefreshReport, RefreshResult,
+};
+use crate::cli::request::{Reply, Request};
+use crate::cli::template::Template;
+use crate::provider::{self, google, static_key, Provider};
+use crate::proxy::{http, mail};
+use crate::storage::{Account, AuthResult, CredentialStore, HealthState, StoredCredential};
+use crate::uri::SecretRef;
 
 const PLIST_LABEL: &str = "dev.tokey.refresh";
 const PLIST_FILENAME: &str = "dev.tokey.refresh.plist";
 
-pub fn cmd_list(provider_filter: Option<&str>) -> Result<()> {
+/// Exit status for errors caused by an account that needs `tokey reauth`,
+/// so scripts can tell "sign in again" apart from transient failures.
+pub const EXIT_NEEDS_REAUTH: i32 = 3;
+
+/// Refresh and save one account, recording the outcome in its health state.
+fn refresh_account(
+    store: &CredentialStore,
+    prov: &dyn Provider,
+    provider_name: &str,
+    label: &str,
+) -> Result<()> {
+    match prov.refresh(store, label) {
+        Ok(new_cred) => {
+            store.update_credential(provider_name, label, new_cred)?;
+            credential_changed(store, provider_name, label);
+            store.clear_health(provider_name, label)
+        }
+        Err(e) => {
+            let needs_reauth = ReauthRequired::is_cause_of(&e);
+            store.record_refresh_failure(
+                provider_name,
+                label,
+                needs_reauth,
+                &format!("{:#}", e),
+            )?;
+            if needs_reauth {
+                return Err(e.context(reauth_hint(provider_name, label)));
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Bring files derived from an account's credentials (kept-updated ADC
+/// exports) in line after the credentials were replaced. Failures only warn:
+/// the credential itself is already saved.
+fn credential_changed(store: &CredentialStore, provider_name: &str, label: &str) {
+    if provider_name == "google" {
+        if let Err(e) = google::sync_adc_exports(store, label) {
+            eprintln!(
+                "Warning: failed to update ADC exports for google/{}: {:#}",
+                label, e
+            );
+        }
+    }
+}
+
+fn reauth_hint(provider_name: &str, label: &str) -> String {
+    format!(
+        "{}/{} needs re-authentication; run `tokey reauth {} {}`",
+        provider_name, label, provider_name, label
+    )
+}
+
+/// Persisted failure state if any, otherwise `expiring` when the provider
+/// has a status warning.
+fn account_health(
+    store: &CredentialStore,
+    prov: &dyn Provider,
+    label: &str,
+    acct: &Account,
+) -> HealthState {
+    match &acct.health {
+        Some(h) if h.state != HealthState::Ok => h.state,
+        _ if prov.status_warning(store, label).is_some() => HealthState::Expiring,
+        _ => HealthState::Ok,
+    }
+}
+
+/// Everything `list` and `status` report about one account.
+fn account_report(
+    store: &CredentialStore,
+    prov: Option<&dyn Provider>,
+    provider_name: &str,
+    label: &str,
+    acct: &Account,
+    default: bool,
+) -> AccountReport {
+    let cred = store.get_credential(provider_name, label).ok();
+    let created_at = cred.as_ref().map(|c| c.created_at);
+    let age_seconds = created_at.map(|ts| CredentialStore::now().saturating_sub(ts));
+    let max_age_days = prov.map(|p| p.max_credential_age_days());
+    let state = match prov {
+        Some(p) => account_health(store, p, label, acct),
+        None => acct.health.as_ref().map(|h| h.state).unwrap_or_default(),
+    };
+    let health = acct.health.clone().unwrap_or_default();
+
+    AccountReport {
+        provider: provider_name.to_string(),
+        provider_display_name: prov.map(|p| p.display_name().to_string()),
+        label: label.to_string(),
+        display_name: acct.display_name.clone(),
+        default,
+        has_credentials: cred.is_some(),
+        created_at,
+        age_seconds,
+        max_age_days,
+        past_max_age: match (age_seconds, max_age_days) {
+            (Some(age), Some(max)) => age / 86400 > max,
+            _ => false,
+        },
+        expires_at: credential_expiry(store, provider_name, label, cred.as_ref()),
+        last_validated: cred.as_ref().and_then(|c| c.last_validated),
+        rotated_at: acct.rotated_at,
+        health: HealthReport {
+            state,
+            failures: health.failures,
+            last_error: health.last_error,
+            failing_since: health.failing_since,
+        },
+        warning: prov.and_then(|p| p.status_warning(store, label)),
+    }
+}
+
+/// When the current token (`expires_at` field) or static key (configured
+/// expiry date) stops working, if known.
+fn credential_expiry(
+    store: &CredentialStore,
+    provider_name: &str,
+    label: &str,
+    cred: Option<&StoredCredential>,
+) -> Option<u64> {
+    if let Some(ts) = cred.and_then(|c| c.fields.get("expires_at")) {
+        return ts.parse().ok();
+    }
+    if provider_name == "static" {
+        let expires = store.key_config(provider_name, label).ok()?.expires?;
+        return static_key::parse_expiry_date(&expires).ok();
+    }
+    None
+}
+
+fn validated_ago(last_validated: Option<u64>) -> String {
+    match last_validated {
+        Some(ts) => {
+            let hours = CredentialStore::now().saturating_sub(ts) / 3600;
+            if hours < 1 {
+                "<1h ago".to_string()
+            } else {
+                format!("{}h ago", hours)
+            }
+        }
+        None => "never".to_string(),
+    }
+}
+
+pub fn cmd_list(provider_filter: Option<&str>, output: OutputFormat) -> Result<()> {
     let store = CredentialStore::new()?;
     let config = store.load_config()?;
 
-    if config.providers.is_empty() {
+    if config.providers.is_empty() && output == OutputFormat::Plain {
         eprintln!("No accounts configured. Run `tokey add <provider>` to get started.");
         return Ok(());
     }
@@ -23,7 +188,9 @@ pub fn cmd_list(provider_filter: Option<&str>) -> Result<()> {
         Some(name) => {
             if !config.providers.contains_key(name) {
                 eprintln!("No accounts for provider '{}'.", name);
-                return Ok(());
+                if output == OutputFormat::Plain {
+                    return Ok(());
+                }
             }
             vec![name]
         }
@@ -34,16 +201,57 @@ pub fn cmd_list(provider_filter: Option<&str>) -> Result<()> {
         }
     };
 
+    let mut accounts = Vec::new();
     for prov_name in providers {
         if let Some(prov_config) = config.providers.get(prov_name) {
-            println!("{}:", prov_name);
+            let prov = provider::get_provider(prov_name).ok();
             let default = prov_config.default_account.as_deref().unwrap_or("");
             let mut labels: Vec<&String> = prov_config.accounts.keys().collect();
             labels.sort();
             for label in labels {
                 let acct = &prov_config.accounts[label];
-                let marker = if label.as_str() == default { " *" } else { "" };
-                println!("  {}{} ({})", label, marker, acct.display_name);
+                accounts.push(account_report(
+                    &store,
+                    prov.as_deref(),
+                    prov_name,
+                    label,
+                    acct,
+                    label.as_str() == default,
+                ));
+            }
+        }
+    }
+
+    match output {
+        OutputFormat::Json => output::print_json("list", &AccountsReport { accounts })?,
+        OutputFormat::Table => output::print_table(
+            &["PROVIDER", "ACCOUNT", "DEFAULT", "NAME", "HEALTH"],
+            &accounts
+                .iter()
+                .map(|a| {
+                    vec![
+                        a.provider.clone(),
+                        a.label.clone(),
+                        if a.default { "*" } else { "" }.to_string(),
+                        a.display_name.clone(),
+                        a.health.state.to_string(),
+                    ]
+                })
+                .collect::<Vec<_>>(),
+        ),
+        OutputFormat::Plain => {
+            let mut current = None;
+            for a in &accounts {
+                if current != Some(&a.provider) {
+                    println!("{}:", a.provider);
+                    current = Some(&a.provider);
+                }
+                let marker = if a.default { " *" } else { "" };
+                let health = match a.health.state {
+                    HealthState::Ok => String::new(),
+                    state => format!(" [{}]", state),
+                };
+                println!("  {}{} ({}){}", a.label, marker, a.display_name, health);
             }
         }
     }
@@ -51,58 +259,107 @@ pub fn cmd_list(provider_filter: Option<&str>) -> Result<()> {
     Ok(())
 }
 
-pub fn cmd_get(provider_name: &str, account: Option<&str>, field: Option<&str>) -> Result<()> {
+pub fn cmd_get(
+    provider_name: &str,
+    account: Option<&str>,
+    field: Option<&str>,
+    scopes: &[String],
+    sasl: Option<&str>,
+    base64: bool,
+) -> Result<()> {
+    // A SASL string is just another derived field
+    let field = sasl.or(field);
+    if base64 && field.is_none() {
+        anyhow::bail!("--base64 needs a single value: pass -f <field> or --sasl <mechanism>");
+    }
+
     let store = CredentialStore::new()?;
     let prov = provider::get_provider(provider_name)?;
     let label = store.resolve_account(provider_name, account)?;
 
+    if !scopes.is_empty() {
+        if provider_name != "google" {
+            anyhow::bail!("--scope is only supported for google");
+        }
+        check_reauth(&store, provider_name, &label)?;
+        let cred = google::scoped_credential(&store, &label, scopes)?;
+        return print_credential(prov.as_ref(), &cred, field, base64);
+    }
+
+    let cred = current_credential(&store, prov.as_ref(), provider_name, &label)?;
+    print_credential(prov.as_ref(), &cred, field, base64)
+}
+
+/// Fail with ReauthRequired if the account is known to need reauth, rather
+/// than handing out credentials already known to be dead.
+fn check_reauth(store: &CredentialStore, provider_name: &str, label: &str) -> Result<()> {
+    if let Some(health) = store.get_account(provider_name, label)?.health {
+        if health.state == HealthState::NeedsReauth {
+            let error = health.last_error.unwrap_or_default();
+            return Err(anyhow::Error::new(ReauthRequired(error))
+                .context(reauth_hint(provider_name, label)));
+        }
+    }
+    Ok(())
+}
+
+/// An account's credentials, refreshed first if stale. A transient refresh
+/// failure falls back to the existing credentials with a warning.
+pub fn current_credential(
+    store: &CredentialStore,
+    prov: &dyn Provider,
+    provider_name: &str,
+    label: &str,
+) -> Result<StoredCredential> {
+    check_reauth(store, provider_name, label)?;
+
     // Check if credentials need refresh
-    let needs_refresh = if provider_name == "google" {
-        // For Google, check if access token is expired based on expires_at
-        store
-            .get_credential(provider_name, &label)
-            .map(|cred| {
-                crate::provider::google::needs_refresh(&cred)
-            })
-            .unwrap_or(false)
-    } else {
-        // For other providers, check credential age
-        store
-            .is_expired(provider_name, &label, prov.max_credential_age_days())
-            .unwrap_or(false)
-    };
+    let needs_refresh = store
+        .get_credential(provider_name, label)
+        .map(|cred| prov.needs_refresh(&cred))
+        .unwrap_or(false);
 
     if needs_refresh {
-        if provider_name == "google" {
-            eprintln!("Access token for {}/{} expired -- refreshing...", provider_name, label);
-        } else {
-            eprintln!(
-                "Credentials for {}/{} are older than {} days -- refreshing...",
-                provider_name,
-                label,
-                prov.max_credential_age_days()
-            );
-        }
-        match prov.refresh(&store, &label) {
-            Ok(new_cred) => {
-                store.update_credential(provider_name, &label, new_cred)?;
-                eprintln!("Credentials refreshed.");
-            }
+        eprintln!(
+            "Credentials for {}/{} are stale -- refreshing...",
+            provider_name, label
+        );
+        match refresh_account(store, prov, provider_name, label) {
+            Ok(()) => eprintln!("Credentials refreshed."),
+            Err(e) if ReauthRequired::is_cause_of(&e) => return Err(e),
             Err(e) => {
                 eprintln!("Refresh failed ({}), using existing credentials.", e);
             }
         }
     }
 
-    let cred = store.get_credential(provider_name, &label)?;
+    store.get_credential(provider_name, label)
+}
+
+/// A stored field, or failing that a derived one.
+fn field_value(prov: &dyn Provider, cred: &StoredCredential, field: &str) -> Result<String> {
+    match cred.fields.get(field) {
+        Some(v) => Ok(v.clone()),
+        None => provider::derived_field(prov, cred, field)?
+            .ok_or_else(|| anyhow::anyhow!("Field '{}' not found", field)),
+    }
+}
 
+/// Print one field (stored or derived) or all stored fields as JSON.
+fn print_credential(
+    prov: &dyn provider::Provider,
+    cred: &StoredCredential,
+    field: Option<&str>,
+    base64: bool,
+) -> Result<()> {
     match field {
         Some(f) => {
-            let val = cred
-                .fields
-                .get(f)
-                .ok_or_else(|| anyhow::anyhow!("Field '{}' not found", f))?;
-            println!("{}", val);
+            let val = field_value(prov, cred, f)?;
+            if base64 {
+                println!("{}", STANDARD.encode(val));
+            } else {
+                println!("{}", val);
+            }
         }
         None => {
             let json = serde_json::to_string_pretty(&cred.fields)?;
@@ -113,11 +370,17 @@ pub fn cmd_get(provider_name: &str, account: Option<&str>, field: Option<&str>)
     Ok(())
 }
 
-pub fn cmd_add(provider_name: &str, label: Option<&str>) -> Result<()> {
+pub fn cmd_add(
+    provider_name: &str,
+    label: Option<&str>,
+    scopes: &[String],
+    expires: Option<&str>,
+) -> Result<()> {
     let store = CredentialStore::new()?;
     let prov = provider::get_provider(provider_name)?;
 
     let effective_label = label.unwrap_or("default");
+    check_expires(provider_name, expires)?;
 
     eprintln!(
         "Adding {} account '{}'...",
@@ -125,16 +388,44 @@ pub fn cmd_add(provider_name: &str, label: Option<&str>) -> Result<()> {
         effective_label
     );
 
-    let result = prov.authenticate(&store, effective_label)?;
-
-    let account = Account {
+    let result = if scopes.is_empty() {
+        prov.authenticate(&store, effective_label)?
+    } else {
+        if provider_name != "google" {
+            anyhow::bail!("--scopes is only supported for google");
+        }
+        google::GoogleProvider.authenticate_with_scopes(&store, effective_label, scopes)?
+    };
+    warn_duplicate(
+        &store,
+        prov.as_ref(),
+        provider_name,
+        effective_label,
+        &result,
+    )?;
+
+    // Re-adding an existing label keeps its per-account settings
+    let mut account = Account {
         display_name: result.display_name.clone(),
         provider_id: result.provider_id.clone(),
         user_id: result.user_id.clone(),
+        claims: result.claims.clone(),
         created_at: CredentialStore::now(),
+        // The new credential starts out healthy
+        health: None,
+        ..store
+            .get_account(provider_name, effective_label)
+            .unwrap_or_default()
     };
+    if !scopes.is_empty() {
+        account.scopes = scopes.to_vec();
+    }
+    if let Some(date) = expires {
+        account.key.get_or_insert_with(Default::default).expires = Some(date.to_string());
+    }
 
     store.save_account(provider_name, effective_label, account, result.credential)?;
+    credential_changed(&store, provider_name, effective_label);
 
     eprintln!(
         "Account saved: {}/{} ({})",
@@ -144,36 +435,239 @@ pub fn cmd_add(provider_name: &str, label: Option<&str>) -> Result<()> {
     Ok(())
 }
 
-pub fn cmd_refresh(provider_name: &str, account: Option<&str>) -> Result<()> {
+/// Point out when a new sign-in is an account already stored under another
+/// label. It is still saved; two labels for one user can be deliberate.
+fn warn_duplicate(
+    store: &CredentialStore,
+    prov: &dyn Provider,
+    provider_name: &str,
+    label: &str,
+    result: &AuthResult,
+) -> Result<()> {
+    let config = store.load_config()?;
+    let Some(provider_config) = config.providers.get(provider_name) else {
+        return Ok(());
+    };
+    for (other, account) in &provider_config.accounts {
+        if other != label && prov.same_identity(account, result) == Some(true) {
+            eprintln!(
+                "Note: {} is already stored as {}/{}.",
+                result.display_name, provider_name, other
+            );
+        }
+    }
+    Ok(())
+}
+
+pub fn cmd_refresh(provider_name: &str, account: Option<&str>, output: OutputFormat) -> Result<()> {
     let store = CredentialStore::new()?;
     let prov = provider::get_provider(provider_name)?;
     let label = store.resolve_account(provider_name, account)?;
 
     eprintln!("Refreshing {}/{}...", provider_name, label);
 
-    let new_cred = prov.refresh(&store, &label)?;
-    store.update_credential(provider_name, &label, new_cred)?;
+    let refreshed = refresh_account(&store, prov.as_ref(), provider_name, &label);
+    if output != OutputFormat::Plain {
+        let mut report = RefreshReport::default();
+        report.push(refresh_result(&store, provider_name, &label, &refreshed));
+        print_refresh_report(&report, output)?;
+    }
+    refreshed?;
 
     eprintln!("Credentials refreshed for {}/{}.", provider_name, label);
     Ok(())
 }
 
-pub fn cmd_remove(provider_name: &str, account: &str) -> Result<()> {
+fn refresh_result(
+    store: &CredentialStore,
+    provider_name: &str,
+    label: &str,
+    refreshed: &Result<()>,
+) -> RefreshResult {
+    let (outcome, error) = match refreshed {
+        Ok(()) => (RefreshOutcome::Refreshed, None),
+        Err(e) if ReauthRequired::is_cause_of(e) => {
+            (RefreshOutcome::NeedsReauth, Some(format!("{:#}", e)))
+        }
+        Err(e) => (RefreshOutcome::Failed, Some(format!("{:#}", e))),
+    };
+    let cred = store.get_credential(provider_name, label).ok();
+    RefreshResult {
+        provider: provider_name.to_string(),
+        label: label.to_string(),
+        outcome,
+        error,
+        expires_at: match outcome {
+            RefreshOutcome::Refreshed => {
+                credential_expiry(store, provider_name, label, cred.as_ref())
+            }
+            _ => None,
+        },
+    }
+}
+
+/// The `table` and `json` forms of a refresh report; `plain` is the
+/// progress already printed to stderr.
+fn print_refresh_report(report: &RefreshReport, output: OutputFormat) -> Result<()> {
+    match output {
+        OutputFormat::Json => output::print_json("refresh", report)?,
+        OutputFormat::Table => output::print_table(
+            &["PROVIDER", "ACCOUNT", "OUTCOME", "EXPIRES", "ERROR"],
+            &report
+                .results
+                .iter()
+                .map(|r| {
+                    vec![
+                        r.provider.clone(),
+                        r.label.clone(),
+                        r.outcome.to_string(),
+                        output::timestamp(r.expires_at),
+                        r.error.clone().unwrap_or_default(),
+                    ]
+                })
+                .collect::<Vec<_>>(),
+        ),
+        OutputFormat::Plain => {}
+    }
+    Ok(())
+}
+
+pub fn cmd_remove(provider_name: &str, account: &str, keep_remote: bool) -> Result<()> {
     let store = CredentialStore::new()?;
 
     // Verify it exists
     store.resolve_account(provider_name, Some(account))?;
 
+    let has_credential = store.get_credential(provider_name, account).is_ok();
+    if !keep_remote && has_credential {
+        let prov = provider::get_provider(provider_name)?;
+        match prov.revoke(&store, account) {
+            Ok(true) => eprintln!("Revoked {}/{} upstream.", provider_name, account),
+            Ok(false) => {}
+            Err(e) => {
+                // Keep the local copy: it's needed to retry the revocation
+                eprintln!(
+                    "Remote revocation failed for {}/{}: {:#}",
+                    provider_name, account, e
+                );
+                eprintln!("Nothing was removed. Retry, or pass --keep-remote to delete only the local copy.");
+                anyhow::bail!("remote revocation failed");
+            }
+        }
+    }
+
     store.remove_account(provider_name, account)?;
     eprintln!("Removed {}/{}.", provider_name, account);
     Ok(())
 }
 
-pub fn cmd_status(provider_filter: Option<&str>, account_filter: Option<&str>) -> Result<()> {
+pub fn cmd_rotate(provider_name: &str, account: &str, expires: Option<&str>) -> Result<()> {
+    let store = CredentialStore::new()?;
+    let prov = provider::get_provider(provider_name)?;
+    let label = store.resolve_account(provider_name, Some(account))?;
+    check_expires(provider_name, expires)?;
+
+    eprintln!("Rotating {}/{}...", provider_name, label);
+
+    let result = prov.authenticate(&store, &label)?;
+    store.update_credential(provider_name, &label, result.credential)?;
+    credential_changed(&store, provider_name, &label);
+
+    let now = CredentialStore::now();
+    store.update_account(provider_name, &label, |acct| {
+        acct.rotated_at = Some(now);
+        acct.health = None;
+        if let Some(date) = expires {
+            acct.key.get_or_insert_with(Default::default).expires = Some(date.to_string());
+        }
+    })?;
+
+    eprintln!("Credentials rotated for {}/{}.", provider_name, label);
+    if expires.is_none() {
+        if let Some(date) = store.key_config(provider_name, &label)?.expires {
+            eprintln!(
+                "Expiry date unchanged ({}); pass --expires to update it.",
+                date
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Reject an `--expires` date that is malformed or not for a static key.
+fn check_expires(provider_name: &str, expires: Option<&str>) -> Result<()> {
+    if let Some(date) = expires {
+        if provider_name != "static" {
+            anyhow::bail!("--expires only applies to static keys");
+        }
+        static_key::parse_expiry_date(date)?;
+    }
+    Ok(())
+}
+
+pub fn cmd_reauth(provider_name: &str, account: &str) -> Result<()> {
+    let store = CredentialStore::new()?;
+    let prov = provider::get_provider(provider_name)?;
+    let label = store.resolve_account(provider_name, Some(account))?;
+    let existing = store.get_account(provider_name, &label)?;
+
+    eprintln!("Re-authenticating {}/{}...", provider_name, label);
+
+    let result = match (provider_name, store.get_credential(provider_name, &label)) {
+        // Ask Google for the same scopes the account had before
+        ("google", Ok(cred)) if cred.fields.contains_key("scopes") => {
+            let scopes: Vec<String> = cred.fields["scopes"]
+                .split_whitespace()
+                .map(str::to_string)
+                .collect();
+            google::GoogleProvider.authenticate_with_scopes(&store, &label, &scopes)?
+        }
+        _ => prov.authenticate(&store, &label)?,
+    };
+
+    if prov.same_identity(&existing, &result) == Some(false) {
+        anyhow::bail!(
+            "Signed in as {} but {}/{} belongs to {}; nothing was changed. Use `tokey add` for a different account.",
+            result.display_name,
+            provider_name,
+            label,
+            existing.display_name
+        );
+    }
+
+    store.update_credential(provider_name, &label, result.credential)?;
+    credential_changed(&store, provider_name, &label);
+    store.update_account(provider_name, &label, |acct| {
+        acct.display_name = result.display_name.clone();
+        // Accounts added before ids or claims were recorded pick them up here
+        if !result.provider_id.is_empty() {
+            acct.provider_id = result.provider_id.clone();
+        }
+        if !result.user_id.is_empty() {
+            acct.user_id = result.user_id.clone();
+        }
+        if result.claims.is_some() {
+            acct.claims = result.claims.clone();
+        }
+        acct.health = None;
+    })?;
+
+    eprintln!(
+        "Re-authenticated {}/{} ({}).",
+        provider_name, label, result.display_name
+    );
+    Ok(())
+}
+
+pub fn cmd_status(
+    provider_filter: Option<&str>,
+    account_filter: Option<&str>,
+    output: OutputFormat,
+) -> Result<()> {
     let store = CredentialStore::new()?;
     let config = store.load_config()?;
 
-    if config.providers.is_empty() {
+    if config.providers.is_empty() && output == OutputFormat::Plain {
         eprintln!("No accounts configured.");
         return Ok(());
     }
@@ -187,6 +681,7 @@ pub fn cmd_status(provider_filter: Option<&str>, account_filter: Option<&str>) -
         }
     };
 
+    let mut accounts = Vec::new();
     for prov_name in providers {
         let prov_config = match config.providers.get(prov_name) {
             Some(c) => c,
@@ -216,58 +711,116 @@ pub fn cmd_status(provider_filter: Option<&str>, account_filter: Option<&str>) -
             }
         };
 
-        println!("{}:", prov_name);
         let default = prov_config.default_account.as_deref().unwrap_or("");
-
         for label in &labels {
             let acct = &prov_config.accounts[label.as_str()];
-            let marker = if label.as_str() == default {
-                " [default]"
-            } else {
-                ""
-            };
+            accounts.push(account_report(
+                &store,
+                Some(prov.as_ref()),
+                prov_name,
+                label,
+                acct,
+                label.as_str() == default,
+            ));
+        }
+    }
 
-            let cred = store.get_credential(prov_name, label);
-            let age_str = match &cred {
-                Ok(c) => {
-                    let age_secs = CredentialStore::now() - c.created_at;
-                    let days = age_secs / 86400;
-                    let max = prov.max_credential_age_days();
-                    if days > max {
-                        format!("{} days old (EXPIRED, max {})", days, max)
-                    } else {
-                        format!("{} days old (max {})", days, max)
-                    }
+    match output {
+        OutputFormat::Json => output::print_json("status", &AccountsReport { accounts })?,
+        OutputFormat::Table => output::print_table(
+            &[
+                "PROVIDER",
+                "ACCOUNT",
+                "DEFAULT",
+                "NAME",
+                "AGE",
+                "MAX",
+                "EXPIRES",
+                "VALIDATED",
+                "HEALTH",
+                "WARNING",
+            ],
+            &accounts
+                .iter()
+                .map(|a| {
+                    vec![
+                        a.provider.clone(),
+                        a.label.clone(),
+                        if a.default { "*" } else { "" }.to_string(),
+                        a.display_name.clone(),
+                        a.age_seconds
+                            .map_or("-".to_string(), |age| format!("{}d", age / 86400)),
+                        a.max_age_days
+                            .map_or("-".to_string(), |max| format!("{}d", max)),
+                        output::timestamp(a.expires_at),
+                        validated_ago(a.last_validated),
+                        a.health.state.to_string(),
+                        a.warning.clone().unwrap_or_default(),
+                    ]
+                })
+                .collect::<Vec<_>>(),
+        ),
+        OutputFormat::Plain => {
+            let mut current = None;
+            for a in &accounts {
+                if current != Some(&a.provider) {
+                    println!("{}:", a.provider);
+                    current = Some(&a.provider);
                 }
-                Err(_) => "no credentials".to_string(),
-            };
-
-            let validated_str = match &cred {
-                Ok(c) => match c.last_validated {
-                    Some(ts) => {
-                        let ago = CredentialStore::now() - ts;
-                        let hours = ago / 3600;
-                        if hours < 1 {
-                            "validated <1h ago".to_string()
-                        } else {
-                            format!("validated {}h ago", hours)
-                        }
-                    }
-                    None => "never validated".to_string(),
-                },
-                Err(_) => String::new(),
-            };
-
-            println!(
-                "  {}{} -- {} | {} | {}",
-                label, marker, acct.display_name, age_str, validated_str
-            );
+                println!("{}", status_line(a));
+            }
         }
     }
 
     Ok(())
 }
 
+/// One account's line in the plain `status` output.
+fn status_line(a: &AccountReport) -> String {
+    let marker = if a.default { " [default]" } else { "" };
+    let age_str = match (a.age_seconds, a.max_age_days) {
+        (Some(age), Some(max)) if a.past_max_age => {
+            format!("{} days old (EXPIRED, max {})", age / 86400, max)
+        }
+        (Some(age), Some(max)) => format!("{} days old (max {})", age / 86400, max),
+        _ => "no credentials".to_string(),
+    };
+    let validated_str = if a.has_credentials {
+        match a.last_validated {
+            Some(ts) => format!("validated {}", validated_ago(Some(ts))),
+            None => "never validated".to_string(),
+        }
+    } else {
+        String::new()
+    };
+
+    let mut line = format!(
+        "  {}{} -- {} | {} | {}",
+        a.label, marker, a.display_name, age_str, validated_str
+    );
+    if let Some(ts) = a.rotated_at {
+        let days = CredentialStore::now().saturating_sub(ts) / 86400;
+        line.push_str(&format!(" | rotated {}d ago", days));
+    }
+    line.push_str(&format!(" | {}", a.health.state));
+    if matches!(
+        a.health.state,
+        HealthState::RefreshFailing | HealthState::NeedsReauth
+    ) && a.health.failures > 0
+    {
+        line.push_str(&format!(
+            " ({} failure{}: {})",
+            a.health.failures,
+            if a.health.failures == 1 { "" } else { "s" },
+            a.health.last_error.as_deref().unwrap_or("unknown error")
+        ));
+    }
+    if let Some(warning) = &a.warning {
+        line.push_str(&format!(" | WARNING: {}", warning));
+    }
+    line
+}
+
 pub fn cmd_default(provider_name: &str, account: &str) -> Result<()> {
     let store = CredentialStore::new()?;
     store.set_default(provider_name, account)?;
@@ -278,118 +831,1162 @@ pub fn cmd_default(provider_name: &str, account: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn cmd_refresh_all() -> Result<()> {
+pub fn cmd_refresh_all(output: OutputFormat) -> Result<()> {
     let store = CredentialStore::new()?;
     let config = store.load_config()?;
 
-    if config.providers.is_empty() {
+    if config.providers.is_empty() && output == OutputFormat::Plain {
         eprintln!("No accounts configured.");
         return Ok(());
     }
 
-    let mut success = 0u32;
-    let mut failed = 0u32;
+    let mut report = RefreshReport::default();
+    let mut prov_names: Vec<&String> = config.providers.keys().collect();
+    prov_names.sort();
+
+    for prov_name in prov_names {
+        let prov_config = &config.providers[prov_name];
+        let mut labels: Vec<&String> = prov_config.accounts.keys().collect();
+        labels.sort();
 
-    for (prov_name, prov_config) in &config.providers {
         let prov = match provider::get_provider(prov_name) {
             Ok(p) => p,
             Err(e) => {
                 eprintln!("[{}] unknown provider: {}", prov_name, e);
-                failed += 1;
+                for label in labels {
+                    let failed = Err(anyhow::anyhow!("unknown provider: {}", e));
+                    report.push(refresh_result(&store, prov_name, label, &failed));
+                }
                 continue;
             }
         };
 
-        for label in prov_config.accounts.keys() {
+        for label in labels {
             eprintln!("[{}/{}] refreshing...", prov_name, label);
-            match prov.refresh(&store, label) {
-                Ok(new_cred) => {
-                    if let Err(e) = store.update_credential(prov_name, label, new_cred) {
-                        eprintln!("[{}/{}] save failed: {}", prov_name, label, e);
-                        failed += 1;
-                    } else {
-                        eprintln!("[{}/{}] ok", prov_name, label);
-                        success += 1;
-                    }
-                }
-                Err(e) => {
-                    eprintln!("[{}/{}] failed: {}", prov_name, label, e);
-                    failed += 1;
-                }
+            let refreshed = refresh_account(&store, prov.as_ref(), prov_name, label);
+            match &refreshed {
+                Ok(()) => eprintln!("[{}/{}] ok", prov_name, label),
+                Err(e) => eprintln!("[{}/{}] failed: {:#}", prov_name, label, e),
             }
+            report.push(refresh_result(&store, prov_name, label, &refreshed));
         }
     }
 
-    eprintln!("Done. {} refreshed, {} failed.", success, failed);
-
-    if failed > 0 {
-        std::process::exit(1);
+    eprintln!(
+        "Done. {} refreshed, {} failed.",
+        report.refreshed, report.failed
+    );
+    print_refresh_report(&report, output)?;
+
+    if report.failed > 0 {
+        anyhow::bail!(
+            "{} of {} accounts failed to refresh",
+            report.failed,
+            report.results.len()
+        );
     }
     Ok(())
 }
 
-// -- Daemon management --------------------------------------------------------
+// -- Field references ---------------------------------------------------------
 
-fn plist_path() -> Result<PathBuf> {
-    let home = dirs::home_dir().context("Could not determine home directory")?;
-    Ok(home.join("Library/LaunchAgents").join(PLIST_FILENAME))
+/// Looks up referenced credential fields, fetching (and if stale,
+/// refreshing) each account only once.
+struct FieldResolver<'a> {
+    store: &'a CredentialStore,
+    credentials: HashMap<(String, String), StoredCredential>,
 }
 
-fn tokey_binary_path() -> Result<PathBuf> {
-    std::env::current_exe().context("Could not determine tokey binary path")
+impl<'a> FieldResolver<'a> {
+    fn new(store: &'a CredentialStore) -> Self {
+        FieldResolver {
+            store,
+            credentials: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, reference: &SecretRef) -> Result<String> {
+        let provider_name = reference.provider.as_str();
+        let prov = provider::get_provider(provider_name)?;
+        let key = (provider_name.to_string(), reference.label(self.store)?);
+        let cred = match self.credentials.get(&key) {
+            Some(cred) => cred,
+            None => {
+                let cred = current_credential(self.store, prov.as_ref(), &key.0, &key.1)?;
+                self.credentials.entry(key.clone()).or_insert(cred)
+            }
+        };
+        let value = field_value(prov.as_ref(), cred, &reference.field)
+            .with_context(|| format!("{}/{}", key.0, key.1))?;
+        Ok(reference.transform(&value))
+    }
 }
 
-fn log_path() -> Result<PathBuf> {
-    let config_dir = dirs::config_dir()
-        .context("Could not determine config directory")?
-        .join("tokey");
-    fs::create_dir_all(&config_dir)?;
-    Ok(config_dir.join("daemon.log"))
+/// Print the value a `tokey://` reference points at.
+pub fn cmd_read(uri: &str) -> Result<()> {
+    let reference: SecretRef = uri.parse()?;
+    let store = CredentialStore::new()?;
+    println!("{}", FieldResolver::new(&store).get(&reference)?);
+    Ok(())
 }
 
-pub fn cmd_daemon_install(interval_hours: u64) -> Result<()> {
-    let plist = plist_path()?;
-    let binary = tokey_binary_path()?;
-    let log = log_path()?;
-    let interval_secs = interval_hours * 3600;
+/// Resolve a file (or stdin) of references to JSON. A JSON document has
+/// every `tokey://` string replaced in place; anything else is read as one
+/// reference per line and becomes an object keyed by reference.
+pub fn cmd_resolve(path: Option<&Path>) -> Result<()> {
+    let input = match path.filter(|p| p.as_os_str() != "-") {
+        Some(p) => {
+            fs::read_to_string(p).with_context(|| format!("Failed to read {}", p.display()))?
+        }
+        None => std::io::read_to_string(std::io::stdin()).context("Failed to read stdin")?,
+    };
+    let store = CredentialStore::new()?;
+    let mut fields = FieldResolver::new(&store);
 
-    let plist_content = format!(
-        r#"<?xml version="1.0" encoding="UTF-8"?>
-<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
-<plist version="1.0">
-<dict>
-    <key>Label</key>
-    <string>{label}</string>
-    <key>ProgramArguments</key>
-    <array>
-        <string>{binary}</string>
-        <string>refresh</string>
-        <string>--all</string>
-    </array>
-    <key>StartInterval</key>
-    <integer>{interval}</integer>
-    <key>RunAtLoad</key>
-    <true/>
-    <key>StandardOutPath</key>
-    <string>{log}</string>
-    <key>StandardErrorPath</key>
-    <string>{log}</string>
-    <key>ProcessType</key>
-    <string>Background</string>
-</dict>
-</plist>
-"#,
-        label = PLIST_LABEL,
-        binary = binary.display(),
-        interval = interval_secs,
-        log = log.display(),
-    );
+    let output = match serde_json::from_str::<serde_json::Value>(&input) {
+        Ok(mut doc) => {
+            resolve_json(&mut doc, &mut fields)?;
+            doc
+        }
+        Err(_) => {
+            let mut map = serde_json::Map::new();
+            for (n, line) in input.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let reference: SecretRef =
+                    line.parse().with_context(|| format!("line {}", n + 1))?;
+                let value = fields.get(&reference).with_context(|| line.to_string())?;
+                map.insert(line.to_string(), value.into());
+            }
+            map.into()
+        }
+    };
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
 
-    // Unload existing if present
-    if plist.exists() {
-        let _ = Command::new("launchctl")
-            .args(["unload", &plist.to_string_lossy()])
-            .output();
+fn resolve_json(value: &mut serde_json::Value, fields: &mut FieldResolver) -> Result<()> {
+    match value {
+        serde_json::Value::String(s) if SecretRef::is_reference(s) => {
+            let reference: SecretRef = s.parse()?;
+            *s = fields
+                .get(&reference)
+                .with_context(|| reference.to_string())?;
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                resolve_json(item, fields)?;
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for item in map.values_mut() {
+                resolve_json(item, fields)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// -- Exec ---------------------------------------------------------------------
+
+/// A `VAR=provider[/account]#field` or `VAR=tokey://...` mapping for `exec`.
+struct EnvRef {
+    var: String,
+    reference: SecretRef,
+}
+
+fn parse_env_ref(spec: &str) -> Result<EnvRef> {
+    let (var, reference) = spec
+        .split_once('=')
+        .with_context(|| format!("Expected VAR=provider[/account]#field, got '{}'", spec))?;
+    let valid_name = var
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && var.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_name {
+        anyhow::bail!("'{}' is not a valid environment variable name", var);
+    }
+    if SecretRef::is_reference(reference) {
+        return Ok(EnvRef {
+            var: var.to_string(),
+            reference: reference.parse()?,
+        });
+    }
+    let (account, field) = reference
+        .split_once('#')
+        .with_context(|| format!("'{}' has no #field (e.g. slack/work#token)", reference))?;
+    let (provider, account) = match account.split_once('/') {
+        Some((p, a)) => (p, Some(a)),
+        None => (account, None),
+    };
+    if provider.is_empty() || field.is_empty() {
+        anyhow::bail!("Expected VAR=provider[/account]#field, got '{}'", spec);
+    }
+    Ok(EnvRef {
+        var: var.to_string(),
+        reference: SecretRef::new(provider, account, field),
+    })
+}
+
+/// Mappings from an env-map file: one `VAR=provider[/account]#field` per
+/// line, blank lines and `#` comments ignored.
+fn read_env_file(path: &Path) -> Result<Vec<EnvRef>> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut refs = Vec::new();
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        refs.push(parse_env_ref(line).with_context(|| format!("{}:{}", path.display(), n + 1))?);
+    }
+    Ok(refs)
+}
+
+/// Run `command` with the referenced credential fields added to its
+/// environment. On Unix tokey replaces itself with the command, so signals
+/// reach it directly and its exit status is the caller's.
+pub fn cmd_exec(env: &[String], env_files: &[PathBuf], command: &[String]) -> Result<()> {
+    // Files first so --env can override a project's defaults
+    let mut refs = Vec::new();
+    for path in env_files {
+        refs.extend(read_env_file(path)?);
+    }
+    for spec in env {
+        refs.push(parse_env_ref(spec)?);
+    }
+    if refs.is_empty() {
+        anyhow::bail!("Nothing to inject: pass --env or --env-file");
+    }
+    let (program, args) = command.split_first().context("No command given")?;
+
+    let store = CredentialStore::new()?;
+    let mut fields = FieldResolver::new(&store);
+    let mut vars = Vec::new();
+    for r in &refs {
+        let value = fields
+            .get(&r.reference)
+            .with_context(|| format!("Setting {}", r.var))?;
+        vars.push((r.var.clone(), value));
+    }
+
+    let mut child = Command::new(program);
+    child.args(args).envs(vars);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        let err = child.exec();
+        Err(err).with_context(|| format!("Failed to run {}", program))
+    }
+    #[cfg(not(unix))]
+    {
+        let status = child
+            .status()
+            .with_context(|| format!("Failed to run {}", program))?;
+        std::process::exit(status.code().unwrap_or(1));
+    }
+}
+
+/// Print assignments for every stored field of an account, named
+/// `<prefix>_<FIELD>` (default prefix `<PROVIDER>_<ACCOUNT>`).
+pub fn cmd_env(
+    provider_name: &str,
+    account: Option<&str>,
+    shell: &str,
+    prefix: Option<&str>,
+) -> Result<()> {
+    let shell: Shell = shell.parse()?;
+    let store = CredentialStore::new()?;
+    let prov = provider::get_provider(provider_name)?;
+    let label = store.resolve_account(provider_name, account)?;
+    let names = store
+        .load_config()?
+        .providers
+        .get(provider_name)
+        .map(|p| p.env.clone())
+        .unwrap_or_default();
+
+    let cred = current_credential(&store, prov.as_ref(), provider_name, &label)?;
+    let default_prefix = format!("{}_{}", provider_name, label);
+    let prefix = prefix.unwrap_or(&default_prefix);
+
+    let mut fields: Vec<_> = cred.fields.iter().collect();
+    fields.sort();
+    for (field, value) in fields {
+        let name = names.get(field).map(String::as_str).unwrap_or(field);
+        println!("{}", shell.assignment(&env::var_name(prefix, name), value));
+    }
+    Ok(())
+}
+
+// -- Templates ----------------------------------------------------------------
+
+/// How often `render --watch` checks the template and credentials for changes.
+const WATCH_POLL: Duration = Duration::from_secs(2);
+/// Re-render at least this often, so tokens nobody else refreshes (no
+/// daemon running) are renewed before they expire.
+const WATCH_RECHECK: Duration = Duration::from_secs(60);
+
+/// Expand `{{ provider[/account].field }}` placeholders in a template and
+/// write the result atomically (or to stdout without `-o`).
+pub fn cmd_render(
+    template: &Path,
+    output: Option<&Path>,
+    mode: Option<&str>,
+    watch: bool,
+) -> Result<()> {
+    let mode = match mode {
+        Some(m) => u32::from_str_radix(m.trim_start_matches("0o"), 8)
+            .ok()
+            .filter(|m| *m <= 0o7777)
+            .with_context(|| format!("Invalid file mode '{}' (use octal, e.g. 0600)", m))?,
+        None => 0o600,
+    };
+    let store = CredentialStore::new()?;
+
+    // clap makes --watch require -o
+    let Some(output) = output else {
+        print!("{}", render_template(&store, template)?);
+        return Ok(());
+    };
+
+    if render_to_file(&store, template, output, mode)? {
+        eprintln!("Rendered {}", output.display());
+    } else {
+        eprintln!("{} is up to date", output.display());
+    }
+    if !watch {
+        return Ok(());
+    }
+
+    eprintln!(
+        "Watching {} and the credential store (Ctrl-C to stop)...",
+        template.display()
+    );
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut seen = (modified(template), modified(store.credentials_path()));
+    let mut last_render = Instant::now();
+    loop {
+        std::thread::sleep(WATCH_POLL);
+        let current = (modified(template), modified(store.credentials_path()));
+        if current == seen && last_render.elapsed() < WATCH_RECHECK {
+            continue;
+        }
+        seen = current;
+        last_render = Instant::now();
+        match render_to_file(&store, template, output, mode) {
+            Ok(true) => eprintln!("Re-rendered {}", output.display()),
+            Ok(false) => {}
+            Err(e) => eprintln!("Render failed, keeping previous output: {:#}", e),
+        }
+    }
+}
+
+fn render_template(store: &CredentialStore, path: &Path) -> Result<String> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let template = Template::parse(&text).with_context(|| path.display().to_string())?;
+    let mut fields = FieldResolver::new(store);
+    template.render(|reference| fields.get(reference))
+}
+
+/// Render into `output` unless it already has the same contents (its mode
+/// is still applied). Returns whether the file was written.
+fn render_to_file(
+    store: &CredentialStore,
+    template: &Path,
+    output: &Path,
+    mode: u32,
+) -> Result<bool> {
+    let rendered = render_template(store, template)?;
+    if fs::read_to_string(output).is_ok_and(|existing| existing == rendered) {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(output, fs::Permissions::from_mode(mode))?;
+        }
+        return Ok(false);
+    }
+    write_atomic(output, &rendered, mode)?;
+    Ok(true)
+}
+
+/// Write via a temporary file in the same directory and rename it into
+/// place, so readers never see a partial file. The temporary file is
+/// created with `mode` (Unix), so the contents are never more exposed.
+fn write_atomic(path: &Path, contents: &str, mode: u32) -> Result<()> {
+    use std::io::Write;
+
+    let name = path
+        .file_name()
+        .with_context(|| format!("{} is not a file path", path.display()))?;
+    let tmp = path.with_file_name(format!(".{}.tokey-tmp", name.to_string_lossy()));
+    let _ = fs::remove_file(&tmp);
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    let mut file = options
+        .open(&tmp)
+        .with_context(|| format!("Failed to create {}", tmp.display()))?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+
+    // The umask may have masked bits off at creation
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(mode))?;
+    }
+    fs::rename(&tmp, path).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+// -- Credential helpers -------------------------------------------------------
+
+/// `provider/label`, or just `provider` for its default account.
+fn split_account(account: &str) -> (&str, Option<&str>) {
+    match account.split_once('/') {
+        Some((p, a)) => (p, Some(a)),
+        None => (account, None),
+    }
+}
+
+/// git's credential helper protocol. tokey owns the credentials, so `store`
+/// is ignored; `erase` (the server rejected what we handed out) refreshes
+/// the account so the next attempt gets a new token.
+pub fn cmd_git_credential(action: &str) -> Result<()> {
+    let request = credential_helper::GitRequest::read(std::io::stdin().lock())?;
+    let store = CredentialStore::new()?;
+    let config = store.load_config()?;
+    let Some(rule) = credential_helper::find_git_rule(&config.git_credentials, &request) else {
+        // Not ours: git asks the next helper or prompts
+        return Ok(());
+    };
+    let (provider_name, account) = split_account(&rule.login.account);
+    let prov = provider::get_provider(provider_name)?;
+    let label = store.resolve_account(provider_name, account)?;
+
+    match action {
+        "get" => {
+            let cred = current_credential(&store, prov.as_ref(), provider_name, &label)?;
+            let (username, password) = credential_helper::login(&rule.login, &cred)?;
+            let expires_at = cred.fields.get("expires_at").and_then(|e| e.parse().ok());
+            print!(
+                "{}",
+                credential_helper::git_response(username.as_deref(), &password, expires_at)?
+            );
+        }
+        "erase" => {
+            let cred = store.get_credential(provider_name, &label)?;
+            let (_, current) = credential_helper::login(&rule.login, &cred)?;
+            if request.get("password") == Some(current.as_str()) {
+                eprintln!(
+                    "{} rejected {}/{}; refreshing...",
+                    rule.host, provider_name, label
+                );
+                refresh_account(&store, prov.as_ref(), provider_name, &label)?;
+            }
+        }
+        // "store", and any action newer git versions add
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Docker's credential helper protocol. Errors also go to stdout, which is
+/// the only output Docker shows.
+pub fn cmd_docker_credential(action: &str) -> Result<()> {
+    docker_credential(action).inspect_err(|e| println!("{:#}", e))
+}
+
+fn docker_credential(action: &str) -> Result<()> {
+    let store = CredentialStore::new()?;
+    let rules = store.load_config()?.docker_credentials;
+    let read_stdin = || std::io::read_to_string(std::io::stdin()).context("Failed to read stdin");
+
+    match action {
+        "get" => {
+            let server_url = read_stdin()?.trim().to_string();
+            let Some(rule) = credential_helper::find_docker_rule(&rules, &server_url) else {
+                // Docker falls back to anonymous access on exactly this answer
+                println!("{}", DOCKER_NOT_FOUND);
+                std::process::exit(1);
+            };
+            let (provider_name, account) = split_account(&rule.login.account);
+            let prov = provider::get_provider(provider_name)?;
+            let label = store.resolve_account(provider_name, account)?;
+            let cred = current_credential(&store, prov.as_ref(), provider_name, &label)?;
+            let (_, secret) = credential_helper::login(&rule.login, &cred)?;
+            let response = DockerCredentials {
+                server_url,
+                username: credential_helper::docker_username(rule, &cred)?,
+                secret,
+            };
+            println!("{}", serde_json::to_string(&response)?);
+        }
+        "store" => {
+            // `docker login` hands over what it got; tokey keeps its own.
+            // Failing here would break `docker login` everywhere under
+            // "credsStore", so other registries' credentials are discarded
+            let request: DockerCredentials =
+                serde_json::from_str(&read_stdin()?).context("Invalid credentials JSON")?;
+            if credential_helper::find_docker_rule(&rules, &request.server_url).is_none() {
+                eprintln!(
+                    "Warning: {} is not managed by tokey; its credentials were not saved",
+                    credential_helper::registry_host(&request.server_url)
+                );
+            }
+        }
+        // `docker logout`: the account itself stays
+        "erase" => {
+            read_stdin()?;
+        }
+        "list" => {
+            let mut registries = serde_json::Map::new();
+            for rule in &rules {
+                let (provider_name, account) = split_account(&rule.login.account);
+                // Listing shouldn't trigger refreshes; a missing account lists as ""
+                let username = store
+                    .resolve_account(provider_name, account)
+                    .and_then(|label| store.get_credential(provider_name, &label))
+                    .and_then(|cred| credential_helper::docker_username(rule, &cred))
+                    .unwrap_or_default();
+                registries.insert(rule.registry.clone(), username.into());
+            }
+            println!("{}", serde_json::Value::from(registries));
+        }
+        "version" => println!("docker-credential-tokey {}", env!("CARGO_PKG_VERSION")),
+        _ => anyhow::bail!("Unknown action '{}' (get, store, erase, list)", action),
+    }
+    Ok(())
+}
+
+// -- Kubernetes ---------------------------------------------------------------
+
+const EXEC_CREDENTIAL_API: &str = "client.authentication.k8s.io/v1";
+/// Tried in order when --field isn't given: OIDC clusters want the id_token.
+const KUBE_TOKEN_FIELDS: &[&str] = &["id_token", "access_token", "token"];
+
+/// kubectl exec plugin: print an ExecCredential with the account's token,
+/// or with `kubeconfig` the `users[]` entry that runs this command.
+pub fn cmd_kube_credential(
+    provider_name: &str,
+    account: Option<&str>,
+    field: Option<&str>,
+    kubeconfig: bool,
+) -> Result<()> {
+    let store = CredentialStore::new()?;
+    let prov = provider::get_provider(provider_name)?;
+    let label = store.resolve_account(provider_name, account)?;
+
+    if kubeconfig {
+        print!("{}", kubeconfig_user(provider_name, &label, field));
+        return Ok(());
+    }
+
+    let cred = current_credential(&store, prov.as_ref(), provider_name, &label)?;
+    let (field, token) = match field {
+        Some(f) => (f, field_value(prov.as_ref(), &cred, f)?),
+        None => KUBE_TOKEN_FIELDS
+            .iter()
+            .find_map(|f| Some((*f, cred.fields.get(*f)?.clone())))
+            .with_context(|| {
+                format!(
+                    "{}/{} has none of {}; pass --field",
+                    provider_name,
+                    label,
+                    KUBE_TOKEN_FIELDS.join(", ")
+                )
+            })?,
+    };
+
+    let mut status = serde_json::json!({ "token": token });
+    // Without an expiry kubectl reuses the token for as long as it runs.
+    // An id_token's lifetime is its own, not the access token's
+    let expires_at = match field {
+        "id_token" => id_token::expires_at(&token),
+        _ => cred
+            .fields
+            .get("expires_at")
+            .and_then(|e| e.parse::<u64>().ok()),
+    };
+    let expires_at = expires_at
+        .and_then(|secs| i64::try_from(secs).ok())
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0));
+    if let Some(expires_at) = expires_at {
+        status["expirationTimestamp"] = expires_at
+            .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+            .into();
+    }
+    let credential = serde_json::json!({
+        "apiVersion": EXEC_CREDENTIAL_API,
+        "kind": "ExecCredential",
+        "status": status,
+    });
+    println!("{}", serde_json::to_string_pretty(&credential)?);
+    Ok(())
+}
+
+/// A kubeconfig `users:` list entry running `tokey kube-credential`.
+fn kubeconfig_user(provider_name: &str, label: &str, field: Option<&str>) -> String {
+    let mut args = vec!["kube-credential", provider_name, label];
+    if let Some(f) = field {
+        args.extend(["--field", f]);
+    }
+    // JSON strings are valid YAML scalars, and quote whatever needs it
+    let quote = |s: &str| serde_json::Value::from(s).to_string();
+    let mut out = String::from("users:\n");
+    out.push_str(&format!(
+        "- name: {}\n",
+        quote(&format!("tokey-{}-{}", provider_name, label))
+    ));
+    out.push_str("  user:\n    exec:\n");
+    out.push_str(&format!("      apiVersion: {}\n", EXEC_CREDENTIAL_API));
+    out.push_str("      command: tokey\n      args:\n");
+    for arg in args {
+        out.push_str(&format!("      - {}\n", quote(arg)));
+    }
+    // Browser sign-ins can't happen under kubectl; reauth is reported instead
+    out.push_str("      interactiveMode: Never\n");
+    out.push_str("      provideClusterInfo: false\n");
+    out
+}
+
+// -- Mail proxy ---------------------------------------------------------------
+
+/// Name of the account secret holding the proxy's local password.
+const MAILPROXY_SECRET: &str = "mailproxy";
+
+pub fn cmd_mailproxy(
+    account: &str,
+    imap: Option<SocketAddr>,
+    smtp: Option<SocketAddr>,
+    imap_upstream: Option<&str>,
+    smtp_upstream: Option<&str>,
+    plain_upstream: bool,
+) -> Result<()> {
+    if imap.is_none() && smtp.is_none() {
+        anyhow::bail!("Nothing to do: pass --imap and/or --smtp");
+    }
+
+    let (provider_name, account) = split_account(account);
+    let store = CredentialStore::new()?;
+    let prov = provider::get_provider(provider_name)?;
+    let label = store.resolve_account(provider_name, account)?;
+
+    // Fail now rather than on the first client login
+    let cred = current_credential(&store, prov.as_ref(), provider_name, &label)?;
+    if prov.sasl_login(&cred).is_none() {
+        anyhow::bail!(
+            "{} accounts can't log in to mail servers",
+            prov.display_name()
+        );
+    }
+
+    let (default_imap, default_smtp) = match provider_name {
+        "google" | "google-sa" => (Some("imap.gmail.com:993"), Some("smtp.gmail.com:465")),
+        _ => (None, None),
+    };
+    let mut routes = Vec::new();
+    for (protocol, listen, upstream, default, flag) in [
+        (
+            mail::Protocol::Imap,
+            imap,
+            imap_upstream,
+            default_imap,
+            "--imap-upstream",
+        ),
+        (
+            mail::Protocol::Smtp,
+            smtp,
+            smtp_upstream,
+            default_smtp,
+            "--smtp-upstream",
+        ),
+    ] {
+        let Some(listen) = listen else { continue };
+        let upstream = upstream.or(default).with_context(|| {
+            format!(
+                "No default {} server for {}; pass {}",
+                protocol, provider_name, flag
+            )
+        })?;
+        routes.push(mail::Route {
+            protocol,
+            listen,
+            upstream: upstream.to_string(),
+            tls: !plain_upstream,
+        });
+    }
+
+    let password = match store
+        .get_secrets(provider_name, &label)?
+        .remove(MAILPROXY_SECRET)
+    {
+        Some(p) => p,
+        None => {
+            let generated: String = rand::thread_rng()
+                .sample_iter(&rand::distributions::Alphanumeric)
+                .take(24)
+                .map(char::from)
+                .collect();
+            store.set_secret(provider_name, &label, MAILPROXY_SECRET, &generated)?;
+            eprintln!(
+                "Generated local password for {}/{}: {}",
+                provider_name, label, generated
+            );
+            eprintln!(
+                "It is stored as secret '{}'; change it with `tokey secret set {} {} {}`.",
+                MAILPROXY_SECRET, provider_name, label, MAILPROXY_SECRET
+            );
+            generated
+        }
+    };
+
+    let provider_name = provider_name.to_string();
+    let login: mail::LoginSource = Arc::new(move || {
+        let store = CredentialStore::new()?;
+        let prov = provider::get_provider(&provider_name)?;
+        let cred = current_credential(&store, prov.as_ref(), &provider_name, &label)?;
+        prov.sasl_login(&cred)
+            .context("Credentials no longer include an access token")
+    });
+
+    mail::serve(routes, password, login)
+}
+
+pub fn cmd_proxy(listen: SocketAddr) -> Result<()> {
+    let store = CredentialStore::new()?;
+    let config = store.load_config()?;
+    if config.http_proxy.is_empty() {
+        anyhow::bail!("No [[http_proxy]] rules in config.toml");
+    }
+
+    let mut routes = Vec::new();
+    for rule in &config.http_proxy {
+        let (provider_name, account) = split_account(&rule.account);
+        let prov = provider::get_provider(provider_name)?;
+        let label = store.resolve_account(provider_name, account)?;
+
+        // Fail now rather than on the first request
+        let cred = current_credential(&store, prov.as_ref(), provider_name, &label)?;
+        if prov.http_headers(&cred).is_empty() {
+            anyhow::bail!(
+                "{}/{} has no credential that can be sent over HTTP",
+                provider_name,
+                label
+            );
+        }
+        let upstream = rule
+            .upstream
+            .clone()
+            .unwrap_or_else(|| format!("https://{}", rule.host));
+        routes.push(http::Route {
+            host: rule.host.clone(),
+            upstream: upstream.trim_end_matches('/').to_string(),
+            account: format!("{}/{}", provider_name, label),
+        });
+    }
+
+    http::serve(listen, routes, Arc::new(StoreAuth::new(store)))
+}
+
+/// Credentials for the HTTP proxy, read from the store on every request so
+/// refreshes by the daemon or other commands are picked up.
+struct StoreAuth {
+    store: CredentialStore,
+    /// One refresh at a time, so concurrent rejections don't each renew
+    /// (and possibly invalidate) the same session. Only held to refresh.
+    lock: Mutex<()>,
+}
+
+impl StoreAuth {
+    fn new(store: CredentialStore) -> Self {
+        StoreAuth {
+            store,
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+impl http::AuthSource for StoreAuth {
+    fn headers(
+        &self,
+        route: &http::Route,
+        rejected: Option<&[(String, String)]>,
+    ) -> Result<Vec<(String, String)>> {
+        let (provider_name, label) = split_account(&route.account);
+        let label = label.context("Route without an account label")?;
+        let store = &self.store;
+        let prov = provider::get_provider(provider_name)?;
+        let must_renew = |cred: &StoredCredential| match rejected {
+            Some(rejected) => prov.http_headers(cred) == rejected,
+            None => prov.needs_refresh(cred),
+        };
+
+        check_reauth(store, provider_name, label)?;
+        let cred = store.get_credential(provider_name, label)?;
+        if !must_renew(&cred) {
+            return Ok(prov.http_headers(&cred));
+        }
+
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        // Requests rejected at the same time all land here; only the first
+        // refreshes, the others find its result in the store
+        let cred = store.get_credential(provider_name, label)?;
+        if rejected.is_some() && must_renew(&cred) {
+            refresh_account(store, prov.as_ref(), provider_name, label)?;
+        }
+        let cred = current_credential(store, prov.as_ref(), provider_name, label)?;
+        Ok(prov.http_headers(&cred))
+    }
+
+    fn rejected(&self, route: &http::Route, status: u16, body: &[u8]) -> bool {
+        let (provider_name, _) = split_account(&route.account);
+        match provider::get_provider(provider_name) {
+            Ok(prov) => prov.http_auth_failed(status, body),
+            Err(_) => status == 401,
+        }
+    }
+}
+
+pub fn cmd_http(
+    account: &str,
+    request: &[String],
+    allow_host: Option<&str>,
+    verbose: bool,
+    raw: bool,
+) -> Result<()> {
+    let request = Request::parse(request)?;
+    let (provider_name, account) = split_account(account);
+    let store = CredentialStore::new()?;
+    let prov = provider::get_provider(provider_name)?;
+    let label = store.resolve_account(provider_name, account)?;
+
+    // A mistyped or pasted URL must not leak the credential to another site
+    let host = request.host();
+    let allowed = provider::http_host_allowed(prov.http_hosts(), host)
+        || allow_host.is_some_and(|h| h.eq_ignore_ascii_case(host));
+    if !allowed {
+        let known = match prov.http_hosts() {
+            [] => String::new(),
+            hosts => format!(" (it is only sent to {} by default)", hosts.join(", ")),
+        };
+        anyhow::bail!(
+            "Refusing to send {}/{} credentials to {}{}; pass --allow-host {} if that is intended",
+            provider_name,
+            label,
+            host,
+            known,
+            host
+        );
+    }
+
+    let cred = current_credential(&store, prov.as_ref(), provider_name, &label)?;
+    let credentials = prov.http_headers(&cred);
+    if credentials.is_empty() {
+        anyhow::bail!(
+            "{}/{} has no credential that can be sent over HTTP",
+            provider_name,
+            label
+        );
+    }
+
+    let client = reqwest::blocking::Client::new();
+    let mut reply = request.send(&client, &credentials)?;
+    if prov.http_auth_failed(reply.status.as_u16(), &reply.body) {
+        eprintln!(
+            "Credentials for {}/{} were rejected -- refreshing and retrying...",
+            provider_name, label
+        );
+        match refresh_account(&store, prov.as_ref(), provider_name, &label) {
+            Ok(()) => {
+                let cred = store.get_credential(provider_name, &label)?;
+                reply = request.send(&client, &prov.http_headers(&cred))?;
+            }
+            Err(e) if ReauthRequired::is_cause_of(&e) => return Err(e),
+            Err(e) => eprintln!("Refresh failed ({:#}).", e),
+        }
+    }
+    print_reply(&reply, verbose, raw)?;
+
+    if prov.http_auth_failed(reply.status.as_u16(), &reply.body) {
+        anyhow::bail!(
+            "{}/{} was rejected by {}",
+            provider_name,
+            label,
+            request.host()
+        );
+    }
+    if !reply.status.is_success() {
+        anyhow::bail!("HTTP {}", reply.status);
+    }
+    Ok(())
+}
+
+/// Body to stdout, pretty-printed if it is JSON; status and headers to
+/// stderr with `verbose`.
+fn print_reply(reply: &Reply, verbose: bool, raw: bool) -> Result<()> {
+    use std::io::Write;
+
+    if verbose {
+        eprintln!("HTTP {}", reply.status);
+        for (name, value) in &reply.headers {
+            eprintln!("{}: {}", name, String::from_utf8_lossy(value.as_bytes()));
+        }
+        eprintln!();
+    }
+    if !raw {
+        if let Ok(json) = serde_json::from_slice::<serde_json::Value>(&reply.body) {
+            println!("{}", serde_json::to_string_pretty(&json)?);
+            return Ok(());
+        }
+    }
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(&reply.body)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+// -- OAuth scopes -------------------------------------------------------------
+
+pub fn cmd_scopes_list(provider_name: &str, account: Option<&str>) -> Result<()> {
+    if provider_name != "google" {
+        anyhow::bail!("Scopes are only tracked for google accounts");
+    }
+    let store = CredentialStore::new()?;
+    let label = store.resolve_account(provider_name, account)?;
+    let cred = store.get_credential(provider_name, &label)?;
+
+    let granted = cred.fields.get("scopes").map(String::as_str).unwrap_or("");
+    for scope in granted.split_whitespace() {
+        println!("{}", scope);
+    }
+    Ok(())
+}
+
+pub fn cmd_scopes_add(provider_name: &str, account: &str, scopes: &[String]) -> Result<()> {
+    if provider_name != "google" {
+        anyhow::bail!("Incremental authorization is only supported for google");
+    }
+    let store = CredentialStore::new()?;
+    let label = store.resolve_account(provider_name, Some(account))?;
+
+    let new_cred = google::add_scopes(&store, &label, scopes)?;
+    store.update_credential(provider_name, &label, new_cred)?;
+    credential_changed(&store, provider_name, &label);
+
+    // Later rotates and re-adds should ask for the added scopes too
+    let mut chosen = google::account_scopes(&store, &label);
+    for scope in scopes {
+        let url = google_oauth::resolve_scope(scope);
+        if !chosen.iter().any(|s| google_oauth::resolve_scope(s) == url) {
+            chosen.push(scope.clone());
+        }
+    }
+    store.update_account(provider_name, &label, |acct| acct.scopes = chosen)?;
+
+    eprintln!("Scopes updated for {}/{}.", provider_name, label);
+    Ok(())
+}
+
+// -- Application Default Credentials ------------------------------------------
+
+pub fn cmd_export_adc(
+    provider_name: &str,
+    account: Option<&str>,
+    output: Option<&Path>,
+    keep_updated: bool,
+    force: bool,
+) -> Result<()> {
+    if provider_name != "google" {
+        anyhow::bail!("ADC export is only supported for google");
+    }
+    let store = CredentialStore::new()?;
+    let label = store.resolve_account(provider_name, account)?;
+    check_reauth(&store, provider_name, &label)?;
+
+    let path = match output {
+        Some(p) => std::path::absolute(p)?,
+        None => adc::default_path()?,
+    };
+    let registered = store
+        .get_account(provider_name, &label)?
+        .adc_exports
+        .contains(&path);
+    if path.exists() && !registered && !force {
+        anyhow::bail!(
+            "{} already exists; pass --force to overwrite it",
+            path.display()
+        );
+    }
+
+    google::authorized_user(&store, &label)?.write(&path)?;
+    eprintln!(
+        "Wrote ADC for {}/{} to {}",
+        provider_name,
+        label,
+        path.display()
+    );
+
+    if keep_updated && !registered {
+        store.update_account(provider_name, &label, |acct| {
+            acct.adc_exports.push(path.clone())
+        })?;
+    }
+    if keep_updated || registered {
+        eprintln!("It will be rewritten whenever the refresh token changes.");
+    }
+    Ok(())
+}
+
+pub fn cmd_import_adc(path: Option<&Path>, label: Option<&str>) -> Result<()> {
+    let store = CredentialStore::new()?;
+    let path = match path {
+        Some(p) => p.to_path_buf(),
+        None => adc::default_path()?,
+    };
+    let label = label.unwrap_or("default");
+
+    eprintln!("Importing {} as google/{}...", path.display(), label);
+    let adc = AuthorizedUser::read(&path)?;
+    let (result, client_config) = google::import_adc(&store, label, &adc)?;
+    warn_duplicate(&store, &google::GoogleProvider, "google", label, &result)?;
+
+    let account = Account {
+        display_name: result.display_name.clone(),
+        provider_id: result.provider_id.clone(),
+        user_id: result.user_id.clone(),
+        claims: result.claims.clone(),
+        created_at: CredentialStore::now(),
+        oauth_client: Some(client_config),
+        health: None,
+        ..store.get_account("google", label).unwrap_or_default()
+    };
+    store.save_account("google", label, account, result.credential)?;
+    credential_changed(&store, "google", label);
+
+    eprintln!("Account saved: google/{} ({})", label, result.display_name);
+    Ok(())
+}
+
+// -- Login script secrets -----------------------------------------------------
+
+pub fn cmd_secret_set(provider_name: &str, account: &str, name: &str) -> Result<()> {
+    let store = CredentialStore::new()?;
+    store.resolve_account(provider_name, Some(account))?;
+
+    let value = prompt::read_secret(&format!("Value for '{}': ", name))?;
+    store.set_secret(provider_name, account, name, &value)?;
+
+    eprintln!("Secret '{}' saved for {}/{}.", name, provider_name, account);
+    Ok(())
+}
+
+pub fn cmd_secret_remove(provider_name: &str, account: &str, name: &str) -> Result<()> {
+    let store = CredentialStore::new()?;
+    store.resolve_account(provider_name, Some(account))?;
+
+    if !store.remove_secret(provider_name, account, name)? {
+        anyhow::bail!("No secret '{}' for {}/{}", name, provider_name, account);
+    }
+
+    eprintln!(
+        "Secret '{}' removed from {}/{}.",
+        name, provider_name, account
+    );
+    Ok(())
+}
+
+pub fn cmd_secret_list(provider_name: &str, account: &str) -> Result<()> {
+    let store = CredentialStore::new()?;
+    store.resolve_account(provider_name, Some(account))?;
+
+    let secrets = store.get_secrets(provider_name, account)?;
+    let mut names: Vec<&String> = secrets.keys().collect();
+    names.sort();
+    for name in names {
+        println!("{}", name);
+    }
+    Ok(())
+}
+
+// -- Daemon management --------------------------------------------------------
+
+fn plist_path() -> Result<PathBuf> {
+    let home = dirs::home_dir().context("Could not determine home directory")?;
+    Ok(home.join("Library/LaunchAgents").join(PLIST_FILENAME))
+}
+
+fn tokey_binary_path() -> Result<PathBuf> {
+    std::env::current_exe().context("Could not determine tokey binary path")
+}
+
+fn log_path() -> Result<PathBuf> {
+    let config_dir = dirs::config_dir()
+        .context("Could not determine config directory")?
+        .join("tokey");
+    fs::create_dir_all(&config_dir)?;
+    Ok(config_dir.join("daemon.log"))
+}
+
+pub fn cmd_daemon_install(interval_hours: u64) -> Result<()> {
+    let plist = plist_path()?;
+    let binary = tokey_binary_path()?;
+    let log = log_path()?;
+    let interval_secs = interval_hours * 3600;
+
+    let plist_content = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{binary}</string>
+        <string>refresh</string>
+        <string>--all</string>
+    </array>
+    <key>StartInterval</key>
+    <integer>{interval}</integer>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+    <key>ProcessType</key>
+    <string>Background</string>
+</dict>
+</plist>
+"#,
+        label = PLIST_LABEL,
+        binary = binary.display(),
+        interval = interval_secs,
+        log = log.display(),
+    );
+
+    // Unload existing if present
+    if plist.exists() {
+        let _ = Command::new("launchctl")
+            .args(["unload", &plist.to_string_lossy()])
+            .output();
     }
 
     // Ensure LaunchAgents dir exists
@@ -410,10 +2007,13 @@ pub fn cmd_daemon_install(interval_hours: u64) -> Result<()> {
     eprintln!("  Interval: every {} hours", interval_hours);
     eprintln!("  Log:      {}", log.display());
     eprintln!("  Runs at load: yes");
-    eprintln!("");
-    eprintln!("The daemon will run `tokey refresh --all` every {} hours", interval_hours);
+    eprintln!();
+    eprintln!(
+        "The daemon will run `tokey refresh --all` every {} hours",
+        interval_hours
+    );
     eprintln!("and once immediately on install/login.");
-    eprintln!("");
+    eprintln!();
     eprintln!("Check status:  tokey daemon status");
     eprintln!("View logs:     tail -f {}", log.display());
     eprintln!("Uninstall:     tokey daemon uninstall");
@@ -440,61 +2040,223 @@ pub fn cmd_daemon_uninstall() -> Result<()> {
     Ok(())
 }
 
-pub fn cmd_daemon_status() -> Result<()> {
-    let plist = plist_path()?;
+pub fn cmd_daemon_status(output: OutputFormat) -> Result<()> {
+    let report = daemon_report()?;
 
-    if !plist.exists() {
+    match output {
+        OutputFormat::Json => return output::print_json("daemon-status", &report),
+        OutputFormat::Table => {
+            let opt = |v: Option<String>| v.unwrap_or_else(|| "-".to_string());
+            output::print_table(
+                &["FIELD", "VALUE"],
+                &[
+                    ("installed", report.installed.to_string()),
+                    ("plist", report.plist.clone()),
+                    ("loaded", opt(report.loaded.map(|l| l.to_string()))),
+                    ("pid", opt(report.pid.map(|p| p.to_string()))),
+                    (
+                        "last_exit_status",
+                        opt(report.last_exit_status.map(|s| s.to_string())),
+                    ),
+                    ("log", opt(report.log.clone())),
+                ]
+                .map(|(field, value)| vec![field.to_string(), value]),
+            );
+            return Ok(());
+        }
+        OutputFormat::Plain => {}
+    }
+
+    if !report.installed {
         eprintln!("Daemon is not installed.");
         eprintln!("Run `tokey daemon install` to set up periodic credential refresh.");
         return Ok(());
     }
 
     eprintln!("Daemon is installed.");
-    eprintln!("  Plist: {}", plist.display());
-
-    let output = Command::new("launchctl")
-        .args(["list", PLIST_LABEL])
-        .output();
+    eprintln!("  Plist: {}", report.plist);
+    match report.loaded {
+        Some(true) => {
+            eprintln!("  Status: loaded");
+            if let Some(pid) = report.pid {
+                eprintln!("  \"PID\" = {};", pid);
+            }
+            if let Some(status) = report.last_exit_status {
+                eprintln!("  \"LastExitStatus\" = {};", status);
+            }
+        }
+        Some(false) => {
+            eprintln!("  Status: not loaded (plist exists but not active)");
+            eprintln!("  Run `tokey daemon install` to re-activate.");
+        }
+        None => eprintln!("  Status: could not query launchctl"),
+    }
 
-    match output {
-        Ok(out) => {
-            let stdout = String::from_utf8_lossy(&out.stdout);
-            if out.status.success() {
-                eprintln!("  Status: loaded");
-                // Parse PID and last exit status from launchctl list output
-                for line in stdout.lines() {
-                    let line = line.trim();
-                    if line.contains("PID") {
-                        eprintln!("  {}", line);
-                    }
-                    if line.contains("LastExitStatus") {
-                        eprintln!("  {}", line);
-                    }
-                }
-            } else {
-                eprintln!("  Status: not loaded (plist exists but not active)");
-                eprintln!("  Run `tokey daemon install` to re-activate.");
+    if let Some(log) = &report.log {
+        eprintln!("  Log: {}", log);
+        if !report.recent_log.is_empty() {
+            eprintln!("  Last log entries:");
+            for line in &report.recent_log {
+                eprintln!("    {}", line);
             }
         }
-        Err(_) => {
-            eprintln!("  Status: could not query launchctl");
+    }
+
+    Ok(())
+}
+
+fn daemon_report() -> Result<DaemonReport> {
+    let plist = plist_path()?;
+    let mut report = DaemonReport {
+        installed: plist.exists(),
+        plist: plist.display().to_string(),
+        ..Default::default()
+    };
+    if !report.installed {
+        return Ok(report);
+    }
+
+    if let Ok(out) = Command::new("launchctl")
+        .args(["list", PLIST_LABEL])
+        .output()
+    {
+        report.loaded = Some(out.status.success());
+        // `launchctl list <label>` prints a plist-ish dict: "PID" = 123;
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        for line in stdout.lines() {
+            let Some((key, value)) = line.trim().split_once(" = ") else {
+                continue;
+            };
+            let value = value.trim_end_matches(';');
+            match key.trim_matches('"') {
+                "PID" => report.pid = value.parse().ok(),
+                "LastExitStatus" => report.last_exit_status = value.parse().ok(),
+                _ => {}
+            }
         }
     }
 
     let log = log_path()?;
     if log.exists() {
-        eprintln!("  Log: {}", log.display());
-        // Show last few lines
+        report.log = Some(log.display().to_string());
         let contents = fs::read_to_string(&log).unwrap_or_default();
         let lines: Vec<&str> = contents.lines().collect();
-        let start = if lines.len() > 5 { lines.len() - 5 } else { 0 };
-        if !lines.is_empty() {
-            eprintln!("  Last log entries:");
-            for line in &lines[start..] {
-                eprintln!("    {}", line);
+        let start = lines.len().saturating_sub(5);
+        report.recent_log = lines[start..].iter().map(|l| l.to_string()).collect();
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+
+    use crate::storage::OAuthClientConfig;
+    use crate::testing::{mock_http, temp_store};
+
+    /// tiny_http only reliably serves as many held-open connections at
+    /// once as it starts worker threads with (4)
+    const CLIENTS: usize = 4;
+
+    /// An upstream accepting only "Bearer new". Rejections wait until all
+    /// clients were rejected, so they all ask for a refresh at once.
+    fn upstream(rejections: Arc<AtomicUsize>) -> String {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let barrier = Arc::new(Barrier::new(CLIENTS));
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let (rejections, barrier) = (rejections.clone(), barrier.clone());
+                std::thread::spawn(move || {
+                    let authorized = request
+                        .headers()
+                        .iter()
+                        .any(|h| h.field.equiv("Authorization") && h.value == "Bearer new");
+                    let status = if authorized {
+                        200
+                    } else {
+                        rejections.fetch_add(1, Ordering::SeqCst);
+                        barrier.wait();
+                        401
+                    };
+                    let _ = request.respond(tiny_http::Response::empty(status));
+                });
             }
-        }
+        });
+        url
     }
 
-    Ok(())
+    #[test]
+    fn proxy_refreshes_once_for_concurrent_rejections() {
+        let store = temp_store();
+        // A second refresh would find nothing listening and fail its request
+        let token_uri = mock_http(vec![(
+            200,
+            r#"{"access_token": "new", "expires_in": 3600}"#.to_string(),
+        )]);
+        let account = Account {
+            oauth_client: Some(OAuthClientConfig {
+                client_id: Some("cid".to_string()),
+                client_secret: Some("secret".to_string()),
+                token_uri: Some(token_uri),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let expires_at = (CredentialStore::now() + 3600).to_string();
+        let fields = [
+            ("refresh_token", "rt"),
+            ("client_id", "cid"),
+            ("access_token", "old"),
+            ("expires_at", expires_at.as_str()),
+        ];
+        let credential = StoredCredential {
+            fields: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            created_at: CredentialStore::now(),
+            last_validated: None,
+        };
+        store
+            .save_account("google", "work", account, credential)
+            .unwrap();
+
+        let rejections = Arc::new(AtomicUsize::new(0));
+        let routes = vec![http::Route {
+            host: "api.example".to_string(),
+            upstream: upstream(rejections.clone()),
+            account: "google/work".to_string(),
+        }];
+        let listen = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        std::thread::spawn(move || http::serve(listen, routes, Arc::new(StoreAuth::new(store))));
+        while TcpStream::connect(listen).is_err() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let proxy = reqwest::Proxy::http(format!("http://{}", listen)).unwrap();
+        let client = reqwest::blocking::Client::builder()
+            .proxy(proxy)
+            .build()
+            .unwrap();
+        let statuses: Vec<u16> = std::thread::scope(|scope| {
+            let requests: Vec<_> = (0..CLIENTS)
+                .map(|_| scope.spawn(|| client.get("http://api.example/v1/me").send()))
+                .collect();
+            requests
+                .into_iter()
+                .map(|r| r.join().unwrap().unwrap().status().as_u16())
+                .collect()
+        });
+        assert_eq!(statuses, vec![200; CLIENTS]);
+        assert_eq!(rejections.load(Ordering::SeqCst), CLIENTS);
+    }
 }
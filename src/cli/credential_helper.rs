@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{DockerCredentialRule, GitCredentialRule, HelperLogin, StoredCredential};
+
+const DEFAULT_USERNAME_FIELD: &str = "username";
+const PASSWORD_FIELDS: &[&str] = &["password", "token", "access_token"];
+
+/// Username and password for a helper rule from the account's credential.
+pub fn login(
+    rule: &HelperLogin,
+    credential: &StoredCredential,
+) -> Result<(Option<String>, String)> {
+    let password = match &rule.password_field {
+        Some(field) => credential
+            .fields
+            .get(field)
+            .with_context(|| format!("{} has no field '{}'", rule.account, field))?,
+        None => PASSWORD_FIELDS
+            .iter()
+            .find_map(|f| credential.fields.get(*f))
+            .with_context(|| {
+                format!(
+                    "{} has none of the fields {}; set password_field",
+                    rule.account,
+                    PASSWORD_FIELDS.join(", ")
+                )
+            })?,
+    };
+    Ok((username(rule, credential)?, password.clone()))
+}
+
+fn username(rule: &HelperLogin, credential: &StoredCredential) -> Result<Option<String>> {
+    Ok(match (&rule.username, &rule.username_field) {
+        (Some(name), _) => Some(name.clone()),
+        (None, Some(field)) => Some(
+            credential
+                .fields
+                .get(field)
+                .with_context(|| format!("{} has no field '{}'", rule.account, field))?
+                .clone(),
+        ),
+        (None, None) => credential.fields.get(DEFAULT_USERNAME_FIELD).cloned(),
+    })
+}
+
+// -- git ----------------------------------------------------------------------
+
+const DEFAULT_PROTOCOL: &str = "https";
+
+/// The attributes git writes to a helper's stdin, one `key=value` per line
+/// up to a blank line or EOF.
+#[derive(Debug, Default)]
+pub struct GitRequest {
+    attrs: HashMap<String, String>,
+}
+
+impl GitRequest {
+    pub fn read(input: impl BufRead) -> Result<Self> {
+        let mut attrs = HashMap::new();
+        for line in input.lines() {
+            let line = line.context("Failed to read credential request")?;
+            if line.is_empty() {
+                break;
+            }
+            // Multi-valued keys (capability[], wwwauth[]) aren't used here
+            if let Some((key, value)) = line.split_once('=') {
+                attrs.insert(key.to_string(), value.to_string());
+            }
+        }
+        Ok(GitRequest { attrs })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.attrs.get(key).map(String::as_str)
+    }
+}
+
+/// The first rule matching the request's protocol, host and path.
+pub fn find_git_rule<'a>(
+    rules: &'a [GitCredentialRule],
+    request: &GitRequest,
+) -> Option<&'a GitCredentialRule> {
+    let protocol = request.get("protocol")?;
+    let host = request.get("host")?;
+    let path = request.get("path").map(|p| p.trim_start_matches('/'));
+
+    rules.iter().find(|rule| {
+        rule.protocol.as_deref().unwrap_or(DEFAULT_PROTOCOL) == protocol
+            && rule.host.eq_ignore_ascii_case(host)
+            && rule.path.as_deref().is_none_or(|prefix| {
+                let prefix = prefix.trim_matches('/');
+                path.is_some_and(|p| p == prefix || p.starts_with(&format!("{}/", prefix)))
+            })
+    })
+}
+
+/// The `get` response. Values can't contain newlines in this protocol.
+pub fn git_response(
+    username: Option<&str>,
+    password: &str,
+    expires_at: Option<u64>,
+) -> Result<String> {
+    let mut out = String::new();
+    for (key, value) in [("username", username), ("password", Some(password))] {
+        if let Some(value) = value {
+            if value.contains(['\n', '\0']) {
+                anyhow::bail!("The {} contains a newline and can't be passed to git", key);
+            }
+            out.push_str(&format!("{}={}\n", key, value));
+        }
+    }
+    // Understood by git 2.41+, which then won't reuse the token past expiry
+    if let Some(expires_at) = expires_at {
+        out.push_str(&format!("password_expiry_utc={}\n", expires_at));
+    }
+    Ok(out)
+}
+
+// -- Docker -------------------------------------------------------------------
+
+/// What Docker expects on stdout (exit status 1) for a registry the helper
+/// has nothing for; anything else is reported as a helper failure.
+pub const DOCKER_NOT_FOUND: &str = "credentials not found in native keychain";
+
+/// The JSON object exchanged by `get` and `store`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DockerCredentials {
+    #[serde(rename = "ServerURL")]
+    pub server_url: String,
+    pub username: String,
+    pub secret: String,
+}
+
+/// The registry host in a server URL as Docker passes it, e.g.
+/// "https://index.docker.io/v1/" or "registry.corp.example:5000".
+pub fn registry_host(server_url: &str) -> &str {
+    let url = server_url.trim();
+    let url = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(url);
+    url.split('/').next().unwrap_or(url)
+}
+
+pub fn find_docker_rule<'a>(
+    rules: &'a [DockerCredentialRule],
+    server_url: &str,
+) -> Option<&'a DockerCredentialRule> {
+    let host = registry_host(server_url);
+    rules
+        .iter()
+        .find(|rule| registry_host(&rule.registry).eq_ignore_ascii_case(host))
+}
+
+/// Docker needs a username; registries taking bearer tokens as basic-auth
+/// passwords usually ignore its value but not its absence.
+pub fn docker_username(
+    rule: &DockerCredentialRule,
+    credential: &StoredCredential,
+) -> Result<String> {
+    username(&rule.login, credential)?.with_context(|| {
+        format!(
+            "No username for {}: set username or username_field in its docker_credentials rule",
+            rule.registry
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(input: &str) -> GitRequest {
+        GitRequest::read(input.as_bytes()).unwrap()
+    }
+
+    fn rule(host: &str, protocol: Option<&str>, path: Option<&str>) -> GitCredentialRule {
+        GitCredentialRule {
+            host: host.to_string(),
+            protocol: protocol.map(str::to_string),
+            path: path.map(str::to_string),
+            login: HelperLogin {
+                account: format!("static/{}", host),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn credential(fields: &[(&str, &str)]) -> StoredCredential {
+        StoredCredential {
+            fields: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            created_at: 0,
+            last_validated: None,
+        }
+    }
+
+    fn matched<'a>(rules: &'a [GitCredentialRule], input: &str) -> Option<&'a str> {
+        find_git_rule(rules, &request(input)).map(|r| r.login.account.as_str())
+    }
+
+    #[test]
+    fn git_rules_match_host_case_insensitively() {
+        let rules = [
+            rule("github.com", None, None),
+            rule("git.corp:8443", None, None),
+        ];
+        assert_eq!(
+            matched(&rules, "protocol=https\nhost=GitHub.com\n"),
+            Some("static/github.com")
+        );
+        assert_eq!(
+            matched(&rules, "protocol=https\nhost=git.corp:8443\n\nignored=1\n"),
+            Some("static/git.corp:8443")
+        );
+        assert_eq!(matched(&rules, "protocol=https\nhost=git.corp\n"), None);
+        assert_eq!(matched(&rules, "protocol=https\n"), None);
+    }
+
+    #[test]
+    fn git_rules_match_protocol() {
+        let rules = [
+            rule("example.com", Some("http"), None),
+            rule("example.com", None, None),
+        ];
+        assert_eq!(
+            matched(&rules, "protocol=http\nhost=example.com\n"),
+            Some("static/example.com")
+        );
+        assert!(std::ptr::eq(
+            find_git_rule(&rules, &request("protocol=https\nhost=example.com\n")).unwrap(),
+            &rules[1]
+        ));
+        assert_eq!(matched(&rules, "protocol=ssh\nhost=example.com\n"), None);
+        assert_eq!(matched(&rules, "host=example.com\n"), None);
+    }
+
+    #[test]
+    fn git_rules_match_path_prefixes() {
+        let rules = [
+            rule("github.com", None, Some("/acme/")),
+            rule("github.com", None, None),
+        ];
+        let with_path = |path: &str| {
+            let input = format!("protocol=https\nhost=github.com\npath={}\n", path);
+            std::ptr::eq(find_git_rule(&rules, &request(&input)).unwrap(), &rules[0])
+        };
+        assert!(with_path("acme"));
+        assert!(with_path("acme/tools.git"));
+        assert!(with_path("/acme/tools.git"));
+        assert!(!with_path("acme-other/tools.git"));
+        assert!(!with_path("other/acme/tools.git"));
+        // Without useHttpPath git sends no path, so only the catch-all applies
+        assert!(std::ptr::eq(
+            find_git_rule(&rules, &request("protocol=https\nhost=github.com\n")).unwrap(),
+            &rules[1]
+        ));
+    }
+
+    #[test]
+    fn login_falls_back_through_password_fields() {
+        let helper = HelperLogin {
+            account: "static/k".to_string(),
+            ..Default::default()
+        };
+        let cred = credential(&[("access_token", "at"), ("token", "t")]);
+        assert_eq!(login(&helper, &cred).unwrap(), (None, "t".to_string()));
+        let cred = credential(&[("access_token", "at"), ("password", "pw")]);
+        assert_eq!(login(&helper, &cred).unwrap(), (None, "pw".to_string()));
+        let cred = credential(&[("access_token", "at")]);
+        assert_eq!(login(&helper, &cred).unwrap(), (None, "at".to_string()));
+        assert!(login(&helper, &credential(&[("secret", "s")])).is_err());
+
+        let helper = HelperLogin {
+            password_field: Some("secret".to_string()),
+            ..helper
+        };
+        let cred = credential(&[("secret", "s"), ("password", "pw")]);
+        assert_eq!(login(&helper, &cred).unwrap(), (None, "s".to_string()));
+        assert!(login(&helper, &credential(&[("password", "pw")])).is_err());
+    }
+
+    #[test]
+    fn login_resolves_username() {
+        let cred = credential(&[("token", "t"), ("username", "alice"), ("user", "bob")]);
+        let mut helper = HelperLogin {
+            account: "static/k".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(login(&helper, &cred).unwrap().0.as_deref(), Some("alice"));
+        assert_eq!(
+            login(&helper, &credential(&[("token", "t")])).unwrap().0,
+            None
+        );
+
+        helper.username_field = Some("user".to_string());
+        assert_eq!(login(&helper, &cred).unwrap().0.as_deref(), Some("bob"));
+        assert!(login(&helper, &credential(&[("token", "t")])).is_err());
+
+        // A fixed username wins over any field
+        helper.username = Some("x-access-token".to_string());
+        assert_eq!(
+            login(&helper, &cred).unwrap().0.as_deref(),
+            Some("x-access-token")
+        );
+    }
+}
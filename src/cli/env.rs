@@ -0,0 +1,205 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+
+/// Output formats for `tokey env`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    /// POSIX `export` lines; also what zsh reads.
+    Bash,
+    Fish,
+    /// PowerShell `$env:` assignments.
+    Pwsh,
+    /// `.env` files (docker compose, python-dotenv, node dotenv).
+    Dotenv,
+    /// systemd `EnvironmentFile=`.
+    Systemd,
+}
+
+impl Shell {
+    pub const NAMES: &'static [&'static str] = &[
+        "bash",
+        "zsh",
+        "fish",
+        "pwsh",
+        "powershell",
+        "dotenv",
+        "systemd",
+    ];
+
+    /// One assignment of `value` to `name`, quoted so the target reads it
+    /// back byte for byte.
+    pub fn assignment(&self, name: &str, value: &str) -> String {
+        match self {
+            Shell::Bash => format!("export {}='{}'", name, value.replace('\'', r"'\''")),
+            Shell::Fish => format!(
+                "set -gx {} '{}'",
+                name,
+                value.replace('\\', r"\\").replace('\'', r"\'")
+            ),
+            Shell::Pwsh => format!("$env:{} = '{}'", name, value.replace('\'', "''")),
+            // Single quotes are literal in most dotenv dialects, but
+            // python-dotenv unescapes \\ and \' inside them; fall back to
+            // double quotes for values that need escaping
+            Shell::Dotenv if !value.contains(['\'', '\\', '\n', '\r']) => {
+                format!("{}='{}'", name, value)
+            }
+            Shell::Dotenv => format!(
+                "{}=\"{}\"",
+                name,
+                value
+                    .replace('\\', r"\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', r"\n")
+                    .replace('\r', r"\r")
+            ),
+            Shell::Systemd => format!(
+                "{}=\"{}\"",
+                name,
+                value
+                    .replace('\\', r"\\")
+                    .replace('"', "\\\"")
+                    .replace('$', r"\$")
+                    .replace('`', r"\`")
+            ),
+        }
+    }
+}
+
+impl FromStr for Shell {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "bash" | "zsh" => Ok(Self::Bash),
+            "fish" => Ok(Self::Fish),
+            "pwsh" | "powershell" => Ok(Self::Pwsh),
+            "dotenv" => Ok(Self::Dotenv),
+            "systemd" => Ok(Self::Systemd),
+            _ => anyhow::bail!("Unsupported shell '{}' ({})", s, Self::NAMES.join(", ")),
+        }
+    }
+}
+
+/// `prefix` and `name` joined into an environment variable name: upper
+/// case, with anything outside `[A-Z0-9_]` turned into `_`.
+pub fn var_name(prefix: &str, name: &str) -> String {
+    let joined = if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}_{}", prefix, name)
+    };
+    let mut var: String = joined
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if var.is_empty() || var.starts_with(|c: char| c.is_ascii_digit()) {
+        var.insert(0, '_');
+    }
+    var
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process::Command;
+
+    /// Values that break naive quoting.
+    const TRICKY: &[&str] = &[
+        "plain",
+        "",
+        "a;b=c&&d|e",
+        "it's \"quoted\"",
+        "''\"\"",
+        "line1\nline2\r\n",
+        r"back\slash\\ and \'",
+        "$HOME ${HOME} `id` $(id) !!",
+        "  spaced  \t",
+        "ünïcödé ☃",
+        "#not a comment",
+    ];
+
+    /// What `shell -c` prints for `$TOKEY_TEST` after evaluating the
+    /// assignment, or None if the shell isn't installed.
+    fn round_trip(shell: &str, script: &str) -> Option<Vec<u8>> {
+        let output = Command::new(shell).arg("-c").arg(script).output().ok()?;
+        assert!(
+            output.status.success(),
+            "{} failed on {:?}: {}",
+            shell,
+            script,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Some(output.stdout)
+    }
+
+    #[test]
+    fn bash_round_trips() {
+        for value in TRICKY {
+            let line = Shell::Bash.assignment("TOKEY_TEST", value);
+            for shell in ["bash", "sh", "zsh"] {
+                let script = format!("{}\nprintf '%s' \"$TOKEY_TEST\"", line);
+                if let Some(out) = round_trip(shell, &script) {
+                    assert_eq!(out, value.as_bytes(), "{}: {}", shell, line);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fish_round_trips() {
+        for value in TRICKY {
+            let line = Shell::Fish.assignment("TOKEY_TEST", value);
+            let script = format!("{}\nprintf '%s' \"$TOKEY_TEST\"", line);
+            if let Some(out) = round_trip("fish", &script) {
+                assert_eq!(out, value.as_bytes(), "{}", line);
+            }
+        }
+        assert_eq!(
+            Shell::Fish.assignment("A", r"it's a \ "),
+            r"set -gx A 'it\'s a \\ '"
+        );
+    }
+
+    #[test]
+    fn pwsh_doubles_single_quotes() {
+        assert_eq!(
+            Shell::Pwsh.assignment("A", "it's $env:HOME `n"),
+            "$env:A = 'it''s $env:HOME `n'"
+        );
+    }
+
+    #[test]
+    fn dotenv_quotes_by_content() {
+        let dotenv = |value| Shell::Dotenv.assignment("A", value);
+        assert_eq!(dotenv("a;b=c $HOME #x"), "A='a;b=c $HOME #x'");
+        assert_eq!(dotenv(r#"say "hi""#), r#"A='say "hi"'"#);
+        // Anything python-dotenv would unescape inside single quotes
+        assert_eq!(dotenv(r"C:\temp"), r#"A="C:\\temp""#);
+        assert_eq!(dotenv("it's"), r#"A="it's""#);
+        assert_eq!(dotenv("a\"b\nc\r"), r#"A="a\"b\nc\r""#);
+    }
+
+    #[test]
+    fn systemd_escapes_specials() {
+        assert_eq!(
+            Shell::Systemd.assignment("A", r#"a"b\c $HOME `id`"#),
+            r#"A="a\"b\\c \$HOME \`id\`""#
+        );
+    }
+
+    #[test]
+    fn var_names_are_sanitised() {
+        assert_eq!(var_name("slack", "token"), "SLACK_TOKEN");
+        assert_eq!(var_name("", "api-key.v2"), "API_KEY_V2");
+        assert_eq!(var_name("", "2fa"), "_2FA");
+        assert_eq!(var_name("", ""), "_");
+    }
+}
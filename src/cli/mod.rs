@@ -1 +1,6 @@
 pub mod commands;
+pub mod credential_helper;
+pub mod env;
+pub mod output;
+pub mod request;
+pub mod template;
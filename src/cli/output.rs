@@ -0,0 +1,339 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::storage::HealthState;
+
+/// Version of the JSON documents printed with `--output json`. Bumped only
+/// for incompatible changes; new fields may appear within a version.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// `--output` formats for `list`, `status`, `refresh` and `daemon status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// The human-readable text tokey has always printed.
+    Plain,
+    /// Aligned columns with a header row.
+    Table,
+    /// One JSON document on stdout.
+    Json,
+}
+
+/// Envelope of every JSON document: `{"version": 1, "kind": "...", ...}`.
+#[derive(Serialize)]
+struct Document<'a, T: Serialize> {
+    version: u32,
+    kind: &'a str,
+    #[serde(flatten)]
+    data: &'a T,
+}
+
+pub fn print_json<T: Serialize>(kind: &str, data: &T) -> Result<()> {
+    println!("{}", json_document(kind, data)?);
+    Ok(())
+}
+
+/// The pretty-printed document `print_json` writes.
+fn json_document<T: Serialize>(kind: &str, data: &T) -> Result<String> {
+    let document = Document {
+        version: SCHEMA_VERSION,
+        kind,
+        data,
+    };
+    Ok(serde_json::to_string_pretty(&document)?)
+}
+
+/// Print rows under a header, each column padded to its widest cell.
+pub fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let header: Vec<String> = header.iter().map(|h| h.to_string()).collect();
+    for row in std::iter::once(&header).chain(rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
+
+/// A unix timestamp as `YYYY-MM-DD HH:MM` UTC for tables; "-" for none.
+pub fn timestamp(ts: Option<u64>) -> String {
+    ts.and_then(|ts| DateTime::<Utc>::from_timestamp(ts as i64, 0))
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+// -- list / status ------------------------------------------------------------
+
+/// `kind: "list"` and `kind: "status"` share this shape.
+#[derive(Debug, Serialize)]
+pub struct AccountsReport {
+    pub accounts: Vec<AccountReport>,
+}
+
+/// One account. Timestamps are unix seconds; fields that don't apply are
+/// null rather than missing.
+#[derive(Debug, Serialize)]
+pub struct AccountReport {
+    pub provider: String,
+    /// Null for providers this build doesn't know.
+    pub provider_display_name: Option<String>,
+    pub label: String,
+    pub display_name: String,
+    pub default: bool,
+    pub has_credentials: bool,
+    pub created_at: Option<u64>,
+    pub age_seconds: Option<u64>,
+    pub max_age_days: Option<u64>,
+    /// Older than `max_age_days`, so the next `get` refreshes first.
+    pub past_max_age: bool,
+    /// When the current token or key stops working, if known.
+    pub expires_at: Option<u64>,
+    pub last_validated: Option<u64>,
+    pub rotated_at: Option<u64>,
+    pub health: HealthReport,
+    /// The provider's `status` warning, e.g. an upcoming key expiry.
+    pub warning: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub state: HealthState,
+    /// Consecutive failed refreshes.
+    pub failures: u32,
+    pub last_error: Option<String>,
+    pub failing_since: Option<u64>,
+}
+
+// -- refresh ------------------------------------------------------------------
+
+/// `kind: "refresh"`, for one account or `--all`.
+#[derive(Debug, Default, Serialize)]
+pub struct RefreshReport {
+    pub results: Vec<RefreshResult>,
+    pub refreshed: u32,
+    pub failed: u32,
+}
+
+impl RefreshReport {
+    pub fn push(&mut self, result: RefreshResult) {
+        match result.outcome {
+            RefreshOutcome::Refreshed => self.refreshed += 1,
+            _ => self.failed += 1,
+        }
+        self.results.push(result);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RefreshResult {
+    pub provider: String,
+    pub label: String,
+    pub outcome: RefreshOutcome,
+    pub error: Option<String>,
+    /// Expiry of the new credential, if it has one.
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RefreshOutcome {
+    Refreshed,
+    /// Possibly transient; the account keeps its old credentials.
+    Failed,
+    /// Only `tokey reauth` will fix it.
+    NeedsReauth,
+}
+
+impl std::fmt::Display for RefreshOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            RefreshOutcome::Refreshed => "refreshed",
+            RefreshOutcome::Failed => "failed",
+            RefreshOutcome::NeedsReauth => "needs-reauth",
+        })
+    }
+}
+
+// -- daemon status ------------------------------------------------------------
+
+/// `kind: "daemon-status"`.
+#[derive(Debug, Default, Serialize)]
+pub struct DaemonReport {
+    pub installed: bool,
+    pub plist: String,
+    /// Whether launchd has the agent loaded; null if launchctl couldn't be run.
+    pub loaded: Option<bool>,
+    pub pid: Option<u32>,
+    pub last_exit_status: Option<i32>,
+    /// Null until the agent has run once.
+    pub log: Option<String>,
+    /// The last few log lines, oldest first.
+    pub recent_log: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    /// Parsed back, so the test pins keys and values, not whitespace.
+    fn document<T: Serialize>(kind: &str, data: &T) -> serde_json::Value {
+        serde_json::from_str(&json_document(kind, data).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn accounts_schema() {
+        let report = AccountsReport {
+            accounts: vec![AccountReport {
+                provider: "google".to_string(),
+                provider_display_name: Some("Google".to_string()),
+                label: "work".to_string(),
+                display_name: "alice@example.com".to_string(),
+                default: true,
+                has_credentials: true,
+                created_at: Some(1_700_000_000),
+                age_seconds: Some(3600),
+                max_age_days: Some(7),
+                past_max_age: false,
+                expires_at: Some(1_700_003_600),
+                last_validated: None,
+                rotated_at: None,
+                health: HealthReport {
+                    state: HealthState::RefreshFailing,
+                    failures: 2,
+                    last_error: Some("timeout".to_string()),
+                    failing_since: Some(1_700_000_100),
+                },
+                warning: None,
+            }],
+        };
+        assert_eq!(
+            document("status", &report),
+            json!({
+                "version": 1,
+                "kind": "status",
+                "accounts": [{
+                    "provider": "google",
+                    "provider_display_name": "Google",
+                    "label": "work",
+                    "display_name": "alice@example.com",
+                    "default": true,
+                    "has_credentials": true,
+                    "created_at": 1_700_000_000,
+                    "age_seconds": 3600,
+                    "max_age_days": 7,
+                    "past_max_age": false,
+                    "expires_at": 1_700_003_600,
+                    "last_validated": null,
+                    "rotated_at": null,
+                    "health": {
+                        "state": "refresh-failing",
+                        "failures": 2,
+                        "last_error": "timeout",
+                        "failing_since": 1_700_000_100,
+                    },
+                    "warning": null,
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn health_states_are_kebab_case() {
+        let states = [
+            HealthState::Ok,
+            HealthState::Expiring,
+            HealthState::RefreshFailing,
+            HealthState::NeedsReauth,
+        ];
+        let names: Vec<_> = states
+            .iter()
+            .map(|s| serde_json::to_value(s).unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                json!("ok"),
+                json!("expiring"),
+                json!("refresh-failing"),
+                json!("needs-reauth")
+            ]
+        );
+    }
+
+    #[test]
+    fn refresh_schema() {
+        let mut report = RefreshReport::default();
+        for (label, outcome) in [
+            ("a", RefreshOutcome::Refreshed),
+            ("b", RefreshOutcome::Failed),
+            ("c", RefreshOutcome::NeedsReauth),
+        ] {
+            report.push(RefreshResult {
+                provider: "slack".to_string(),
+                label: label.to_string(),
+                outcome,
+                error: (outcome != RefreshOutcome::Refreshed).then(|| "boom".to_string()),
+                expires_at: (outcome == RefreshOutcome::Refreshed).then_some(1_700_000_000),
+            });
+        }
+        let result = |label: &str, outcome: &str, error: Option<&str>, expires: Option<u64>| {
+            json!({
+                "provider": "slack",
+                "label": label,
+                "outcome": outcome,
+                "error": error,
+                "expires_at": expires,
+            })
+        };
+        assert_eq!(
+            document("refresh", &report),
+            json!({
+                "version": 1,
+                "kind": "refresh",
+                "results": [
+                    result("a", "refreshed", None, Some(1_700_000_000)),
+                    result("b", "failed", Some("boom"), None),
+                    result("c", "needs-reauth", Some("boom"), None),
+                ],
+                "refreshed": 1,
+                "failed": 2,
+            })
+        );
+    }
+
+    #[test]
+    fn daemon_status_schema() {
+        let report = DaemonReport {
+            installed: true,
+            plist: "/tmp/agent.plist".to_string(),
+            loaded: Some(true),
+            pid: None,
+            last_exit_status: Some(0),
+            log: None,
+            recent_log: vec!["ok".to_string()],
+        };
+        assert_eq!(
+            document("daemon-status", &report),
+            json!({
+                "version": 1,
+                "kind": "daemon-status",
+                "installed": true,
+                "plist": "/tmp/agent.plist",
+                "loaded": true,
+                "pid": null,
+                "last_exit_status": 0,
+                "log": null,
+                "recent_log": ["ok"],
+            })
+        );
+    }
+}
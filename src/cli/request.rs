@@ -0,0 +1,194 @@
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use reqwest::blocking::Client;
+use reqwest::{Method, StatusCode, Url};
+
+use crate::proxy::secure_transport;
+
+/// One request item after the URL, httpie-style.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    /// `Name:Value`
+    Header(String, String),
+    /// `name==value`, always in the query string.
+    Query(String, String),
+    /// `name=value`: a body field, or a query parameter for GET-like methods.
+    Field(String, String),
+    /// `name:=json`: a raw JSON value; makes the body JSON.
+    JsonField(String, serde_json::Value),
+}
+
+impl FromStr for Item {
+    type Err = anyhow::Error;
+
+    /// Split at the first separator; `:=` and `==` win over `:` and `=`
+    /// at the same position.
+    fn from_str(s: &str) -> Result<Self> {
+        for (i, _) in s.char_indices() {
+            let rest = &s[i..];
+            let Some(sep) = [":=", "==", "=", ":"]
+                .into_iter()
+                .find(|sep| rest.starts_with(sep))
+            else {
+                continue;
+            };
+            let (name, value) = (&s[..i], &rest[sep.len()..]);
+            if name.is_empty() {
+                break;
+            }
+            let (name, value) = (name.to_string(), value.to_string());
+            return Ok(match sep {
+                ":=" => {
+                    let json = serde_json::from_str(&value)
+                        .with_context(|| format!("'{}' is not valid JSON after :=", value))?;
+                    Item::JsonField(name, json)
+                }
+                "==" => Item::Query(name, value),
+                "=" => Item::Field(name, value),
+                _ => Item::Header(name, value.trim_start().to_string()),
+            });
+        }
+        anyhow::bail!(
+            "Expected Header:Value, name==query, name=field or name:=json, got '{}'",
+            s
+        )
+    }
+}
+
+enum Body {
+    Empty,
+    Form(Vec<(String, String)>),
+    Json(serde_json::Map<String, serde_json::Value>),
+}
+
+/// A request as given on the command line, before credentials are added.
+pub struct Request {
+    method: Method,
+    url: Url,
+    headers: Vec<(String, String)>,
+    body: Body,
+}
+
+/// What came back, fully read so it can be checked for auth errors.
+pub struct Reply {
+    pub status: StatusCode,
+    pub headers: reqwest::header::HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Parse `[METHOD] URL [ITEM...]`. The method defaults to GET, or POST
+    /// when there are body fields; `https://` is assumed without a scheme.
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let (method, rest) = match args {
+            [method, rest @ ..] if is_method(method) && !rest.is_empty() => (Some(method), rest),
+            _ => (None, args),
+        };
+        let (url, items) = rest.split_first().context("No URL given")?;
+        let url = if url.contains("://") {
+            url.to_string()
+        } else {
+            format!("https://{}", url)
+        };
+        let mut url = Url::parse(&url).with_context(|| format!("Invalid URL '{}'", url))?;
+        if !secure_transport(&url) {
+            anyhow::bail!("Refusing to send credentials to {}; use https://", url);
+        }
+        let items = items
+            .iter()
+            .map(|item| item.parse())
+            .collect::<Result<Vec<Item>>>()?;
+
+        let has_body = items
+            .iter()
+            .any(|item| matches!(item, Item::Field(..) | Item::JsonField(..)));
+        let method = match method {
+            Some(method) => Method::from_bytes(method.to_ascii_uppercase().as_bytes())?,
+            None if has_body => Method::POST,
+            None => Method::GET,
+        };
+        let fields_in_query = matches!(method, Method::GET | Method::HEAD | Method::DELETE);
+        let json = items.iter().any(|item| matches!(item, Item::JsonField(..)));
+        if json && fields_in_query {
+            anyhow::bail!("{} requests have no body for := fields", method);
+        }
+
+        let mut headers = Vec::new();
+        let mut form = Vec::new();
+        let mut object = serde_json::Map::new();
+        for item in items {
+            match item {
+                Item::Header(name, value) => headers.push((name, value)),
+                Item::Query(name, value) => {
+                    url.query_pairs_mut().append_pair(&name, &value);
+                }
+                Item::Field(name, value) if fields_in_query => {
+                    url.query_pairs_mut().append_pair(&name, &value);
+                }
+                Item::Field(name, value) if json => {
+                    object.insert(name, value.into());
+                }
+                Item::Field(name, value) => form.push((name, value)),
+                Item::JsonField(name, value) => {
+                    object.insert(name, value);
+                }
+            }
+        }
+        let body = if json {
+            Body::Json(object)
+        } else if !form.is_empty() {
+            Body::Form(form)
+        } else {
+            Body::Empty
+        };
+        Ok(Request {
+            method,
+            url,
+            headers,
+            body,
+        })
+    }
+
+    pub fn host(&self) -> &str {
+        self.url.host_str().unwrap_or_default()
+    }
+
+    /// Send with `credentials` as headers. Headers given on the command line
+    /// take precedence, so e.g. an explicit Authorization is sent as is.
+    pub fn send(&self, client: &Client, credentials: &[(String, String)]) -> Result<Reply> {
+        let mut request = client.request(self.method.clone(), self.url.clone());
+        for (name, value) in credentials {
+            if !self
+                .headers
+                .iter()
+                .any(|(n, _)| n.eq_ignore_ascii_case(name))
+            {
+                request = request.header(name, value);
+            }
+        }
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        request = match &self.body {
+            Body::Empty => request,
+            Body::Form(fields) => request.form(fields),
+            Body::Json(object) => request.json(object),
+        };
+
+        let response = request
+            .send()
+            .with_context(|| format!("{} {} failed", self.method, self.url))?;
+        Ok(Reply {
+            status: response.status(),
+            headers: response.headers().clone(),
+            body: response.bytes()?.to_vec(),
+        })
+    }
+}
+
+fn is_method(s: &str) -> bool {
+    ["GET", "POST", "PUT", "PATCH", "DELETE", "HEAD", "OPTIONS"]
+        .iter()
+        .any(|m| m.eq_ignore_ascii_case(s))
+}
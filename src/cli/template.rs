@@ -0,0 +1,172 @@
+use anyhow::{Context, Result};
+
+use crate::uri::SecretRef;
+
+/// A placeholder's contents: a `tokey://` reference, or the short form
+/// `provider[/account].field`.
+fn parse_placeholder(expr: &str) -> Result<SecretRef> {
+    if SecretRef::is_reference(expr) {
+        return expr.parse();
+    }
+    let (account, field) = expr
+        .rsplit_once('.')
+        .with_context(|| format!("'{}' has no .field (e.g. slack/work.token)", expr))?;
+    let (provider, account) = match account.split_once('/') {
+        Some((p, a)) => (p, Some(a)),
+        None => (account, None),
+    };
+    let valid = |part: &str| {
+        !part.is_empty()
+            && !part
+                .chars()
+                .any(|c| c.is_whitespace() || matches!(c, '{' | '}'))
+    };
+    if !valid(provider) || !valid(field) || !account.is_none_or(valid) {
+        anyhow::bail!("Expected provider[/account].field, got '{}'", expr);
+    }
+    Ok(SecretRef::new(provider, account, field))
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    Value(SecretRef),
+}
+
+/// A parsed template: literal text with placeholders in between. `\{{`
+/// stands for a literal `{{`.
+pub struct Template<'a> {
+    segments: Vec<Segment<'a>>,
+}
+
+impl<'a> Template<'a> {
+    pub fn parse(text: &'a str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find("{{") {
+            if let Some(text) = rest[..start].strip_suffix('\\') {
+                segments.push(Segment::Text(text));
+                segments.push(Segment::Text("{{"));
+                rest = &rest[start + 2..];
+                continue;
+            }
+            segments.push(Segment::Text(&rest[..start]));
+            let line = text[..text.len() - rest.len() + start]
+                .matches('\n')
+                .count()
+                + 1;
+            let after = &rest[start + 2..];
+            let end = after
+                .find("}}")
+                .filter(|&end| !after[..end].contains("{{"))
+                .with_context(|| format!("line {}: unterminated {{{{", line))?;
+            let placeholder =
+                parse_placeholder(after[..end].trim()).with_context(|| format!("line {}", line))?;
+            segments.push(Segment::Value(placeholder));
+            rest = &after[end + 2..];
+        }
+        segments.push(Segment::Text(rest));
+        Ok(Template { segments })
+    }
+
+    /// Substitute each placeholder with the value `resolve` returns for it.
+    pub fn render(&self, mut resolve: impl FnMut(&SecretRef) -> Result<String>) -> Result<String> {
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => out.push_str(text),
+                Segment::Value(p) => out.push_str(&resolve(p)?),
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Render with each placeholder replaced by its reference.
+    fn expand(text: &str) -> Result<String> {
+        Template::parse(text)?.render(|r| Ok(format!("<{}>", r)))
+    }
+
+    #[test]
+    fn expands_short_and_uri_placeholders() {
+        assert_eq!(
+            expand("User {{ google/work.email }}\n").unwrap(),
+            "User <tokey://google/work/email>\n"
+        );
+        assert_eq!(expand("{{slack.token}}").unwrap(), "<tokey://slack/token>");
+        assert_eq!(
+            expand("{{ tokey://slack/work/token?json }}").unwrap(),
+            "<tokey://slack/work/token?json>"
+        );
+        assert_eq!(expand("no placeholders }}").unwrap(), "no placeholders }}");
+    }
+
+    #[test]
+    fn expands_several_placeholders_on_one_line() {
+        assert_eq!(
+            expand("{{ a/x.id }}:{{ a/x.secret }}@{{b.host}}").unwrap(),
+            "<tokey://a/x/id>:<tokey://a/x/secret>@<tokey://b/host>"
+        );
+    }
+
+    #[test]
+    fn escaped_braces_stay_literal() {
+        assert_eq!(
+            expand("\\{{ not.this }} but {{ this.one }}").unwrap(),
+            "{{ not.this }} but <tokey://this/one>"
+        );
+        assert_eq!(expand("\\{{\\{{").unwrap(), "{{{{");
+    }
+
+    #[test]
+    fn unterminated_placeholders_are_errors() {
+        for text in [
+            "a {{ slack.token",
+            "a\nb {{ slack.token }\n",
+            "{{ slack.token {{ google.email }}",
+            "{{ tokey://slack/work {{ google.email }}",
+        ] {
+            let err = format!("{:#}", expand(text).unwrap_err());
+            assert!(err.contains("unterminated"), "{:?}: {}", text, err);
+        }
+        let err = format!("{:#}", expand("ok\n\nx {{ a.b").unwrap_err());
+        assert!(err.starts_with("line 3"), "{}", err);
+    }
+
+    #[test]
+    fn malformed_placeholders_are_errors() {
+        for text in [
+            "{{}}",
+            "{{ slack }}",
+            "{{ .token }}",
+            "{{ slack. }}",
+            "{{ slack/.token }}",
+            "{{ slack work.token }}",
+            "{{ tokey://slack }}",
+            "{{ tokey://slack/work/token?nope }}",
+        ] {
+            assert!(expand(text).is_err(), "{:?}", text);
+        }
+        let err = format!("{:#}", expand("x\n{{ slack }}").unwrap_err());
+        assert!(err.starts_with("line 2"), "{}", err);
+    }
+
+    #[test]
+    fn unknown_references_fail_the_whole_render() {
+        let template = Template::parse("a={{ known.x }} b={{ missing.y }}").unwrap();
+        let mut resolved = Vec::new();
+        let result = template.render(|r| {
+            resolved.push(r.provider.clone());
+            match r.provider.as_str() {
+                "known" => Ok("1".to_string()),
+                _ => anyhow::bail!("Provider '{}' not found", r.provider),
+            }
+        });
+        // No half-expanded output: the error replaces the whole result
+        assert!(result.unwrap_err().to_string().contains("'missing'"));
+        assert_eq!(resolved, ["known", "missing"]);
+    }
+}
@@ -1,4 +1,8 @@
 pub mod auth;
 pub mod cli;
 pub mod provider;
+pub mod proxy;
 pub mod storage;
+#[cfg(test)]
+mod testing;
+pub mod uri;
@@ -1,9 +1,14 @@
+use std::ffi::OsString;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
 use clap::{Parser, Subcommand};
 
-mod auth;
-mod cli;
-mod provider;
-mod storage;
+use tokey::auth::sasl::SaslMechanism;
+use tokey::auth::ReauthRequired;
+use tokey::cli;
+use tokey::cli::env::Shell;
+use tokey::cli::output::OutputFormat;
 
 #[derive(Parser)]
 #[command(name = "tokey", about = "Credential manager for apps without SSO")]
@@ -18,6 +23,9 @@ enum Commands {
     List {
         /// Filter by provider name
         provider: Option<String>,
+        /// Output format (json is a versioned schema; see README)
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Plain)]
+        output: OutputFormat,
     },
 
     /// Get credentials (JSON to stdout, auto-refreshes expired creds)
@@ -29,6 +37,15 @@ enum Commands {
         /// Output a single field value instead of JSON
         #[arg(short, long)]
         field: Option<String>,
+        /// Google only: mint a token limited to these granted scopes
+        #[arg(long = "scope", value_delimiter = ',')]
+        scopes: Vec<String>,
+        /// Output a SASL initial response for IMAP/SMTP (xoauth2, oauthbearer)
+        #[arg(long, conflicts_with = "field", value_parser = clap::builder::PossibleValuesParser::new(SaslMechanism::NAMES))]
+        sasl: Option<String>,
+        /// Base64-encode the value printed by -f/--sasl
+        #[arg(long)]
+        base64: bool,
     },
 
     /// Add a new account via browser authentication
@@ -38,6 +55,12 @@ enum Commands {
         /// Account label
         #[arg(short, long)]
         label: Option<String>,
+        /// Google only: scopes to request (aliases like gmail,calendar or URLs)
+        #[arg(long, value_delimiter = ',')]
+        scopes: Vec<String>,
+        /// Expiry date for static keys (YYYY-MM-DD)
+        #[arg(long)]
+        expires: Option<String>,
     },
 
     /// Force credential renewal (headless, no browser window)
@@ -49,14 +72,39 @@ enum Commands {
         /// Refresh all accounts across all providers
         #[arg(long)]
         all: bool,
+        /// Output format (json is a versioned schema; see README)
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Plain)]
+        output: OutputFormat,
     },
 
-    /// Delete an account and its credentials
+    /// Revoke an account's credentials upstream and delete them locally
     Remove {
         /// Provider name
         provider: String,
         /// Account label
         account: String,
+        /// Only delete local state; leave tokens/sessions valid upstream
+        #[arg(long)]
+        keep_remote: bool,
+    },
+
+    /// Replace an account's credentials in place (e.g. a rotated API key)
+    Rotate {
+        /// Provider name
+        provider: String,
+        /// Account label
+        account: String,
+        /// New expiry date for static keys (YYYY-MM-DD)
+        #[arg(long)]
+        expires: Option<String>,
+    },
+
+    /// Sign in again for an existing account, keeping its label and settings
+    Reauth {
+        /// Provider name
+        provider: String,
+        /// Account label
+        account: String,
     },
 
     /// Credential health overview
@@ -65,6 +113,9 @@ enum Commands {
         provider: Option<String>,
         /// Filter by account label
         account: Option<String>,
+        /// Output format (json is a versioned schema; see README)
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Plain)]
+        output: OutputFormat,
     },
 
     /// Set the default account for a provider
@@ -75,6 +126,174 @@ enum Commands {
         account: String,
     },
 
+    /// Inspect or extend the OAuth scopes granted to an account
+    Scopes {
+        #[command(subcommand)]
+        action: ScopesAction,
+    },
+
+    /// Manage named secrets used by login scripts
+    Secret {
+        #[command(subcommand)]
+        action: SecretAction,
+    },
+
+    /// Write an account as a gcloud Application Default Credentials file
+    ExportAdc {
+        /// Provider name (google)
+        provider: String,
+        /// Account label (uses default if omitted)
+        account: Option<String>,
+        /// Output path (default: gcloud's application_default_credentials.json)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Rewrite the file whenever the account's refresh token changes
+        #[arg(long)]
+        keep_updated: bool,
+        /// Overwrite an existing file not previously written by tokey
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Import an authorized_user ADC file (e.g. a gcloud login) as a google account
+    ImportAdc {
+        /// ADC file (default: gcloud's application_default_credentials.json)
+        path: Option<PathBuf>,
+        /// Account label
+        #[arg(short, long)]
+        label: Option<String>,
+    },
+
+    /// Print an account's credentials as shell/env-file assignments
+    Env {
+        /// Provider name (e.g. slack)
+        provider: String,
+        /// Account label (uses default if omitted)
+        account: Option<String>,
+        /// Output syntax
+        #[arg(long, default_value = "bash", value_parser = clap::builder::PossibleValuesParser::new(Shell::NAMES))]
+        shell: String,
+        /// Variable name prefix (default: PROVIDER_ACCOUNT; "" for none)
+        #[arg(long)]
+        prefix: Option<String>,
+    },
+
+    /// Print the value of a tokey:// reference (tokey://provider[/account]/field)
+    Read {
+        /// Reference, e.g. tokey://slack/work/token or tokey://google/access_token?base64
+        uri: String,
+    },
+
+    /// Resolve the tokey:// references in a JSON document or list to JSON
+    Resolve {
+        /// JSON document, or one reference per line (stdin if omitted or -)
+        path: Option<PathBuf>,
+    },
+
+    /// Fill {{ provider[/account].field }} placeholders in a config file template
+    Render {
+        /// Template file
+        template: PathBuf,
+        /// Output file, written atomically (stdout if omitted)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Output file permissions, in octal (default: 0600)
+        #[arg(long)]
+        mode: Option<String>,
+        /// Keep running and re-render when the credentials change
+        #[arg(long, requires = "output")]
+        watch: bool,
+    },
+
+    /// Run a command with credentials added to its environment
+    Exec {
+        /// Variable to set, as VAR=provider[/account]#field (repeatable)
+        #[arg(short, long = "env", value_name = "VAR=REF")]
+        env: Vec<String>,
+        /// File of VAR=provider[/account]#field lines (repeatable)
+        #[arg(long = "env-file", value_name = "PATH")]
+        env_files: Vec<PathBuf>,
+        /// Command and its arguments, after --
+        #[arg(required = true, last = true)]
+        command: Vec<String>,
+    },
+
+    /// Git credential helper (credential.helper = "!tokey git-credential")
+    GitCredential {
+        /// get, store or erase (sent by git)
+        action: String,
+    },
+
+    /// Docker credential helper (credsStore = "tokey", via docker-credential-tokey)
+    DockerCredential {
+        /// get, store, erase or list (sent by Docker)
+        action: String,
+    },
+
+    /// kubectl exec credential plugin: print an ExecCredential for an account
+    KubeCredential {
+        /// Provider name (e.g. google)
+        provider: String,
+        /// Account label (uses default if omitted)
+        account: Option<String>,
+        /// Field to use as the bearer token (default: id_token, access_token or token)
+        #[arg(short, long)]
+        field: Option<String>,
+        /// Print the kubeconfig users[] entry that runs this command instead
+        #[arg(long)]
+        kubeconfig: bool,
+    },
+
+    /// Local IMAP/SMTP proxy that logs in upstream with the account's OAuth token
+    Mailproxy {
+        /// Account as provider/label (label defaults to the provider's default)
+        #[arg(long)]
+        account: String,
+        /// Local IMAP listen address (e.g. 127.0.0.1:1143)
+        #[arg(long)]
+        imap: Option<SocketAddr>,
+        /// Local SMTP listen address (e.g. 127.0.0.1:1025)
+        #[arg(long)]
+        smtp: Option<SocketAddr>,
+        /// Upstream IMAP server as host:port (default for Google: imap.gmail.com:993)
+        #[arg(long)]
+        imap_upstream: Option<String>,
+        /// Upstream SMTP server as host:port (default for Google: smtp.gmail.com:465)
+        #[arg(long)]
+        smtp_upstream: Option<String>,
+        /// Connect upstream without TLS (loopback only, for local test servers)
+        #[arg(long)]
+        plain_upstream: bool,
+    },
+
+    /// Local HTTP proxy that adds account credentials to requests, routed
+    /// by host through [[http_proxy]] rules in config.toml
+    Proxy {
+        /// Listen address (loopback only)
+        #[arg(long, default_value = "127.0.0.1:8899")]
+        listen: SocketAddr,
+    },
+
+    /// Send an HTTP request with an account's credentials attached
+    Http {
+        /// Account as provider/label (label defaults to the provider's default)
+        account: String,
+        /// [METHOD] URL [ITEM...], where items are Header:Value, name==query,
+        /// name=field and name:=json
+        #[arg(required = true, value_name = "REQUEST")]
+        request: Vec<String>,
+        /// Send the credentials to this host even though it isn't one of the
+        /// provider's own API hosts
+        #[arg(long, value_name = "HOST")]
+        allow_host: Option<String>,
+        /// Print the response status and headers to stderr
+        #[arg(short, long)]
+        verbose: bool,
+        /// Print JSON responses as received instead of pretty-printed
+        #[arg(long)]
+        raw: bool,
+    },
+
     /// Manage the background refresh daemon (macOS launchd)
     Daemon {
         #[command(subcommand)]
@@ -82,6 +301,56 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum ScopesAction {
+    /// Show the scopes granted to an account
+    List {
+        /// Provider name (google)
+        provider: String,
+        /// Account label (uses default if omitted)
+        account: Option<String>,
+    },
+    /// Incrementally authorize more scopes, keeping those already granted
+    Add {
+        /// Provider name (google)
+        provider: String,
+        /// Account label
+        account: String,
+        /// Scopes to add (aliases like drive or full URLs)
+        #[arg(required = true, value_delimiter = ',')]
+        scopes: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum SecretAction {
+    /// Store a secret (prompts without echo, or reads one line from stdin)
+    Set {
+        /// Provider name
+        provider: String,
+        /// Account label
+        account: String,
+        /// Secret name as referenced by the login script
+        name: String,
+    },
+    /// Delete a stored secret
+    Remove {
+        /// Provider name
+        provider: String,
+        /// Account label
+        account: String,
+        /// Secret name
+        name: String,
+    },
+    /// List stored secret names (values are never printed)
+    List {
+        /// Provider name
+        provider: String,
+        /// Account label
+        account: String,
+    },
+}
+
 #[derive(Subcommand)]
 enum DaemonAction {
     /// Install the launchd agent for periodic credential refresh
@@ -93,49 +362,200 @@ enum DaemonAction {
     /// Uninstall the launchd agent
     Uninstall,
     /// Check daemon status
-    Status,
+    Status {
+        /// Output format (json is a versioned schema; see README)
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Plain)]
+        output: OutputFormat,
+    },
+}
+
+/// Arguments with the subcommand filled in when run through a helper link:
+/// git runs `git-credential-tokey <action>` for `credential.helper = tokey`,
+/// Docker `docker-credential-tokey <action>` for `"credsStore": "tokey"`.
+fn helper_args() -> Vec<OsString> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    let invoked_as = args
+        .first()
+        .and_then(|arg0| Path::new(arg0).file_stem())
+        .and_then(|name| name.to_str());
+    let subcommand = match invoked_as {
+        Some("git-credential-tokey") => "git-credential",
+        Some("docker-credential-tokey") => "docker-credential",
+        _ => return args,
+    };
+    args.insert(1, subcommand.into());
+    args
 }
 
 fn main() {
-    let cli = Cli::parse();
+    let cli = Cli::parse_from(helper_args());
 
     let result = match &cli.command {
-        Commands::List { provider } => cli::commands::cmd_list(provider.as_deref()),
+        Commands::List { provider, output } => {
+            cli::commands::cmd_list(provider.as_deref(), *output)
+        }
         Commands::Get {
             provider,
             account,
             field,
-        } => cli::commands::cmd_get(provider, account.as_deref(), field.as_deref()),
-        Commands::Add { provider, label } => cli::commands::cmd_add(provider, label.as_deref()),
+            scopes,
+            sasl,
+            base64,
+        } => cli::commands::cmd_get(
+            provider,
+            account.as_deref(),
+            field.as_deref(),
+            scopes,
+            sasl.as_deref(),
+            *base64,
+        ),
+        Commands::Add {
+            provider,
+            label,
+            scopes,
+            expires,
+        } => cli::commands::cmd_add(provider, label.as_deref(), scopes, expires.as_deref()),
         Commands::Refresh {
             provider,
             account,
             all,
+            output,
         } => {
             if *all {
-                cli::commands::cmd_refresh_all()
+                cli::commands::cmd_refresh_all(*output)
             } else {
                 let prov = provider.as_deref().unwrap_or_else(|| {
                     eprintln!("error: provider name required (or use --all)");
                     std::process::exit(1);
                 });
-                cli::commands::cmd_refresh(prov, account.as_deref())
+                cli::commands::cmd_refresh(prov, account.as_deref(), *output)
             }
         }
-        Commands::Remove { provider, account } => cli::commands::cmd_remove(provider, account),
-        Commands::Status { provider, account } => {
-            cli::commands::cmd_status(provider.as_deref(), account.as_deref())
-        }
+        Commands::Remove {
+            provider,
+            account,
+            keep_remote,
+        } => cli::commands::cmd_remove(provider, account, *keep_remote),
+        Commands::Rotate {
+            provider,
+            account,
+            expires,
+        } => cli::commands::cmd_rotate(provider, account, expires.as_deref()),
+        Commands::Reauth { provider, account } => cli::commands::cmd_reauth(provider, account),
+        Commands::Status {
+            provider,
+            account,
+            output,
+        } => cli::commands::cmd_status(provider.as_deref(), account.as_deref(), *output),
         Commands::Default { provider, account } => cli::commands::cmd_default(provider, account),
+        Commands::Scopes { action } => match action {
+            ScopesAction::List { provider, account } => {
+                cli::commands::cmd_scopes_list(provider, account.as_deref())
+            }
+            ScopesAction::Add {
+                provider,
+                account,
+                scopes,
+            } => cli::commands::cmd_scopes_add(provider, account, scopes),
+        },
+        Commands::Secret { action } => match action {
+            SecretAction::Set {
+                provider,
+                account,
+                name,
+            } => cli::commands::cmd_secret_set(provider, account, name),
+            SecretAction::Remove {
+                provider,
+                account,
+                name,
+            } => cli::commands::cmd_secret_remove(provider, account, name),
+            SecretAction::List { provider, account } => {
+                cli::commands::cmd_secret_list(provider, account)
+            }
+        },
+        Commands::ExportAdc {
+            provider,
+            account,
+            output,
+            keep_updated,
+            force,
+        } => cli::commands::cmd_export_adc(
+            provider,
+            account.as_deref(),
+            output.as_deref(),
+            *keep_updated,
+            *force,
+        ),
+        Commands::ImportAdc { path, label } => {
+            cli::commands::cmd_import_adc(path.as_deref(), label.as_deref())
+        }
+        Commands::Env {
+            provider,
+            account,
+            shell,
+            prefix,
+        } => cli::commands::cmd_env(provider, account.as_deref(), shell, prefix.as_deref()),
+        Commands::Read { uri } => cli::commands::cmd_read(uri),
+        Commands::Resolve { path } => cli::commands::cmd_resolve(path.as_deref()),
+        Commands::Render {
+            template,
+            output,
+            mode,
+            watch,
+        } => cli::commands::cmd_render(template, output.as_deref(), mode.as_deref(), *watch),
+        Commands::Exec {
+            env,
+            env_files,
+            command,
+        } => cli::commands::cmd_exec(env, env_files, command),
+        Commands::GitCredential { action } => cli::commands::cmd_git_credential(action),
+        Commands::DockerCredential { action } => cli::commands::cmd_docker_credential(action),
+        Commands::KubeCredential {
+            provider,
+            account,
+            field,
+            kubeconfig,
+        } => cli::commands::cmd_kube_credential(
+            provider,
+            account.as_deref(),
+            field.as_deref(),
+            *kubeconfig,
+        ),
+        Commands::Mailproxy {
+            account,
+            imap,
+            smtp,
+            imap_upstream,
+            smtp_upstream,
+            plain_upstream,
+        } => cli::commands::cmd_mailproxy(
+            account,
+            *imap,
+            *smtp,
+            imap_upstream.as_deref(),
+            smtp_upstream.as_deref(),
+            *plain_upstream,
+        ),
+        Commands::Proxy { listen } => cli::commands::cmd_proxy(*listen),
+        Commands::Http {
+            account,
+            request,
+            allow_host,
+            verbose,
+            raw,
+        } => cli::commands::cmd_http(account, request, allow_host.as_deref(), *verbose, *raw),
         Commands::Daemon { action } => match action {
             DaemonAction::Install { interval } => cli::commands::cmd_daemon_install(*interval),
             DaemonAction::Uninstall => cli::commands::cmd_daemon_uninstall(),
-            DaemonAction::Status => cli::commands::cmd_daemon_status(),
+            DaemonAction::Status { output } => cli::commands::cmd_daemon_status(*output),
         },
     };
 
     if let Err(e) = result {
         eprintln!("error: {:#}", e);
+        if ReauthRequired::is_cause_of(&e) {
+            std::process::exit(cli::commands::EXIT_NEEDS_REAUTH);
+        }
         std::process::exit(1);
     }
 }
@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+
+use crate::storage::{
+    AuthResult, CommandConfig, CommandOutputFormat, CredentialStore, StoredCredential,
+};
+
+use super::Provider;
+
+/// Refresh this long before a known expiry.
+const EXPIRY_SKEW_SECS: u64 = 60;
+
+/// Credentials produced by an arbitrary shell command (gcloud, aws, internal
+/// token minters). Output is cached in the store and the command is re-run
+/// only once the cached value is stale.
+pub struct ExecProvider;
+
+impl Provider for ExecProvider {
+    fn name(&self) -> &str {
+        "exec"
+    }
+
+    fn display_name(&self) -> &str {
+        "Command"
+    }
+
+    fn credential_fields(&self) -> &[&str] {
+        &["token", "expires_at"]
+    }
+
+    fn max_credential_age_days(&self) -> u64 {
+        // Only used when the command reports neither a TTL nor an expiry
+        1
+    }
+
+    fn authenticate(&self, store: &CredentialStore, label: &str) -> Result<AuthResult> {
+        let config = store.command_config("exec", label)?;
+        let credential = run_command(&config)?;
+
+        eprintln!(
+            "Command produced {} field(s): {}",
+            credential.fields.len(),
+            sorted_keys(&credential.fields).join(", ")
+        );
+
+        Ok(AuthResult {
+            label: label.to_string(),
+            display_name: label.to_string(),
+            provider_id: String::new(),
+            user_id: String::new(),
+            credential,
+            claims: None,
+        })
+    }
+
+    fn refresh(&self, store: &CredentialStore, label: &str) -> Result<StoredCredential> {
+        let config = store.command_config("exec", label)?;
+        run_command(&config)
+    }
+
+    fn validate(
+        &self,
+        _store: &CredentialStore,
+        _label: &str,
+        credential: &StoredCredential,
+    ) -> Result<bool> {
+        Ok(!self.needs_refresh(credential))
+    }
+
+    fn needs_refresh(&self, credential: &StoredCredential) -> bool {
+        match credential
+            .fields
+            .get("expires_at")
+            .and_then(|v| v.parse::<u64>().ok())
+        {
+            Some(expires_at) => CredentialStore::now() + EXPIRY_SKEW_SECS >= expires_at,
+            None => {
+                let age = CredentialStore::now().saturating_sub(credential.created_at);
+                age > self.max_credential_age_days() * 24 * 60 * 60
+            }
+        }
+    }
+}
+
+fn run_command(config: &CommandConfig) -> Result<StoredCredential> {
+    let run = config.run.as_deref().context(
+        "No command configured. Set `run` under [providers.exec.accounts.<label>.command]",
+    )?;
+
+    #[cfg(not(target_os = "windows"))]
+    let mut command = {
+        let mut c = Command::new("sh");
+        c.arg("-c").arg(run);
+        c
+    };
+    #[cfg(target_os = "windows")]
+    let mut command = {
+        let mut c = Command::new("cmd");
+        c.arg("/C").arg(run);
+        c
+    };
+
+    // stderr stays attached so interactive CLIs (e.g. `aws sso login`) can prompt
+    let output = command
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Failed to run `{}`", run))?;
+
+    if !output.status.success() {
+        anyhow::bail!("`{}` exited with {}", run, output.status);
+    }
+
+    let stdout = String::from_utf8(output.stdout).context("Command output is not UTF-8")?;
+    let mut fields = parse_output(&stdout, config)?;
+
+    let now = CredentialStore::now();
+    let expires_at = match &config.expiry_field {
+        Some(name) => {
+            let raw = fields
+                .get(name)
+                .with_context(|| format!("Expiry field '{}' not in command output", name))?;
+            Some(parse_timestamp(raw, now)?)
+        }
+        None => config.ttl_secs.map(|ttl| now + ttl),
+    };
+    if let Some(ts) = expires_at {
+        fields.insert("expires_at".to_string(), ts.to_string());
+    }
+
+    Ok(StoredCredential {
+        fields,
+        created_at: now,
+        last_validated: Some(now),
+    })
+}
+
+fn parse_output(stdout: &str, config: &CommandConfig) -> Result<HashMap<String, String>> {
+    let mut fields = HashMap::new();
+
+    match config.format.unwrap_or_default() {
+        CommandOutputFormat::Value => {
+            let value = stdout.trim();
+            if value.is_empty() {
+                anyhow::bail!("Command produced no output");
+            }
+            let name = config.field.as_deref().unwrap_or("token");
+            fields.insert(name.to_string(), value.to_string());
+        }
+        CommandOutputFormat::Json => {
+            let json: serde_json::Value =
+                serde_json::from_str(stdout).context("Command output is not valid JSON")?;
+            let target = match &config.json_pointer {
+                Some(ptr) => json
+                    .pointer(ptr)
+                    .with_context(|| format!("JSON pointer '{}' not found in output", ptr))?,
+                None => &json,
+            };
+            let obj = target
+                .as_object()
+                .context("Command JSON output is not an object")?;
+            for (key, value) in obj {
+                let s = match value {
+                    serde_json::Value::String(s) => s.clone(),
+                    serde_json::Value::Null => continue,
+                    other => other.to_string(),
+                };
+                fields.insert(key.clone(), s);
+            }
+        }
+        CommandOutputFormat::Env => {
+            for line in stdout.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let line = line.strip_prefix("export ").unwrap_or(line);
+                let (key, value) = line
+                    .split_once('=')
+                    .with_context(|| format!("Expected KEY=VALUE, got '{}'", line))?;
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+                    .unwrap_or(value);
+                fields.insert(key.trim().to_string(), value.to_string());
+            }
+        }
+    }
+
+    if fields.is_empty() {
+        anyhow::bail!("Command output contained no fields");
+    }
+    Ok(fields)
+}
+
+/// Values below this are lifetimes (`expires_in` style), not unix times.
+const MIN_UNIX_TIMESTAMP: u64 = 1_000_000_000;
+
+/// Unix seconds, an RFC 3339 timestamp such as AWS's "Expiration", or a
+/// lifetime relative to `now`: a small number of seconds ("3600") or a
+/// duration with a unit ("90s", "45m", "12h", "7d").
+fn parse_timestamp(raw: &str, now: u64) -> Result<u64> {
+    let raw = raw.trim();
+    if let Ok(n) = raw.parse::<u64>() {
+        return Ok(if n < MIN_UNIX_TIMESTAMP { now + n } else { n });
+    }
+    if let Some(secs) = parse_duration(raw) {
+        return Ok(now + secs);
+    }
+    let dt = chrono::DateTime::parse_from_rfc3339(raw).with_context(|| {
+        format!(
+            "Unrecognised expiry '{}' (want unix seconds, RFC 3339 or a duration like 1h)",
+            raw
+        )
+    })?;
+    Ok(dt.timestamp().max(0) as u64)
+}
+
+fn parse_duration(raw: &str) -> Option<u64> {
+    let split = raw.len().checked_sub(1)?;
+    let (n, unit) = raw.split_at(split);
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    n.parse::<u64>().ok()?.checked_mul(scale)
+}
+
+fn sorted_keys(fields: &HashMap<String, String>) -> Vec<&str> {
+    let mut keys: Vec<&str> = fields.keys().map(|s| s.as_str()).collect();
+    keys.sort();
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(format: CommandOutputFormat) -> CommandConfig {
+        CommandConfig {
+            format: Some(format),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn value_output_is_trimmed_into_one_field() {
+        let mut cfg = config(CommandOutputFormat::Value);
+        let fields = parse_output("ya29.abc\n", &cfg).unwrap();
+        assert_eq!(sorted_keys(&fields), ["token"]);
+        assert_eq!(fields["token"], "ya29.abc");
+
+        cfg.field = Some("access_token".into());
+        let fields = parse_output("ya29.abc", &cfg).unwrap();
+        assert_eq!(fields["access_token"], "ya29.abc");
+
+        assert!(parse_output(" \n", &cfg).is_err());
+    }
+
+    #[test]
+    fn json_output_copies_top_level_keys() {
+        let out = r#"{"Version": 1, "AccessKeyId": "AKIA", "SessionToken": null,
+            "Expiration": "2026-01-01T00:00:00Z", "Nested": {"a": true}}"#;
+        let fields = parse_output(out, &config(CommandOutputFormat::Json)).unwrap();
+        assert_eq!(
+            sorted_keys(&fields),
+            ["AccessKeyId", "Expiration", "Nested", "Version"]
+        );
+        assert_eq!(fields["AccessKeyId"], "AKIA");
+        assert_eq!(fields["Version"], "1");
+        assert_eq!(fields["Nested"], r#"{"a":true}"#);
+    }
+
+    #[test]
+    fn json_pointer_selects_a_nested_object() {
+        let out = r#"{"Credentials": {"AccessKeyId": "AKIA"}, "Other": "x"}"#;
+        let mut cfg = config(CommandOutputFormat::Json);
+        cfg.json_pointer = Some("/Credentials".into());
+        let fields = parse_output(out, &cfg).unwrap();
+        assert_eq!(sorted_keys(&fields), ["AccessKeyId"]);
+
+        cfg.json_pointer = Some("/Missing".into());
+        let err = parse_output(out, &cfg).unwrap_err().to_string();
+        assert!(err.contains("'/Missing' not found"), "{}", err);
+    }
+
+    #[test]
+    fn json_output_must_be_a_non_empty_object() {
+        let cfg = config(CommandOutputFormat::Json);
+        for out in ["not json", "[1, 2]", r#""token""#, "{}", r#"{"a": null}"#] {
+            assert!(parse_output(out, &cfg).is_err(), "{}", out);
+        }
+    }
+
+    #[test]
+    fn env_output_parses_key_value_lines() {
+        let out = "# generated\n\nexport AWS_ACCESS_KEY_ID=AKIA\n\
+                   AWS_SECRET_ACCESS_KEY=\"se=cret\"\n  REGION = 'eu-west-1'\nEMPTY=\n";
+        let fields = parse_output(out, &config(CommandOutputFormat::Env)).unwrap();
+        assert_eq!(
+            sorted_keys(&fields),
+            [
+                "AWS_ACCESS_KEY_ID",
+                "AWS_SECRET_ACCESS_KEY",
+                "EMPTY",
+                "REGION"
+            ]
+        );
+        assert_eq!(fields["AWS_ACCESS_KEY_ID"], "AKIA");
+        assert_eq!(fields["AWS_SECRET_ACCESS_KEY"], "se=cret");
+        assert_eq!(fields["REGION"], "eu-west-1");
+        assert_eq!(fields["EMPTY"], "");
+    }
+
+    #[test]
+    fn env_output_rejects_bad_lines_and_empty_output() {
+        let cfg = config(CommandOutputFormat::Env);
+        let err = parse_output("TOKEN=abc\nnot a pair\n", &cfg)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Expected KEY=VALUE"), "{}", err);
+        let err = parse_output("# nothing here\n", &cfg)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("no fields"), "{}", err);
+    }
+
+    #[test]
+    fn expiry_accepts_epoch_rfc3339_and_relative() {
+        let now = 1_700_000_000;
+        assert_eq!(parse_timestamp("1767225600", now).unwrap(), 1_767_225_600);
+        assert_eq!(
+            parse_timestamp("2026-01-01T00:00:00Z", now).unwrap(),
+            1_767_225_600
+        );
+        assert_eq!(
+            parse_timestamp("2026-01-01T01:00:00+01:00", now).unwrap(),
+            1_767_225_600
+        );
+        assert_eq!(parse_timestamp(" 3600 ", now).unwrap(), now + 3600);
+        assert_eq!(parse_timestamp("90s", now).unwrap(), now + 90);
+        assert_eq!(parse_timestamp("45m", now).unwrap(), now + 45 * 60);
+        assert_eq!(parse_timestamp("12h", now).unwrap(), now + 12 * 3600);
+        assert_eq!(parse_timestamp("7d", now).unwrap(), now + 7 * 86400);
+        for raw in ["", "soon", "1w", "h", "-5", "2026-01-01"] {
+            assert!(parse_timestamp(raw, now).is_err(), "{:?}", raw);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_command_records_expiry() {
+        let mut cfg = config(CommandOutputFormat::Json);
+        cfg.run = Some(r#"echo '{"token": "t", "expires_in": 3600}'"#.into());
+        cfg.expiry_field = Some("expires_in".into());
+        let cred = run_command(&cfg).unwrap();
+        let expires_at: u64 = cred.fields["expires_at"].parse().unwrap();
+        assert_eq!(expires_at, cred.created_at + 3600);
+
+        cfg.expiry_field = Some("Expiration".into());
+        let err = run_command(&cfg).unwrap_err().to_string();
+        assert!(
+            err.contains("'Expiration' not in command output"),
+            "{}",
+            err
+        );
+
+        cfg.expiry_field = None;
+        cfg.ttl_secs = Some(300);
+        let cred = run_command(&cfg).unwrap();
+        assert_eq!(
+            cred.fields["expires_at"],
+            (cred.created_at + 300).to_string()
+        );
+
+        cfg.run = Some("exit 3".into());
+        assert!(run_command(&cfg).is_err());
+        cfg.run = None;
+        assert!(run_command(&cfg).is_err());
+    }
+}
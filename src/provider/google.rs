@@ -1,9 +1,14 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 
-use crate::auth::google_oauth::{self, ALL_SCOPES};
-use crate::storage::{AuthResult, CredentialStore, StoredCredential};
+use crate::auth::adc::AuthorizedUser;
+use crate::auth::google_oauth::{self, AuthorizeOptions, OAuthClient, ALL_SCOPES};
+use crate::storage::{
+    Account, AuthResult, CredentialStore, IdentityClaims, Layered, OAuthClientConfig, ScopedToken,
+    StoredCredential,
+};
 
 use super::Provider;
 
@@ -19,7 +24,14 @@ impl Provider for GoogleProvider {
     }
 
     fn credential_fields(&self) -> &[&str] {
-        &["access_token", "refresh_token", "email", "scopes", "expires_at"]
+        &[
+            "access_token",
+            "refresh_token",
+            "email",
+            "scopes",
+            "expires_at",
+            "id_token",
+        ]
     }
 
     fn max_credential_age_days(&self) -> u64 {
@@ -29,37 +41,9 @@ impl Provider for GoogleProvider {
         7
     }
 
-    fn authenticate(&self, _store: &CredentialStore, label: &str) -> Result<AuthResult> {
-        eprintln!("Starting Google authentication...");
-        eprintln!("Requesting access to: Gmail, Calendar, Contacts, Tasks, Drive, YouTube, Photos");
-        eprintln!();
-
-        let creds = google_oauth::authenticate(ALL_SCOPES)?;
-
-        eprintln!();
-        eprintln!("Successfully authenticated as: {}", creds.email);
-
-        let mut fields = HashMap::new();
-        fields.insert("access_token".to_string(), creds.access_token);
-        fields.insert("refresh_token".to_string(), creds.refresh_token);
-        fields.insert("email".to_string(), creds.email.clone());
-        fields.insert("scopes".to_string(), creds.scopes.join(" "));
-        fields.insert("expires_at".to_string(), creds.expires_at.to_string());
-
-        Ok(AuthResult {
-            label: label.to_string(),
-            credential: StoredCredential {
-                fields,
-                created_at: std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs(),
-                last_validated: None,
-            },
-            display_name: creds.email.clone(),
-            provider_id: creds.email, // Use email as provider_id for Google
-            user_id: String::new(),
-        })
+    /// Asks for the scopes the account was added with, or all of them.
+    fn authenticate(&self, store: &CredentialStore, label: &str) -> Result<AuthResult> {
+        self.authenticate_with_scopes(store, label, &account_scopes(store, label))
     }
 
     fn refresh(&self, store: &CredentialStore, label: &str) -> Result<StoredCredential> {
@@ -70,13 +54,31 @@ impl Provider for GoogleProvider {
             .get("refresh_token")
             .ok_or_else(|| anyhow::anyhow!("No refresh token found"))?;
 
+        let client = client_for_credential(store, label, &existing)?;
+
         eprintln!("Refreshing Google access token...");
 
-        let (new_access_token, new_expires_at) = google_oauth::refresh_token(refresh_token)?;
+        let refreshed = google_oauth::refresh_token(&client, refresh_token, None)?;
 
         let mut fields = existing.fields.clone();
-        fields.insert("access_token".to_string(), new_access_token);
-        fields.insert("expires_at".to_string(), new_expires_at.to_string());
+        fields.insert("access_token".to_string(), refreshed.access_token);
+        fields.insert("expires_at".to_string(), refreshed.expires_at.to_string());
+        if let Some(id_token) = refreshed.id_token {
+            // The identity was verified at sign-in; a JWKS outage must not
+            // fail the refresh, only cost the unverifiable id_token
+            match google_oauth::verify_id_token(&client, &id_token, &jwks_cache(store)) {
+                Ok(_) => {
+                    fields.insert("id_token".to_string(), id_token);
+                }
+                Err(e) => {
+                    eprintln!("Warning: dropping id_token for google/{}: {:#}", label, e);
+                    fields.remove("id_token");
+                }
+            }
+        } else {
+            // The old one expires with the old access token; never hand it out
+            fields.remove("id_token");
+        }
 
         eprintln!("Token refreshed successfully");
 
@@ -92,7 +94,12 @@ impl Provider for GoogleProvider {
         })
     }
 
-    fn validate(&self, credential: &StoredCredential) -> Result<bool> {
+    fn validate(
+        &self,
+        _store: &CredentialStore,
+        _label: &str,
+        credential: &StoredCredential,
+    ) -> Result<bool> {
         let access_token = credential
             .fields
             .get("access_token")
@@ -119,6 +126,485 @@ impl Provider for GoogleProvider {
 
         Ok(response.status().is_success())
     }
+
+    fn needs_refresh(&self, credential: &StoredCredential) -> bool {
+        needs_refresh(credential)
+    }
+
+    fn sasl_login(&self, credential: &StoredCredential) -> Option<(String, String)> {
+        let email = credential.fields.get("email")?;
+        let token = credential.fields.get("access_token")?;
+        Some((email.clone(), token.clone()))
+    }
+
+    fn http_hosts(&self) -> &[&str] {
+        &["*.googleapis.com"]
+    }
+
+    fn same_identity(&self, existing: &Account, result: &AuthResult) -> Option<bool> {
+        // `sub` survives email address changes; older accounts only have the email
+        match (&existing.claims, &result.claims) {
+            (Some(old), Some(new)) => Some(old.sub == new.sub),
+            _ => super::same_ids(existing, result),
+        }
+    }
+
+    fn revoke(&self, store: &CredentialStore, label: &str) -> Result<bool> {
+        let existing = store.get_credential("google", label)?;
+        let client = client_for_credential(store, label, &existing)?;
+
+        // Revoking the refresh token also invalidates every access token
+        // minted from it, including down-scoped ones.
+        let token = existing
+            .fields
+            .get("refresh_token")
+            .or_else(|| existing.fields.get("access_token"))
+            .context("No token to revoke")?;
+
+        // Google answers 400 invalid_token for tokens that are already dead
+        let revoked = google_oauth::revoke_token(&client, token)?;
+        if !revoked {
+            eprintln!("Token for google/{} was already invalid upstream.", label);
+        }
+        Ok(revoked)
+    }
+}
+
+const ENV_CLIENT_ID: &str = "GOOGLE_CLIENT_ID";
+const ENV_CLIENT_SECRET: &str = "GOOGLE_CLIENT_SECRET";
+const ENV_CLIENT_SECRET_FILE: &str = "GOOGLE_CLIENT_SECRET_FILE";
+
+/// OAuth client settings from the environment, if any.
+fn env_client_config() -> OAuthClientConfig {
+    OAuthClientConfig {
+        client_id: std::env::var(ENV_CLIENT_ID).ok(),
+        client_secret: std::env::var(ENV_CLIENT_SECRET).ok(),
+        client_secret_file: std::env::var_os(ENV_CLIENT_SECRET_FILE).map(Into::into),
+        ..Default::default()
+    }
+}
+
+/// Build a client from config, falling back to the built-in Thunderbird
+/// client when no client id is set (redirect/token overrides still apply).
+fn build_client(config: &OAuthClientConfig) -> Result<OAuthClient> {
+    let mut client = if let Some(path) = &config.client_secret_file {
+        OAuthClient::from_secret_file(path)?
+    } else if let Some(id) = &config.client_id {
+        let secret = config
+            .client_secret
+            .as_deref()
+            .context(format!("OAuth client '{}' has no client_secret", id))?;
+        OAuthClient::new(id, secret)
+    } else {
+        OAuthClient::thunderbird()
+    };
+
+    if let Some(uri) = &config.redirect_uri {
+        client.redirect_uri = uri.clone();
+    }
+    if let Some(uri) = &config.token_uri {
+        client.token_url = uri.clone();
+    }
+    if let Some(uri) = &config.revoke_uri {
+        client.revoke_url = uri.clone();
+    }
+    if let Some(uri) = &config.jwks_uri {
+        client.jwks_url = uri.clone();
+    }
+    Ok(client)
+}
+
+/// Cached copy of Google's id_token signing keys.
+fn jwks_cache(store: &CredentialStore) -> PathBuf {
+    store.cache_path("google-jwks.json")
+}
+
+/// Client configs for an account, most specific first: account section,
+/// environment, provider section.
+fn client_configs(store: &CredentialStore, label: &str) -> Result<Vec<OAuthClientConfig>> {
+    let config = store.load_config()?;
+    let provider_config = config.providers.get("google");
+    let provider_level = provider_config
+        .and_then(|p| p.oauth_client.clone())
+        .unwrap_or_default();
+    let account_level = provider_config
+        .and_then(|p| p.accounts.get(label))
+        .and_then(|a| a.oauth_client.clone())
+        .unwrap_or_default();
+
+    Ok(vec![account_level, env_client_config(), provider_level])
+}
+
+/// The client new tokens for this account should be issued to.
+pub fn resolve_client(store: &CredentialStore, label: &str) -> Result<OAuthClient> {
+    let configs = client_configs(store, label)?;
+    let effective = configs
+        .iter()
+        .rev()
+        .fold(OAuthClientConfig::default(), |acc, c| acc.merge(c));
+    build_client(&effective)
+}
+
+/// The client that issued this credential's refresh token. Google rejects a
+/// refresh token presented by any other client, so this never falls back.
+pub fn client_for_credential(
+    store: &CredentialStore,
+    label: &str,
+    credential: &StoredCredential,
+) -> Result<OAuthClient> {
+    let preferred = resolve_client(store, label)?;
+    let issued_to = match credential.fields.get("client_id") {
+        Some(id) => id,
+        // Tokens from before client ids were recorded came from Thunderbird
+        None => return Ok(OAuthClient::thunderbird()),
+    };
+    if &preferred.client_id == issued_to {
+        return Ok(preferred);
+    }
+
+    for config in client_configs(store, label)?
+        .iter()
+        .filter(|c| c.has_client())
+    {
+        let client = build_client(config)?;
+        if &client.client_id == issued_to {
+            return Ok(client);
+        }
+    }
+    let builtin = OAuthClient::thunderbird();
+    if &builtin.client_id == issued_to {
+        return Ok(builtin);
+    }
+
+    anyhow::bail!(
+        "Refresh token for google/{} was issued to OAuth client {}, which is no longer configured. \
+         Restore that client or run `tokey add google --label {}` to re-authorize.",
+        label,
+        issued_to,
+        label
+    )
+}
+
+impl GoogleProvider {
+    /// Run the consent flow for a specific set of scopes (aliases or URLs).
+    pub fn authenticate_with_scopes(
+        &self,
+        store: &CredentialStore,
+        label: &str,
+        scopes: &[String],
+    ) -> Result<AuthResult> {
+        let client = resolve_client(store, label)?;
+        let scopes: Vec<String> = scopes
+            .iter()
+            .map(|s| google_oauth::resolve_scope(s))
+            .collect();
+
+        eprintln!("Starting Google authentication...");
+        eprintln!("OAuth client: {}", client.client_id);
+        eprintln!("Requesting access to: {}", describe_scopes(&scopes));
+        eprintln!();
+
+        let creds = google_oauth::authenticate(
+            &client,
+            &scopes,
+            &AuthorizeOptions::default(),
+            &jwks_cache(store),
+        )?;
+
+        eprintln!();
+        eprintln!("Successfully authenticated as: {}", creds.email);
+        eprintln!("Granted: {}", describe_scopes(&creds.scopes));
+
+        let mut fields = HashMap::new();
+        fields.insert("access_token".to_string(), creds.access_token);
+        fields.insert("refresh_token".to_string(), creds.refresh_token);
+        fields.insert("email".to_string(), creds.email.clone());
+        fields.insert("scopes".to_string(), creds.scopes.join(" "));
+        fields.insert("expires_at".to_string(), creds.expires_at.to_string());
+        fields.insert("client_id".to_string(), client.client_id.clone());
+        if let Some(id_token) = creds.id_token {
+            fields.insert("id_token".to_string(), id_token);
+        }
+
+        Ok(AuthResult {
+            label: label.to_string(),
+            credential: StoredCredential {
+                fields,
+                created_at: std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+                last_validated: None,
+            },
+            display_name: creds.email.clone(),
+            provider_id: creds.email, // Use email as provider_id for Google
+            user_id: creds
+                .claims
+                .as_ref()
+                .map(|c| c.sub.clone())
+                .unwrap_or_default(),
+            claims: creds.claims,
+        })
+    }
+}
+
+/// Incrementally authorize more scopes for an existing account. Google
+/// merges them with what was granted before (`include_granted_scopes`).
+pub fn add_scopes(
+    store: &CredentialStore,
+    label: &str,
+    scopes: &[String],
+) -> Result<StoredCredential> {
+    let existing = store.get_credential("google", label)?;
+    let client = client_for_credential(store, label, &existing)?;
+    let scopes: Vec<String> = scopes
+        .iter()
+        .map(|s| google_oauth::resolve_scope(s))
+        .collect();
+
+    eprintln!(
+        "Requesting additional access to: {}",
+        describe_scopes(&scopes)
+    );
+
+    let options = AuthorizeOptions {
+        include_granted_scopes: true,
+        login_hint: existing.fields.get("email").cloned(),
+    };
+    let creds = google_oauth::authenticate(&client, &scopes, &options, &jwks_cache(store))?;
+
+    let account = store.get_account("google", label)?;
+    let same_user = match (&account.claims, &creds.claims) {
+        (Some(old), Some(new)) => old.sub == new.sub,
+        _ => existing
+            .fields
+            .get("email")
+            .is_none_or(|email| email == &creds.email),
+    };
+    if !same_user {
+        anyhow::bail!(
+            "Authorized as {} but google/{} belongs to {}; nothing was changed",
+            creds.email,
+            label,
+            account.display_name
+        );
+    }
+
+    eprintln!("Granted: {}", describe_scopes(&creds.scopes));
+
+    let mut fields = existing.fields.clone();
+    fields.insert("access_token".to_string(), creds.access_token);
+    fields.insert("refresh_token".to_string(), creds.refresh_token);
+    fields.insert("scopes".to_string(), creds.scopes.join(" "));
+    fields.insert("expires_at".to_string(), creds.expires_at.to_string());
+    if let Some(id_token) = creds.id_token {
+        fields.insert("id_token".to_string(), id_token);
+    }
+
+    Ok(StoredCredential {
+        fields,
+        created_at: existing.created_at,
+        last_validated: Some(CredentialStore::now()),
+    })
+}
+
+/// The scopes recorded on the account, or every supported scope for
+/// accounts that never chose (including ones not added yet).
+pub fn account_scopes(store: &CredentialStore, label: &str) -> Vec<String> {
+    match store.get_account("google", label) {
+        Ok(account) if !account.scopes.is_empty() => account.scopes,
+        _ => ALL_SCOPES.iter().map(|s| s.to_string()).collect(),
+    }
+}
+
+/// A credential view holding an access token limited to `scopes`, minted
+/// from the stored refresh token and cached per scope set. The refresh token
+/// itself is deliberately left out.
+pub fn scoped_credential(
+    store: &CredentialStore,
+    label: &str,
+    scopes: &[String],
+) -> Result<StoredCredential> {
+    let existing = store.get_credential("google", label)?;
+    let granted: Vec<&str> = existing
+        .fields
+        .get("scopes")
+        .map(|s| s.split_whitespace().collect())
+        .unwrap_or_default();
+
+    let mut wanted: Vec<String> = scopes
+        .iter()
+        .map(|s| google_oauth::resolve_scope(s))
+        .collect();
+    wanted.sort();
+    wanted.dedup();
+
+    for scope in &wanted {
+        // A ".readonly" variant is covered by its full-access scope
+        let covered = granted.contains(&scope.as_str())
+            || scope
+                .strip_suffix(".readonly")
+                .is_some_and(|base| granted.contains(&base));
+        if !covered {
+            anyhow::bail!(
+                "Scope '{}' was not granted to google/{} (granted: {}). Use `tokey scopes add` first.",
+                google_oauth::scope_alias(scope),
+                label,
+                describe_scopes(&granted.iter().map(|s| s.to_string()).collect::<Vec<_>>())
+            );
+        }
+    }
+
+    let key = wanted.join(" ");
+    let now = CredentialStore::now();
+    let token = match store.get_scoped_token("google", label, &key)? {
+        Some(t) if now + 300 < t.expires_at => t,
+        _ => {
+            let refresh_token = existing
+                .fields
+                .get("refresh_token")
+                .context("No refresh token found")?;
+            let client = client_for_credential(store, label, &existing)?;
+
+            eprintln!("Minting access token for: {}", describe_scopes(&wanted));
+            let refreshed = google_oauth::refresh_token(&client, refresh_token, Some(&wanted))?;
+
+            let token = ScopedToken {
+                access_token: refreshed.access_token,
+                expires_at: refreshed.expires_at,
+            };
+            store.set_scoped_token("google", label, &key, token.clone())?;
+            token
+        }
+    };
+
+    let mut fields = HashMap::new();
+    fields.insert("access_token".to_string(), token.access_token);
+    fields.insert("expires_at".to_string(), token.expires_at.to_string());
+    fields.insert("scopes".to_string(), key);
+    if let Some(email) = existing.fields.get("email") {
+        fields.insert("email".to_string(), email.clone());
+    }
+
+    Ok(StoredCredential {
+        fields,
+        created_at: existing.created_at,
+        last_validated: existing.last_validated,
+    })
+}
+
+/// The account as an `authorized_user` ADC file: its refresh token plus the
+/// OAuth client that issued it (Google rejects the token with any other).
+pub fn authorized_user(store: &CredentialStore, label: &str) -> Result<AuthorizedUser> {
+    let credential = store.get_credential("google", label)?;
+    let refresh_token = credential
+        .fields
+        .get("refresh_token")
+        .context("No refresh token found")?;
+    let client = client_for_credential(store, label, &credential)?;
+    Ok(AuthorizedUser::new(
+        &client.client_id,
+        &client.client_secret,
+        refresh_token,
+    ))
+}
+
+/// Rewrite the account's kept-updated ADC files that no longer match it.
+pub fn sync_adc_exports(store: &CredentialStore, label: &str) -> Result<()> {
+    let account = store.get_account("google", label)?;
+    if account.adc_exports.is_empty() {
+        return Ok(());
+    }
+
+    let current = authorized_user(store, label)?;
+    for path in &account.adc_exports {
+        let up_to_date = AuthorizedUser::read(path).is_ok_and(|existing| existing == current);
+        if !up_to_date {
+            current.write(path)?;
+            eprintln!("Updated {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Turn an `authorized_user` ADC file (e.g. a gcloud login) into a google
+/// account. Returns the OAuth client config the account must keep, since the
+/// refresh token only works with the client it was issued to.
+pub fn import_adc(
+    store: &CredentialStore,
+    label: &str,
+    adc: &AuthorizedUser,
+) -> Result<(AuthResult, OAuthClientConfig)> {
+    let client_config = OAuthClientConfig {
+        client_id: Some(adc.client_id.clone()),
+        client_secret: Some(adc.client_secret.clone()),
+        ..Default::default()
+    };
+    // Endpoint overrides from the provider section still apply
+    let provider_level = store
+        .load_config()?
+        .providers
+        .get("google")
+        .and_then(|p| p.oauth_client.clone())
+        .unwrap_or_default();
+    let client = build_client(&provider_level.merge(&client_config))?;
+
+    eprintln!("Refreshing with OAuth client {}...", client.client_id);
+    let refreshed = google_oauth::refresh_token(&client, &adc.refresh_token, None)?;
+    // gcloud logins include `openid`, so the refresh usually returns an id_token
+    let claims: Option<IdentityClaims> = match &refreshed.id_token {
+        Some(token) => Some(google_oauth::verify_id_token(
+            &client,
+            token,
+            &jwks_cache(store),
+        )?),
+        None => None,
+    };
+    let email = match claims.as_ref().and_then(|c| c.email.clone()) {
+        Some(email) => email,
+        None => google_oauth::get_user_email(&refreshed.access_token).context(
+            "Could not look up the account's email (the login needs the userinfo.email scope)",
+        )?,
+    };
+    let scopes = refreshed.scopes.unwrap_or_default();
+
+    eprintln!("Imported: {}", email);
+    eprintln!("Granted: {}", describe_scopes(&scopes));
+
+    let mut fields = HashMap::new();
+    fields.insert("access_token".to_string(), refreshed.access_token);
+    fields.insert("refresh_token".to_string(), adc.refresh_token.clone());
+    fields.insert("email".to_string(), email.clone());
+    fields.insert("scopes".to_string(), scopes.join(" "));
+    fields.insert("expires_at".to_string(), refreshed.expires_at.to_string());
+    fields.insert("client_id".to_string(), client.client_id.clone());
+    if let Some(id_token) = refreshed.id_token {
+        fields.insert("id_token".to_string(), id_token);
+    }
+
+    let now = CredentialStore::now();
+    let result = AuthResult {
+        label: label.to_string(),
+        credential: StoredCredential {
+            fields,
+            created_at: now,
+            last_validated: Some(now),
+        },
+        display_name: email.clone(),
+        provider_id: email,
+        user_id: claims.as_ref().map(|c| c.sub.clone()).unwrap_or_default(),
+        claims,
+    };
+    Ok((result, client_config))
+}
+
+/// Comma-separated short names for a list of scope URLs.
+pub fn describe_scopes(scopes: &[String]) -> String {
+    scopes
+        .iter()
+        .map(|s| google_oauth::scope_alias(s))
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 /// Check if credentials need refresh (access token expired or expiring soon)
@@ -136,3 +622,72 @@ pub fn needs_refresh(credential: &StoredCredential) -> bool {
         true // No expires_at, assume needs refresh
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{mock_http, temp_store};
+
+    /// An account whose client talks to `token_uri` and `jwks_uri`.
+    fn account(store: &CredentialStore, token_uri: String, jwks_uri: String) {
+        let account = Account {
+            oauth_client: Some(OAuthClientConfig {
+                client_id: Some("cid".to_string()),
+                client_secret: Some("secret".to_string()),
+                token_uri: Some(token_uri),
+                jwks_uri: Some(jwks_uri),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let fields = [
+            ("refresh_token", "rt"),
+            ("client_id", "cid"),
+            ("access_token", "old"),
+            ("id_token", "old.id.token"),
+        ];
+        let credential = StoredCredential {
+            fields: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            created_at: CredentialStore::now(),
+            last_validated: None,
+        };
+        store
+            .save_account("google", "work", account, credential)
+            .unwrap();
+    }
+
+    #[test]
+    fn refresh_survives_unverifiable_id_token() {
+        let store = temp_store();
+        let token_uri = mock_http(vec![(
+            200,
+            r#"{"access_token": "new", "expires_in": 3600, "id_token": "a.b.c"}"#.to_string(),
+        )]);
+        // Signing keys can't be fetched
+        let jwks_uri = mock_http(vec![(503, String::new())]);
+        account(&store, token_uri, jwks_uri);
+
+        let refreshed = GoogleProvider.refresh(&store, "work").unwrap();
+        assert_eq!(refreshed.fields["access_token"], "new");
+        assert_eq!(refreshed.fields["refresh_token"], "rt");
+        assert!(!refreshed.fields.contains_key("id_token"));
+    }
+
+    #[test]
+    fn refresh_without_id_token_drops_the_old_one() {
+        let store = temp_store();
+        let token_uri = mock_http(vec![(
+            200,
+            r#"{"access_token": "new", "expires_in": 3600}"#.to_string(),
+        )]);
+        account(&store, token_uri, String::new());
+
+        let refreshed = GoogleProvider.refresh(&store, "work").unwrap();
+        assert_eq!(refreshed.fields["access_token"], "new");
+        assert!(!refreshed.fields.contains_key("id_token"));
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use crate::auth::google_oauth;
+use crate::auth::prompt;
+use crate::auth::service_account::{self, ServiceAccountKey, DEFAULT_TOKEN_URL};
+use crate::storage::{AuthResult, CredentialStore, ServiceAccountConfig, StoredCredential};
+
+use super::google;
+use super::Provider;
+
+const DEFAULT_SCOPE: &str = "cloud-platform";
+
+/// GCP service accounts, optionally impersonating a Workspace user through
+/// domain-wide delegation. Tokens are minted with a signed JWT assertion,
+/// so refresh needs no browser and no refresh token.
+pub struct ServiceAccountProvider;
+
+impl Provider for ServiceAccountProvider {
+    fn name(&self) -> &str {
+        "google-sa"
+    }
+
+    fn display_name(&self) -> &str {
+        "Google service account"
+    }
+
+    fn credential_fields(&self) -> &[&str] {
+        &[
+            "access_token",
+            "expires_at",
+            "client_email",
+            "scopes",
+            "subject",
+        ]
+    }
+
+    fn max_credential_age_days(&self) -> u64 {
+        // Access tokens are re-minted from expires_at; the key has no age limit
+        3650
+    }
+
+    fn authenticate(&self, store: &CredentialStore, label: &str) -> Result<AuthResult> {
+        let config = store.service_account_config("google-sa", label)?;
+        let key_file = match &config.key_file {
+            Some(path) => path.clone(),
+            None => PathBuf::from(prompt::read_line("Path to service-account JSON key: ")?),
+        };
+
+        let key = ServiceAccountKey::from_file(&key_file)?;
+        eprintln!("Importing service account {}", key.client_email);
+
+        let mut fields = HashMap::new();
+        fields.insert("client_email".to_string(), key.client_email.clone());
+        fields.insert("private_key".to_string(), key.private_key.clone());
+        if let Some(id) = &key.private_key_id {
+            fields.insert("private_key_id".to_string(), id.clone());
+        }
+        if let Some(project) = &key.project_id {
+            fields.insert("project_id".to_string(), project.clone());
+        }
+        if let Some(uri) = &key.token_uri {
+            fields.insert("token_uri".to_string(), uri.clone());
+        }
+
+        let mut credential = StoredCredential {
+            fields,
+            created_at: CredentialStore::now(),
+            last_validated: None,
+        };
+        mint_token(&mut credential, &config)?;
+
+        let subject = credential.fields.get("subject").cloned();
+        let display_name = match &subject {
+            Some(sub) => format!("{} as {}", key.client_email, sub),
+            None => key.client_email.clone(),
+        };
+        eprintln!(
+            "Token minted for: {}",
+            google::describe_scopes(&scope_list(&credential))
+        );
+
+        Ok(AuthResult {
+            label: label.to_string(),
+            display_name,
+            provider_id: key.project_id.unwrap_or_default(),
+            user_id: key.client_email,
+            credential,
+            claims: None,
+        })
+    }
+
+    fn refresh(&self, store: &CredentialStore, label: &str) -> Result<StoredCredential> {
+        let mut credential = store.get_credential("google-sa", label)?;
+        let config = store.service_account_config("google-sa", label)?;
+        mint_token(&mut credential, &config)?;
+        Ok(credential)
+    }
+
+    fn validate(
+        &self,
+        _store: &CredentialStore,
+        _label: &str,
+        credential: &StoredCredential,
+    ) -> Result<bool> {
+        Ok(credential.fields.contains_key("access_token") && !google::needs_refresh(credential))
+    }
+
+    fn needs_refresh(&self, credential: &StoredCredential) -> bool {
+        google::needs_refresh(credential)
+    }
+
+    /// Mail logins act as the impersonated user, if any.
+    fn sasl_login(&self, credential: &StoredCredential) -> Option<(String, String)> {
+        let fields = &credential.fields;
+        let user = fields
+            .get("subject")
+            .or_else(|| fields.get("client_email"))?;
+        let token = fields.get("access_token")?;
+        Some((user.clone(), token.clone()))
+    }
+
+    fn http_hosts(&self) -> &[&str] {
+        &["*.googleapis.com"]
+    }
+}
+
+/// Sign an assertion and fetch a fresh access token into `credential`.
+/// Scopes and subject come from the current config, so removing one there
+/// takes effect on the next refresh; the token endpoint falls back to the
+/// key's own.
+fn mint_token(credential: &mut StoredCredential, config: &ServiceAccountConfig) -> Result<()> {
+    let fields = &credential.fields;
+    let client_email = fields
+        .get("client_email")
+        .context("Missing 'client_email' field")?;
+    let private_key = fields
+        .get("private_key")
+        .context("Missing 'private_key' field")?;
+
+    let scopes: Vec<String> = if config.scopes.is_empty() {
+        vec![google_oauth::resolve_scope(DEFAULT_SCOPE)]
+    } else {
+        config
+            .scopes
+            .iter()
+            .map(|s| google_oauth::resolve_scope(s))
+            .collect()
+    };
+    // Never the stored subject: dropping it from config must stop the
+    // impersonation
+    let subject = config.subject.clone();
+    let token_url = config
+        .token_uri
+        .clone()
+        .or_else(|| fields.get("token_uri").cloned())
+        .unwrap_or_else(|| DEFAULT_TOKEN_URL.to_string());
+
+    let assertion = service_account::signed_assertion(
+        client_email,
+        private_key,
+        fields.get("private_key_id").map(String::as_str),
+        &scopes,
+        &token_url,
+        subject.as_deref(),
+    )?;
+    let (access_token, expires_at) = service_account::fetch_token(&token_url, &assertion)?;
+
+    let now = CredentialStore::now();
+    let fields = &mut credential.fields;
+    fields.insert("access_token".to_string(), access_token);
+    fields.insert("expires_at".to_string(), expires_at.to_string());
+    fields.insert("scopes".to_string(), scopes.join(" "));
+    match subject {
+        Some(sub) => fields.insert("subject".to_string(), sub),
+        None => fields.remove("subject"),
+    };
+    credential.last_validated = Some(now);
+    Ok(())
+}
+
+fn scope_list(credential: &StoredCredential) -> Vec<String> {
+    credential
+        .fields
+        .get("scopes")
+        .map(|s| s.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc;
+    use std::thread;
+
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+    use rsa::RsaPrivateKey;
+
+    /// A token endpoint that answers every request with a token and passes
+    /// the claims of each assertion it receives back to the test.
+    fn token_endpoint() -> (String, mpsc::Receiver<serde_json::Value>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/token", server.server_addr().to_ip().unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).unwrap();
+                let assertion = url::form_urlencoded::parse(body.as_bytes())
+                    .find(|(k, _)| k == "assertion")
+                    .unwrap()
+                    .1;
+                let payload = assertion.split('.').nth(1).unwrap();
+                let claims = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap());
+                let _ = tx.send(claims.unwrap());
+                let _ = request.respond(tiny_http::Response::from_string(
+                    r#"{"access_token": "at", "expires_in": 3600}"#,
+                ));
+            }
+        });
+        (url, rx)
+    }
+
+    fn credential() -> StoredCredential {
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        let pem = key.to_pkcs8_pem(LineEnding::LF).unwrap();
+        let fields = [
+            ("client_email", "sa@project.iam.gserviceaccount.com"),
+            ("private_key", pem.as_str()),
+        ];
+        StoredCredential {
+            fields: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            created_at: CredentialStore::now(),
+            last_validated: None,
+        }
+    }
+
+    #[test]
+    fn subject_and_scopes_follow_config() {
+        let (token_uri, assertions) = token_endpoint();
+        let mut credential = credential();
+        let mut config = ServiceAccountConfig {
+            token_uri: Some(token_uri),
+            scopes: vec!["gmail.readonly".to_string()],
+            subject: Some("alice@example.com".to_string()),
+            ..Default::default()
+        };
+
+        mint_token(&mut credential, &config).unwrap();
+        let claims = assertions.recv().unwrap();
+        assert_eq!(claims["sub"], "alice@example.com");
+        assert_eq!(
+            claims["scope"],
+            "https://www.googleapis.com/auth/gmail.readonly"
+        );
+        assert_eq!(credential.fields["subject"], "alice@example.com");
+        assert_eq!(credential.fields["access_token"], "at");
+
+        // Removing them from config takes effect on the next refresh
+        config.subject = None;
+        config.scopes.clear();
+        mint_token(&mut credential, &config).unwrap();
+        let claims = assertions.recv().unwrap();
+        assert!(claims.get("sub").is_none());
+        assert_eq!(
+            claims["scope"],
+            "https://www.googleapis.com/auth/cloud-platform"
+        );
+        assert!(!credential.fields.contains_key("subject"));
+    }
+}
@@ -1,9 +1,14 @@
+pub mod exec;
 pub mod google;
+pub mod google_sa;
 pub mod slack;
+pub mod static_key;
+pub mod totp;
 
 use anyhow::Result;
 
-use crate::storage::{AuthResult, CredentialStore, StoredCredential};
+use crate::auth::sasl::SaslMechanism;
+use crate::storage::{Account, AuthResult, CredentialStore, StoredCredential};
 
 pub trait Provider {
     fn name(&self) -> &str;
@@ -12,17 +17,160 @@ pub trait Provider {
     fn max_credential_age_days(&self) -> u64;
     fn authenticate(&self, store: &CredentialStore, label: &str) -> Result<AuthResult>;
     fn refresh(&self, store: &CredentialStore, label: &str) -> Result<StoredCredential>;
-    fn validate(&self, credential: &StoredCredential) -> Result<bool>;
+    fn validate(
+        &self,
+        store: &CredentialStore,
+        label: &str,
+        credential: &StoredCredential,
+    ) -> Result<bool>;
+
+    /// Whether `get` should refresh before returning. Defaults to the
+    /// credential age check against `max_credential_age_days`.
+    fn needs_refresh(&self, credential: &StoredCredential) -> bool {
+        let age = CredentialStore::now().saturating_sub(credential.created_at);
+        age > self.max_credential_age_days() * 24 * 60 * 60
+    }
+
+    /// Values computed from the stored fields rather than stored themselves
+    /// (e.g. a TOTP code). Returns None for unknown names.
+    fn derived_field(&self, _credential: &StoredCredential, _name: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// User name and access token for SASL XOAUTH2/OAUTHBEARER logins to
+    /// mail servers. None if the credentials can't be used that way.
+    fn sasl_login(&self, _credential: &StoredCredential) -> Option<(String, String)> {
+        None
+    }
+
+    /// Whether a fresh sign-in is the same user as an existing account:
+    /// None when there's nothing to compare (e.g. static keys).
+    fn same_identity(&self, existing: &Account, result: &AuthResult) -> Option<bool> {
+        same_ids(existing, result)
+    }
+
+    /// Invalidate the credential upstream (not the local copy). Returns
+    /// false when there is nothing remote to revoke, including when the
+    /// provider reports the credential as already invalid.
+    fn revoke(&self, _store: &CredentialStore, _label: &str) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Extra line for `status`, e.g. an upcoming expiry. None if all is well.
+    fn status_warning(&self, _store: &CredentialStore, _label: &str) -> Option<String> {
+        None
+    }
+
+    /// Headers that present the credential to the provider's HTTP APIs.
+    /// Defaults to a bearer token from `access_token` or `token`; empty if
+    /// the credential has neither.
+    fn http_headers(&self, credential: &StoredCredential) -> Vec<(String, String)> {
+        ["access_token", "token"]
+            .iter()
+            .find_map(|f| credential.fields.get(*f))
+            .map(|token| vec![("Authorization".to_string(), format!("Bearer {}", token))])
+            .unwrap_or_default()
+    }
+
+    /// Whether an HTTP response means the credential was rejected, so a
+    /// refresh and retry is worth trying. Defaults to status 401.
+    fn http_auth_failed(&self, status: u16, _body: &[u8]) -> bool {
+        status == 401
+    }
+
+    /// Hosts `tokey http` sends the credential to without `--allow-host`:
+    /// exact names or `*.domain` for any subdomain. Empty when the
+    /// credential isn't tied to a known service (static keys, commands).
+    fn http_hosts(&self) -> &[&str] {
+        &[]
+    }
+}
+
+/// Whether `host` is one of `patterns`, as returned by `http_hosts`.
+pub fn http_host_allowed(patterns: &[&str], host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    patterns
+        .iter()
+        .any(|pattern| match pattern.strip_prefix("*.") {
+            Some(domain) => host.ends_with(&format!(".{}", domain)),
+            None => host == *pattern,
+        })
+}
+
+/// A provider's own derived fields, plus the SASL strings ("xoauth2",
+/// "oauthbearer") shared by every provider with a `sasl_login`.
+pub fn derived_field(
+    prov: &dyn Provider,
+    credential: &StoredCredential,
+    name: &str,
+) -> Result<Option<String>> {
+    if let Some(value) = prov.derived_field(credential, name)? {
+        return Ok(Some(value));
+    }
+    let Ok(mechanism) = name.parse::<SaslMechanism>() else {
+        return Ok(None);
+    };
+    match prov.sasl_login(credential) {
+        Some((user, token)) => Ok(Some(mechanism.initial_response(&user, &token))),
+        None => anyhow::bail!(
+            "{} credentials can't be used for SASL {}",
+            prov.display_name(),
+            mechanism
+        ),
+    }
+}
+
+/// Compare the provider and user ids both sides know. Any mismatch means a
+/// different identity; empty ids (providers without one) are skipped.
+pub fn same_ids(existing: &Account, result: &AuthResult) -> Option<bool> {
+    let pairs = [
+        (&existing.provider_id, &result.provider_id),
+        (&existing.user_id, &result.user_id),
+    ];
+    let mut compared = pairs
+        .iter()
+        .filter(|(old, new)| !old.is_empty() && !new.is_empty())
+        .peekable();
+    compared.peek()?;
+    Some(compared.all(|(old, new)| old == new))
 }
 
 pub fn get_provider(name: &str) -> Result<Box<dyn Provider>> {
     match name {
         "slack" => Ok(Box::new(slack::SlackProvider)),
         "google" => Ok(Box::new(google::GoogleProvider)),
-        _ => anyhow::bail!("Unknown provider: '{}'. Available: slack, google", name),
+        "totp" => Ok(Box::new(totp::TotpProvider)),
+        "static" => Ok(Box::new(static_key::StaticProvider)),
+        "exec" => Ok(Box::new(exec::ExecProvider)),
+        "google-sa" => Ok(Box::new(google_sa::ServiceAccountProvider)),
+        _ => anyhow::bail!(
+            "Unknown provider: '{}'. Available: {}",
+            name,
+            all_provider_names().join(", ")
+        ),
     }
 }
 
 pub fn all_provider_names() -> &'static [&'static str] {
-    &["slack", "google"]
+    &["slack", "google", "google-sa", "totp", "static", "exec"]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_hosts_match_names_and_subdomains() {
+        let slack = slack::SlackProvider.http_hosts();
+        assert!(http_host_allowed(slack, "slack.com"));
+        assert!(http_host_allowed(slack, "Files.Slack.com"));
+        assert!(!http_host_allowed(slack, "evilslack.com"));
+        assert!(!http_host_allowed(slack, "slack.com.evil.example"));
+
+        let google = google::GoogleProvider.http_hosts();
+        assert!(http_host_allowed(google, "gmail.googleapis.com"));
+        assert!(!http_host_allowed(google, "googleapis.com"));
+        assert!(!http_host_allowed(google, "api.example.com"));
+        assert!(!http_host_allowed(&[], "slack.com"));
+    }
 }
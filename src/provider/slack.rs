@@ -3,8 +3,10 @@ use std::collections::HashMap;
 use anyhow::{Context, Result};
 
 use crate::auth::chrome_auth;
-use crate::storage::{AuthResult, CredentialStore, StoredCredential};
+use crate::auth::login_script::LoginScript;
+use crate::storage::{AuthResult, CredentialStore, LoginStep, StoredCredential};
 
+use super::totp;
 use super::Provider;
 
 pub struct SlackProvider;
@@ -29,9 +31,16 @@ impl Provider for SlackProvider {
     fn authenticate(&self, store: &CredentialStore, label: &str) -> Result<AuthResult> {
         let profile_dir = store.chrome_profile_dir("slack", label);
         let existing = profile_dir.exists();
+        let chrome = store.chrome_config("slack", label)?;
 
         // Interactive login -- always visible so user can SSO/captcha
-        let creds = chrome_auth::extract_credentials_with_chrome(profile_dir, existing, false)?;
+        let creds = chrome_auth::extract_credentials_with_chrome(
+            profile_dir,
+            existing,
+            false,
+            &chrome,
+            None,
+        )?;
 
         let mut fields = HashMap::new();
         fields.insert("token".to_string(), creds.token);
@@ -49,13 +58,22 @@ impl Provider for SlackProvider {
                 created_at: now,
                 last_validated: None,
             },
+            claims: None,
         })
     }
 
     fn refresh(&self, store: &CredentialStore, label: &str) -> Result<StoredCredential> {
         let profile_dir = store.chrome_profile_dir("slack", label);
+        let chrome = store.chrome_config("slack", label)?;
+        let login = login_script(store, label)?;
         // Headless -- reuses existing Chrome profile with session cookies
-        let creds = chrome_auth::extract_credentials_with_chrome(profile_dir, true, true)?;
+        let creds = chrome_auth::extract_credentials_with_chrome(
+            profile_dir,
+            true,
+            true,
+            &chrome,
+            login.as_ref(),
+        )?;
 
         let mut fields = HashMap::new();
         fields.insert("token".to_string(), creds.token);
@@ -70,7 +88,12 @@ impl Provider for SlackProvider {
         })
     }
 
-    fn validate(&self, credential: &StoredCredential) -> Result<bool> {
+    fn validate(
+        &self,
+        _store: &CredentialStore,
+        _label: &str,
+        credential: &StoredCredential,
+    ) -> Result<bool> {
         let token = credential
             .fields
             .get("token")
@@ -90,4 +113,211 @@ impl Provider for SlackProvider {
 
         Ok(resp.get("ok").and_then(|v| v.as_bool()).unwrap_or(false))
     }
+
+    /// Cookie-based accounts are signed out of their browser session
+    /// first, then the token itself is revoked.
+    fn revoke(&self, store: &CredentialStore, label: &str) -> Result<bool> {
+        let existing = store.get_credential("slack", label)?;
+        let token = existing
+            .fields
+            .get("token")
+            .context("Missing 'token' field")?;
+        let cookie = existing.fields.get("cookie").map(String::as_str);
+
+        let client = reqwest::blocking::Client::new();
+        let signed_out = match cookie {
+            Some(cookie) => {
+                let signed_out = sign_out(&client, SLACK_API_URL, token, cookie)?;
+                if signed_out {
+                    eprintln!("Signed slack/{} out of its browser session.", label);
+                }
+                signed_out
+            }
+            None => false,
+        };
+        let revoked = revoke_token(&client, SLACK_API_URL, token, cookie)?;
+        if !signed_out && !revoked {
+            eprintln!("Token for slack/{} was already invalid upstream.", label);
+        }
+        Ok(signed_out || revoked)
+    }
+
+    fn http_headers(&self, credential: &StoredCredential) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        if let Some(token) = credential.fields.get("token") {
+            headers.push(("Authorization".to_string(), format!("Bearer {}", token)));
+        }
+        // xoxc tokens are only accepted alongside the d cookie
+        if let Some(cookie) = credential.fields.get("cookie") {
+            headers.push(("Cookie".to_string(), cookie.clone()));
+        }
+        headers
+    }
+
+    fn http_hosts(&self) -> &[&str] {
+        &["slack.com", "*.slack.com"]
+    }
+
+    /// The Web API answers 200 with `{"ok": false, "error": "invalid_auth"}`
+    /// rather than 401 when the session is gone.
+    fn http_auth_failed(&self, status: u16, body: &[u8]) -> bool {
+        if status == 401 {
+            return true;
+        }
+        serde_json::from_slice::<serde_json::Value>(body)
+            .ok()
+            .and_then(|v| v.get("error").and_then(|e| e.as_str()).map(str::to_string))
+            .is_some_and(|e| AUTH_ERRORS.contains(&e.as_str()))
+    }
+}
+
+const SLACK_API_URL: &str = "https://slack.com/api";
+/// Web API errors meaning the token or session is no longer valid.
+const AUTH_ERRORS: &[&str] = &[
+    "invalid_auth",
+    "token_revoked",
+    "account_inactive",
+    "not_authed",
+];
+
+/// End the browser session behind the `d` cookie, as the web client's own
+/// sign-out does. False if the session was already gone.
+fn sign_out(
+    client: &reqwest::blocking::Client,
+    api_url: &str,
+    token: &str,
+    cookie: &str,
+) -> Result<bool> {
+    call(client, api_url, "auth.signout", token, Some(cookie))
+}
+
+/// Revoke the token. False if it was already invalid.
+fn revoke_token(
+    client: &reqwest::blocking::Client,
+    api_url: &str,
+    token: &str,
+    cookie: Option<&str>,
+) -> Result<bool> {
+    call(client, api_url, "auth.revoke", token, cookie)
+}
+
+/// Call a Web API method for its effect: true if it succeeded, false if
+/// the credential was already invalid, an error otherwise.
+fn call(
+    client: &reqwest::blocking::Client,
+    api_url: &str,
+    method: &str,
+    token: &str,
+    cookie: Option<&str>,
+) -> Result<bool> {
+    // xoxc client tokens are only accepted with the d cookie
+    let mut request = client
+        .post(format!("{}/{}", api_url, method))
+        .header("Authorization", format!("Bearer {}", token));
+    if let Some(cookie) = cookie {
+        request = request.header("Cookie", cookie);
+    }
+    let resp: serde_json::Value = request
+        .send()
+        .with_context(|| format!("Failed to reach Slack {}", method))?
+        .json()
+        .with_context(|| format!("Slack {} returned an invalid response", method))?;
+
+    if resp.get("ok").and_then(|v| v.as_bool()) == Some(true) {
+        return Ok(true);
+    }
+    let error = resp
+        .get("error")
+        .and_then(|v| v.as_str())
+        .unwrap_or("unknown");
+    if AUTH_ERRORS.contains(&error) {
+        return Ok(false);
+    }
+    anyhow::bail!("Slack {} failed: {}", method, error)
+}
+
+/// The account's configured login script, if any, with its stored secrets.
+/// Not checked here: most refreshes never need it, so problems are only
+/// reported when the sign-in page appears and the script runs.
+fn login_script(store: &CredentialStore, label: &str) -> Result<Option<LoginScript>> {
+    let account = store.get_account("slack", label)?;
+    if account.login.is_empty() {
+        return Ok(None);
+    }
+
+    let mut totp_accounts = HashMap::new();
+    for step in &account.login {
+        if let LoginStep::Totp {
+            account: Some(totp_label),
+            ..
+        } = step
+        {
+            // A missing account is reported by `check` if the step runs
+            if let Ok(seed) = totp::load_seed(store, totp_label) {
+                totp_accounts.insert(totp_label.clone(), seed);
+            }
+        }
+    }
+
+    let script = LoginScript {
+        steps: account.login,
+        secrets: store.get_secrets("slack", label)?,
+        totp_accounts,
+    };
+    Ok(Some(script))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::mock_http;
+
+    fn ok() -> (u16, String) {
+        (200, r#"{"ok": true}"#.to_string())
+    }
+
+    fn error(error: &str) -> (u16, String) {
+        (200, format!(r#"{{"ok": false, "error": "{}"}}"#, error))
+    }
+
+    #[test]
+    fn web_api_calls_report_their_outcome() {
+        let client = reqwest::blocking::Client::new();
+        let api = mock_http(vec![
+            ok(),
+            error("invalid_auth"),
+            error("token_revoked"),
+            error("ratelimited"),
+        ]);
+        assert!(sign_out(&client, &api, "xoxc-1", "d=abc").unwrap());
+        assert!(!sign_out(&client, &api, "xoxc-1", "d=abc").unwrap());
+        assert!(!revoke_token(&client, &api, "xoxc-1", Some("d=abc")).unwrap());
+        let err = revoke_token(&client, &api, "xoxc-1", None).unwrap_err();
+        assert_eq!(err.to_string(), "Slack auth.revoke failed: ratelimited");
+    }
+
+    #[test]
+    fn sign_out_sends_the_session_cookie() {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let api = format!("http://{}/api", server.server_addr().to_ip().unwrap());
+        let client = reqwest::blocking::Client::new();
+        let handle = std::thread::spawn(move || sign_out(&client, &api, "xoxc-1", "d=abc"));
+
+        let request = server.recv().unwrap();
+        assert_eq!(request.url(), "/api/auth.signout");
+        let header = |name: &'static str| {
+            request
+                .headers()
+                .iter()
+                .find(|h| h.field.equiv(name))
+                .map(|h| h.value.to_string())
+        };
+        assert_eq!(header("Authorization").as_deref(), Some("Bearer xoxc-1"));
+        assert_eq!(header("Cookie").as_deref(), Some("d=abc"));
+        request
+            .respond(tiny_http::Response::from_string(r#"{"ok": true}"#))
+            .unwrap();
+        assert!(handle.join().unwrap().unwrap());
+    }
 }
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use chrono::{NaiveDate, NaiveTime};
+
+use crate::auth::prompt;
+use crate::storage::{AuthResult, CredentialStore, HttpProbe, StaticKeyConfig, StoredCredential};
+
+use super::Provider;
+
+const DEFAULT_FIELDS: &[&str] = &["token"];
+const DEFAULT_WARN_DAYS: u64 = 14;
+
+/// API keys, PATs and other secrets that are entered by hand and can't be
+/// renewed automatically.
+pub struct StaticProvider;
+
+impl Provider for StaticProvider {
+    fn name(&self) -> &str {
+        "static"
+    }
+
+    fn display_name(&self) -> &str {
+        "Static secret"
+    }
+
+    fn credential_fields(&self) -> &[&str] {
+        DEFAULT_FIELDS
+    }
+
+    fn max_credential_age_days(&self) -> u64 {
+        // Expiry comes from the configured date, not the age
+        3650
+    }
+
+    fn authenticate(&self, store: &CredentialStore, label: &str) -> Result<AuthResult> {
+        let config = store.key_config("static", label)?;
+        let names: Vec<String> = if config.fields.is_empty() {
+            DEFAULT_FIELDS.iter().map(|s| s.to_string()).collect()
+        } else {
+            config.fields.clone()
+        };
+
+        let mut fields = HashMap::new();
+        for name in &names {
+            let value = prompt::read_secret(&format!("{}: ", name))?;
+            fields.insert(name.clone(), value);
+        }
+
+        let mut credential = StoredCredential {
+            fields,
+            created_at: CredentialStore::now(),
+            last_validated: None,
+        };
+
+        if let Some(probe) = &config.probe {
+            run_probe(probe, &credential)?;
+            credential.last_validated = Some(CredentialStore::now());
+            eprintln!("Validation probe succeeded.");
+        }
+
+        Ok(AuthResult {
+            label: label.to_string(),
+            display_name: label.to_string(),
+            provider_id: String::new(),
+            user_id: String::new(),
+            credential,
+            claims: None,
+        })
+    }
+
+    fn refresh(&self, store: &CredentialStore, label: &str) -> Result<StoredCredential> {
+        // Nothing to renew; refresh re-runs the probe so the daemon notices
+        // revoked keys.
+        let mut existing = store.get_credential("static", label)?;
+        let config = store.key_config("static", label)?;
+
+        if let Some(warning) = expiry_warning(&config)? {
+            eprintln!("static/{}: {}", label, warning);
+        }
+
+        if let Some(probe) = &config.probe {
+            run_probe(probe, &existing)?;
+            existing.last_validated = Some(CredentialStore::now());
+        }
+        Ok(existing)
+    }
+
+    fn validate(
+        &self,
+        store: &CredentialStore,
+        label: &str,
+        credential: &StoredCredential,
+    ) -> Result<bool> {
+        if credential.fields.is_empty() {
+            return Ok(false);
+        }
+        match store.key_config("static", label)?.probe {
+            Some(probe) => Ok(expected_status(&probe, send_probe(&probe, credential)?)),
+            None => Ok(true),
+        }
+    }
+
+    fn needs_refresh(&self, _credential: &StoredCredential) -> bool {
+        false
+    }
+
+    fn status_warning(&self, store: &CredentialStore, label: &str) -> Option<String> {
+        let config = store.key_config("static", label).ok()?;
+        expiry_warning(&config).unwrap_or_else(|e| Some(e.to_string()))
+    }
+}
+
+/// Parse a YYYY-MM-DD expiry date to a unix timestamp (midnight UTC).
+pub fn parse_expiry_date(date: &str) -> Result<u64> {
+    let day = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .with_context(|| format!("Invalid expiry date '{}' (expected YYYY-MM-DD)", date))?;
+    Ok(day.and_time(NaiveTime::MIN).and_utc().timestamp().max(0) as u64)
+}
+
+fn expiry_warning(config: &StaticKeyConfig) -> Result<Option<String>> {
+    let Some(date) = &config.expires else {
+        return Ok(None);
+    };
+    let expires_at = parse_expiry_date(date)?;
+    let now = CredentialStore::now();
+
+    if now >= expires_at {
+        return Ok(Some(format!("EXPIRED on {}", date)));
+    }
+
+    let days_left = (expires_at - now) / 86400;
+    if days_left <= config.warn_days.unwrap_or(DEFAULT_WARN_DAYS) {
+        Ok(Some(format!("expires in {} days ({})", days_left, date)))
+    } else {
+        Ok(None)
+    }
+}
+
+fn fill_placeholders(template: &str, credential: &StoredCredential) -> String {
+    let mut out = template.to_string();
+    for (name, value) in &credential.fields {
+        out = out.replace(&format!("{{{{{}}}}}", name), value);
+    }
+    out
+}
+
+fn run_probe(probe: &HttpProbe, credential: &StoredCredential) -> Result<()> {
+    let status = send_probe(probe, credential)?;
+    if !expected_status(probe, status) {
+        anyhow::bail!("Validation probe to {} returned {}", probe.url, status);
+    }
+    Ok(())
+}
+
+fn expected_status(probe: &HttpProbe, status: reqwest::StatusCode) -> bool {
+    match probe.expect_status {
+        Some(code) => status.as_u16() == code,
+        None => status.is_success(),
+    }
+}
+
+/// Send the probe request and return its status; errors only if the
+/// request itself fails.
+fn send_probe(probe: &HttpProbe, credential: &StoredCredential) -> Result<reqwest::StatusCode> {
+    let method: reqwest::Method = probe
+        .method
+        .as_deref()
+        .unwrap_or("GET")
+        .to_ascii_uppercase()
+        .parse()
+        .context("Invalid probe method")?;
+
+    let url = fill_placeholders(&probe.url, credential);
+    let client = reqwest::blocking::Client::new();
+    let mut request = client.request(method, &url);
+    for (name, value) in &probe.headers {
+        request = request.header(name, fill_placeholders(value, credential));
+    }
+
+    Ok(request
+        .send()
+        .with_context(|| format!("Validation probe to {} failed", probe.url))?
+        .status())
+}
@@ -0,0 +1,288 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use reqwest::blocking::Client;
+use reqwest::Url;
+use tiny_http::{Header, Request, Response, Server};
+
+use super::secure_transport;
+
+/// Upper bound on any single upstream request.
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Headers that describe one connection rather than the request, plus the
+/// ones the proxy sets itself.
+const SKIP_REQUEST_HEADERS: &[&str] = &[
+    "connection",
+    "proxy-connection",
+    "keep-alive",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+    "content-length",
+    // Bodies are inspected for auth errors, so ask for them uncompressed
+    "accept-encoding",
+];
+const SKIP_RESPONSE_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "content-length",
+];
+
+/// A host the proxy accepts requests for and the account it signs them as.
+#[derive(Debug, Clone)]
+pub struct Route {
+    /// `host[:port]` as clients address it.
+    pub host: String,
+    /// Base URL requests are forwarded to, without a trailing slash.
+    pub upstream: String,
+    /// `provider/label`, for logging.
+    pub account: String,
+}
+
+/// Where the proxy gets credentials from. Calls may come from several
+/// connection threads at once.
+pub trait AuthSource: Send + Sync {
+    /// Headers to add to a request for `route`. `rejected` holds headers
+    /// the upstream just turned down: renew the credentials first, unless
+    /// they have changed since those were handed out.
+    fn headers(
+        &self,
+        route: &Route,
+        rejected: Option<&[(String, String)]>,
+    ) -> Result<Vec<(String, String)>>;
+    /// Whether a response means the route's credentials were rejected.
+    fn rejected(&self, route: &Route, status: u16, body: &[u8]) -> bool;
+}
+
+/// Serve plain-HTTP requests on `listen` until the process is killed,
+/// forwarding each to its route's upstream with credentials attached.
+/// Clients either use the proxy as an HTTP proxy (`http://host/...`
+/// request targets) or address it directly with a matching Host header.
+pub fn serve(listen: SocketAddr, routes: Vec<Route>, auth: Arc<dyn AuthSource>) -> Result<()> {
+    if !listen.ip().is_loopback() {
+        anyhow::bail!(
+            "Refusing to listen on {}: the HTTP proxy only accepts local connections",
+            listen
+        );
+    }
+    for route in &routes {
+        let url = Url::parse(&route.upstream).with_context(|| {
+            format!(
+                "Invalid upstream URL '{}' for {}",
+                route.upstream, route.host
+            )
+        })?;
+        if !secure_transport(&url) {
+            anyhow::bail!(
+                "Refusing upstream {}: use https://, or http:// for a loopback host",
+                route.upstream
+            );
+        }
+    }
+
+    let client = Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(UPSTREAM_TIMEOUT)
+        .build()?;
+    let server = Server::http(listen)
+        .map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", listen, e))?;
+    eprintln!("HTTP proxy listening on {}", listen);
+    for route in &routes {
+        eprintln!("  {} -> {} ({})", route.host, route.upstream, route.account);
+    }
+
+    let routes = Arc::new(routes);
+    for request in server.incoming_requests() {
+        let routes = routes.clone();
+        let auth = auth.clone();
+        let client = client.clone();
+        thread::spawn(move || handle(request, &routes, auth.as_ref(), &client));
+    }
+    Ok(())
+}
+
+fn handle(mut request: Request, routes: &[Route], auth: &dyn AuthSource, client: &Client) {
+    let method = request.method().to_string();
+    if method.eq_ignore_ascii_case("CONNECT") {
+        eprintln!(
+            "[HTTP] CONNECT {} -> 501 (tunnelling not supported)",
+            request.url()
+        );
+        let _ = request.respond(
+            Response::from_string(
+                "tokey proxy can't add credentials inside a TLS tunnel. Request \
+                 http://<host>/... instead; it is forwarded upstream over https.\n",
+            )
+            .with_status_code(501),
+        );
+        return;
+    }
+
+    let (host, path) = match target(&request) {
+        Some(target) => target,
+        None => {
+            let _ = request.respond(
+                Response::from_string("No target host in request\n").with_status_code(400),
+            );
+            return;
+        }
+    };
+    // Query strings often carry tokens or personal data; keep them out of logs
+    let logged_path = path.split('?').next().unwrap_or_default().to_string();
+
+    let Some(route) = routes.iter().find(|r| r.host.eq_ignore_ascii_case(&host)) else {
+        eprintln!("[HTTP] {} {}{} -> 403 (no rule)", method, host, logged_path);
+        let _ = request.respond(
+            Response::from_string(format!("No http_proxy rule for host '{}'\n", host))
+                .with_status_code(403),
+        );
+        return;
+    };
+
+    let mut body = Vec::new();
+    if let Err(e) = request.as_reader().read_to_end(&mut body) {
+        eprintln!(
+            "[HTTP] {} {}{}: failed to read request body: {}",
+            method, host, logged_path, e
+        );
+        return;
+    }
+
+    let response = forward(&request, route, &method, &path, body, auth, client);
+    let response = match response {
+        Ok((reply, refreshed)) => {
+            eprintln!(
+                "[HTTP] {} {}{} -> {} ({}{})",
+                method,
+                host,
+                logged_path,
+                reply.status,
+                route.account,
+                if refreshed { ", refreshed" } else { "" }
+            );
+            let mut response = Response::from_data(reply.body).with_status_code(reply.status);
+            for (name, value) in reply.headers {
+                if let Ok(header) = Header::from_bytes(name.as_bytes(), value) {
+                    response.add_header(header);
+                }
+            }
+            response
+        }
+        Err(e) => {
+            eprintln!(
+                "[HTTP] {} {}{} -> 502 ({}): {:#}",
+                method, host, logged_path, route.account, e
+            );
+            Response::from_string(format!("tokey proxy: {:#}\n", e)).with_status_code(502)
+        }
+    };
+    let _ = request.respond(response);
+}
+
+/// The requested host and path-with-query, from an absolute-form target
+/// (proxy use) or the Host header (direct use).
+fn target(request: &Request) -> Option<(String, String)> {
+    let url = request.url();
+    if let Some(rest) = url.strip_prefix("http://") {
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        return Some((host.trim_end_matches(":80").to_string(), path.to_string()));
+    }
+    let host = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Host"))?
+        .value
+        .as_str()
+        .trim_end_matches(":80")
+        .to_string();
+    Some((host, url.to_string()))
+}
+
+/// An upstream response, relayed to the client as is.
+struct Reply {
+    status: u16,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+}
+
+/// Send the request upstream, and if the credentials are rejected, once
+/// more with refreshed ones. Also returns whether the retry happened.
+fn forward(
+    request: &Request,
+    route: &Route,
+    method: &str,
+    path: &str,
+    body: Vec<u8>,
+    auth: &dyn AuthSource,
+    client: &Client,
+) -> Result<(Reply, bool)> {
+    let method = reqwest::Method::from_bytes(method.as_bytes())?;
+    let url = format!("{}{}", route.upstream, path);
+
+    let send = |credentials: &[(String, String)]| -> Result<Reply> {
+        let mut upstream = client.request(method.clone(), &url).body(body.clone());
+        for header in request.headers() {
+            let name = header.field.as_str().as_str();
+            let lower = name.to_ascii_lowercase();
+            if SKIP_REQUEST_HEADERS.contains(&lower.as_str())
+                || credentials
+                    .iter()
+                    .any(|(n, _)| n.eq_ignore_ascii_case(name))
+            {
+                continue;
+            }
+            upstream = upstream.header(name, header.value.as_str());
+        }
+        for (name, value) in credentials {
+            upstream = upstream.header(name, value);
+        }
+
+        let response = upstream
+            .send()
+            .with_context(|| format!("Request to {} failed", route.upstream))?;
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| !SKIP_RESPONSE_HEADERS.contains(&name.as_str()))
+            .map(|(name, value)| (name.to_string(), value.as_bytes().to_vec()))
+            .collect();
+        let body = response.bytes()?.to_vec();
+        Ok(Reply {
+            status,
+            headers,
+            body,
+        })
+    };
+
+    let credentials = auth.headers(route, None)?;
+    let reply = send(&credentials)?;
+    if !auth.rejected(route, reply.status, &reply.body) {
+        return Ok((reply, false));
+    }
+    eprintln!(
+        "[HTTP] {} rejected {} ({}); refreshing and retrying",
+        route.host, route.account, reply.status
+    );
+    match auth.headers(route, Some(&credentials)) {
+        Ok(credentials) => Ok((send(&credentials)?, true)),
+        // Pass the rejection on: it tells the client more than a 502 would
+        Err(e) => {
+            eprintln!("[HTTP] refreshing {} failed: {:#}", route.account, e);
+            Ok((reply, false))
+        }
+    }
+}
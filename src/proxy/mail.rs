@@ -0,0 +1,917 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::auth::sasl::SaslMechanism;
+
+use super::resolves_to_loopback;
+
+/// How long the splice loop waits on one side before checking the other.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// Upper bound on any single upstream response during login.
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+/// Unauthenticated local clients are dropped after this long.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(300);
+/// The upstream tag used for our own AUTHENTICATE command.
+const IMAP_AUTH_TAG: &str = "T0";
+/// Longest line or IMAP literal accepted before login. Nothing sent at
+/// that stage (a user name, a password) comes close, so more is refused
+/// rather than buffered.
+const MAX_LOGIN_DATA: usize = 64 * 1024;
+
+/// Returns the SASL user and a current access token. Called once per login
+/// so each session starts with a freshly refreshed token.
+pub type LoginSource = Arc<dyn Fn() -> Result<(String, String)> + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Imap,
+    Smtp,
+}
+
+impl std::fmt::Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Protocol::Imap => "IMAP",
+            Protocol::Smtp => "SMTP",
+        })
+    }
+}
+
+/// One local listener and the server it forwards to.
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub protocol: Protocol,
+    pub listen: SocketAddr,
+    /// `host:port` of the real server (implicit TLS unless `tls` is false).
+    pub upstream: String,
+    pub tls: bool,
+}
+
+/// Accept connections on every route until the process is killed. Local
+/// clients authenticate with `password` (any user name); the proxy then logs
+/// in upstream with XOAUTH2 and relays the session unchanged.
+pub fn serve(routes: Vec<Route>, password: String, login: LoginSource) -> Result<()> {
+    let password = Arc::new(password);
+    let mut handles = Vec::new();
+
+    for route in routes {
+        if !route.listen.ip().is_loopback() {
+            anyhow::bail!(
+                "Refusing to listen on {}: the mail proxy only accepts local connections",
+                route.listen
+            );
+        }
+        if !route.tls && !resolves_to_loopback(&route.upstream) {
+            anyhow::bail!(
+                "Refusing plaintext connection to {}: only loopback upstreams may skip TLS",
+                route.upstream
+            );
+        }
+        let listener = TcpListener::bind(route.listen)
+            .with_context(|| format!("Failed to listen on {}", route.listen))?;
+        eprintln!(
+            "{} proxy listening on {} -> {}{}",
+            route.protocol,
+            route.listen,
+            route.upstream,
+            if route.tls { " (TLS)" } else { " (plaintext)" }
+        );
+
+        let password = password.clone();
+        let login = login.clone();
+        handles.push(thread::spawn(move || {
+            for stream in listener.incoming() {
+                let client = match stream {
+                    Ok(s) => s,
+                    Err(e) => {
+                        eprintln!("[{}] accept failed: {}", route.protocol, e);
+                        continue;
+                    }
+                };
+                let route = route.clone();
+                let password = password.clone();
+                let login = login.clone();
+                thread::spawn(move || {
+                    let peer = client
+                        .peer_addr()
+                        .map(|a| a.to_string())
+                        .unwrap_or_default();
+                    let result = match route.protocol {
+                        Protocol::Imap => handle_imap(client, &route, &password, &login),
+                        Protocol::Smtp => handle_smtp(client, &route, &password, &login),
+                    };
+                    match result {
+                        Ok(()) => eprintln!("[{}] {} closed", route.protocol, peer),
+                        Err(e) => eprintln!("[{}] {} error: {:#}", route.protocol, peer, e),
+                    }
+                });
+            }
+        }));
+    }
+
+    for handle in handles {
+        let _ = handle.join();
+    }
+    Ok(())
+}
+
+// -- Upstream connection ------------------------------------------------------
+
+enum Upstream {
+    Plain(TcpStream),
+    Tls(Box<native_tls::TlsStream<TcpStream>>),
+}
+
+impl Upstream {
+    fn connect(route: &Route) -> Result<Self> {
+        let addr = route
+            .upstream
+            .to_socket_addrs()
+            .with_context(|| format!("Invalid upstream address '{}'", route.upstream))?
+            .next()
+            .with_context(|| format!("'{}' did not resolve", route.upstream))?;
+        let tcp = TcpStream::connect_timeout(&addr, UPSTREAM_TIMEOUT)
+            .with_context(|| format!("Failed to connect to {}", route.upstream))?;
+        tcp.set_read_timeout(Some(UPSTREAM_TIMEOUT))?;
+
+        if !route.tls {
+            return Ok(Upstream::Plain(tcp));
+        }
+        let host = route
+            .upstream
+            .rsplit_once(':')
+            .map(|(h, _)| h)
+            .unwrap_or(&route.upstream);
+        let tls = native_tls::TlsConnector::new()?
+            .connect(host, tcp)
+            .map_err(|e| anyhow::anyhow!("TLS handshake with {} failed: {}", route.upstream, e))?;
+        Ok(Upstream::Tls(Box::new(tls)))
+    }
+
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            Upstream::Plain(s) => s,
+            Upstream::Tls(s) => s.get_ref(),
+        }
+    }
+}
+
+impl Read for Upstream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Upstream::Plain(s) => s.read(buf),
+            Upstream::Tls(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Upstream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Upstream::Plain(s) => s.write(buf),
+            Upstream::Tls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Upstream::Plain(s) => s.flush(),
+            Upstream::Tls(s) => s.flush(),
+        }
+    }
+}
+
+/// Relay bytes both ways until either side closes. A TLS stream can't be
+/// split across threads, so one thread polls both sides with short timeouts.
+/// Anything already read into the buffers during login is forwarded first.
+fn splice(client: BufReader<TcpStream>, upstream: BufReader<Upstream>) -> Result<()> {
+    let pending_up = client.buffer().to_vec();
+    let pending_down = upstream.buffer().to_vec();
+    let mut client = client.into_inner();
+    let mut upstream = upstream.into_inner();
+
+    upstream.write_all(&pending_up)?;
+    upstream.flush()?;
+    client.write_all(&pending_down)?;
+
+    client.set_read_timeout(Some(POLL_INTERVAL))?;
+    upstream.tcp().set_read_timeout(Some(POLL_INTERVAL))?;
+
+    let mut buf = [0u8; 16 * 1024];
+    loop {
+        match client.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => {
+                upstream.write_all(&buf[..n])?;
+                upstream.flush()?;
+            }
+            Err(e) if is_timeout(&e) => {}
+            Err(e) => return Err(e.into()),
+        }
+        match upstream.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => client.write_all(&buf[..n])?,
+            Err(e) if is_timeout(&e) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// One CRLF-terminated line without the line ending; None at EOF. Lines
+/// longer than `MAX_LOGIN_DATA` are an error.
+fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<String>> {
+    let mut line = String::new();
+    let limit = MAX_LOGIN_DATA as u64 + 2;
+    if reader.take(limit).read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') && line.len() as u64 == limit {
+        anyhow::bail!("Line longer than {} bytes", MAX_LOGIN_DATA);
+    }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+fn send(stream: &mut impl Write, line: &str) -> Result<()> {
+    stream.write_all(line.as_bytes())?;
+    stream.write_all(b"\r\n")?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Compare without exiting early on the first differing byte.
+fn password_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Decode a SASL PLAIN response ("authzid\0user\0password").
+fn decode_plain(b64: &str) -> Option<(String, String)> {
+    let raw = STANDARD.decode(b64.trim()).ok()?;
+    let text = String::from_utf8(raw).ok()?;
+    let mut parts = text.splitn(3, '\0');
+    let _authzid = parts.next()?;
+    let user = parts.next()?;
+    let pass = parts.next()?;
+    Some((user.to_string(), pass.to_string()))
+}
+
+fn xoauth2(login: &LoginSource) -> Result<String> {
+    let (user, token) = login()?;
+    Ok(STANDARD.encode(SaslMechanism::XOAuth2.initial_response(&user, &token)))
+}
+
+// -- IMAP ---------------------------------------------------------------------
+
+const IMAP_CAPABILITY: &str = "IMAP4rev1 AUTH=PLAIN";
+
+fn handle_imap(
+    client: TcpStream,
+    route: &Route,
+    password: &str,
+    login: &LoginSource,
+) -> Result<()> {
+    client.set_read_timeout(Some(LOGIN_TIMEOUT))?;
+    let mut writer = client.try_clone()?;
+    let mut reader = BufReader::new(client);
+
+    send(
+        &mut writer,
+        &format!(
+            "* OK [CAPABILITY {}] tokey mailproxy ready",
+            IMAP_CAPABILITY
+        ),
+    )?;
+
+    loop {
+        let Some(args) = read_imap_command(&mut reader, &mut writer)? else {
+            return Ok(());
+        };
+        if args.len() < 2 {
+            send(&mut writer, "* BAD Missing command")?;
+            continue;
+        }
+        let tag = args[0].as_str();
+        let credentials = match args[1].to_ascii_uppercase().as_str() {
+            "CAPABILITY" => {
+                send(&mut writer, &format!("* CAPABILITY {}", IMAP_CAPABILITY))?;
+                send(&mut writer, &format!("{} OK CAPABILITY completed", tag))?;
+                continue;
+            }
+            "NOOP" => {
+                send(&mut writer, &format!("{} OK NOOP completed", tag))?;
+                continue;
+            }
+            "LOGOUT" => {
+                send(&mut writer, "* BYE tokey mailproxy closing")?;
+                send(&mut writer, &format!("{} OK LOGOUT completed", tag))?;
+                return Ok(());
+            }
+            "LOGIN" if args.len() == 4 => Some((args[2].clone(), args[3].clone())),
+            "AUTHENTICATE" if args.len() >= 3 && args[2].eq_ignore_ascii_case("PLAIN") => {
+                let response = match args.get(3) {
+                    Some(initial) => initial.clone(),
+                    None => {
+                        send(&mut writer, "+ ")?;
+                        read_line(&mut reader)?.unwrap_or_default()
+                    }
+                };
+                decode_plain(&response)
+            }
+            "AUTHENTICATE" => {
+                send(
+                    &mut writer,
+                    &format!("{} NO Only AUTHENTICATE PLAIN is supported", tag),
+                )?;
+                continue;
+            }
+            _ => {
+                send(&mut writer, &format!("{} BAD Log in first", tag))?;
+                continue;
+            }
+        };
+
+        match credentials {
+            Some((_, pass)) if password_matches(&pass, password) => {}
+            _ => {
+                send(
+                    &mut writer,
+                    &format!("{} NO [AUTHENTICATIONFAILED] Invalid credentials", tag),
+                )?;
+                continue;
+            }
+        }
+
+        let tag = tag.to_string();
+        match imap_upstream_login(route, login) {
+            Ok((upstream, status)) => {
+                // Present upstream's tagged OK (often with CAPABILITY) as ours
+                send(&mut writer, &format!("{} {}", tag, status))?;
+                return splice(reader, upstream);
+            }
+            Err(e) => {
+                send(
+                    &mut writer,
+                    &format!("{} NO [UNAVAILABLE] Upstream login failed", tag),
+                )?;
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// Connect and AUTHENTICATE XOAUTH2. Returns the connection and the text
+/// of upstream's tagged OK response (without the tag).
+fn imap_upstream_login(
+    route: &Route,
+    login: &LoginSource,
+) -> Result<(BufReader<Upstream>, String)> {
+    let mut upstream = BufReader::new(Upstream::connect(route)?);
+
+    let greeting = read_line(&mut upstream)?.context("Upstream closed before greeting")?;
+    if !greeting.starts_with("* OK") {
+        anyhow::bail!("Unexpected upstream greeting: {}", greeting);
+    }
+
+    let command = format!("{} AUTHENTICATE XOAUTH2 {}", IMAP_AUTH_TAG, xoauth2(login)?);
+    send(upstream.get_mut(), &command)?;
+
+    let prefix = format!("{} ", IMAP_AUTH_TAG);
+    loop {
+        let line = read_line(&mut upstream)?.context("Upstream closed during login")?;
+        if line.starts_with('+') {
+            // Error details as a base64 JSON challenge; an empty reply ends it
+            let detail = STANDARD
+                .decode(line.trim_start_matches('+').trim())
+                .ok()
+                .and_then(|d| String::from_utf8(d).ok())
+                .unwrap_or_default();
+            eprintln!("[IMAP] upstream rejected token: {}", detail);
+            send(upstream.get_mut(), "")?;
+        } else if let Some(status) = line.strip_prefix(&prefix) {
+            if status.starts_with("OK") {
+                return Ok((upstream, status.to_string()));
+            }
+            anyhow::bail!("Upstream XOAUTH2 login failed: {}", status);
+        }
+        // Untagged responses before the tagged one are dropped
+    }
+}
+
+/// Read one IMAP command as a list of arguments (tag first), following
+/// `{n}` / `{n+}` literals across lines. None at EOF, or after refusing a
+/// non-synchronizing literal that is too large to skip.
+fn read_imap_command(
+    reader: &mut BufReader<TcpStream>,
+    writer: &mut TcpStream,
+) -> Result<Option<Vec<String>>> {
+    let mut args = Vec::new();
+    loop {
+        let Some(line) = read_line(reader)? else {
+            return Ok(None);
+        };
+        let (text, literal) = split_literal(&line);
+        args.extend(tokenize_imap(text));
+
+        let Some((len, synchronizing)) = literal else {
+            return Ok(Some(args));
+        };
+        if len > MAX_LOGIN_DATA {
+            let tag = args.first().map(String::as_str).unwrap_or("*");
+            send(writer, &format!("{} BAD Literal too large", tag))?;
+            if !synchronizing {
+                // The data is already on its way and can't be told apart
+                // from the next command
+                send(writer, "* BYE Literal too large")?;
+                return Ok(None);
+            }
+            args.clear();
+            continue;
+        }
+        if synchronizing {
+            send(writer, "+ Ready for literal data")?;
+        }
+        let mut data = vec![0u8; len];
+        reader.read_exact(&mut data)?;
+        args.push(String::from_utf8_lossy(&data).into_owned());
+    }
+}
+
+/// Split a trailing `{n}` or `{n+}` literal marker off a command line.
+fn split_literal(line: &str) -> (&str, Option<(usize, bool)>) {
+    let Some(body) = line.strip_suffix('}') else {
+        return (line, None);
+    };
+    let Some(open) = body.rfind('{') else {
+        return (line, None);
+    };
+    let spec = &body[open + 1..];
+    let (digits, synchronizing) = match spec.strip_suffix('+') {
+        Some(d) => (d, false),
+        None => (spec, true),
+    };
+    match digits.parse::<usize>() {
+        Ok(len) => (&line[..open], Some((len, synchronizing))),
+        Err(_) => (line, None),
+    }
+}
+
+/// Split on spaces, honouring "quoted strings" with backslash escapes.
+fn tokenize_imap(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c == ' ' {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut token = String::new();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some(escaped) = chars.next() {
+                            token.push(escaped);
+                        }
+                    }
+                    '"' => break,
+                    _ => token.push(c),
+                }
+            }
+            tokens.push(token);
+        } else {
+            let mut token = String::new();
+            while let Some(&c) = chars.peek() {
+                if c == ' ' {
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+            tokens.push(token);
+        }
+    }
+    tokens
+}
+
+// -- SMTP ---------------------------------------------------------------------
+
+fn handle_smtp(
+    client: TcpStream,
+    route: &Route,
+    password: &str,
+    login: &LoginSource,
+) -> Result<()> {
+    client.set_read_timeout(Some(LOGIN_TIMEOUT))?;
+    let mut writer = client.try_clone()?;
+    let mut reader = BufReader::new(client);
+
+    send(&mut writer, "220 tokey mailproxy ESMTP ready")?;
+    let mut helo_name = "localhost".to_string();
+
+    loop {
+        let Some(line) = read_line(&mut reader)? else {
+            return Ok(());
+        };
+        let (verb, rest) = line.split_once(' ').unwrap_or((line.as_str(), ""));
+        let credentials = match verb.to_ascii_uppercase().as_str() {
+            "EHLO" => {
+                if !rest.trim().is_empty() {
+                    helo_name = rest.trim().to_string();
+                }
+                send(&mut writer, "250-tokey mailproxy")?;
+                send(&mut writer, "250 AUTH PLAIN LOGIN")?;
+                continue;
+            }
+            "HELO" => {
+                send(&mut writer, "250 tokey mailproxy")?;
+                continue;
+            }
+            "NOOP" | "RSET" => {
+                send(&mut writer, "250 2.0.0 OK")?;
+                continue;
+            }
+            "QUIT" => {
+                send(&mut writer, "221 2.0.0 Bye")?;
+                return Ok(());
+            }
+            "AUTH" => {
+                let mut parts = rest.split_whitespace();
+                let mechanism = parts.next().unwrap_or("").to_ascii_uppercase();
+                let initial = parts.next().map(str::to_string);
+                match mechanism.as_str() {
+                    "PLAIN" => {
+                        let response = match initial {
+                            Some(r) => r,
+                            None => {
+                                send(&mut writer, "334 ")?;
+                                read_line(&mut reader)?.unwrap_or_default()
+                            }
+                        };
+                        decode_plain(&response)
+                    }
+                    "LOGIN" => {
+                        let user = match initial {
+                            Some(r) => r,
+                            None => {
+                                send(&mut writer, "334 VXNlcm5hbWU6")?;
+                                read_line(&mut reader)?.unwrap_or_default()
+                            }
+                        };
+                        send(&mut writer, "334 UGFzc3dvcmQ6")?;
+                        let pass = read_line(&mut reader)?.unwrap_or_default();
+                        decode_b64(&user).zip(decode_b64(&pass))
+                    }
+                    _ => {
+                        send(&mut writer, "504 5.5.4 Unrecognized authentication type")?;
+                        continue;
+                    }
+                }
+            }
+            _ => {
+                send(&mut writer, "530 5.7.0 Authentication required")?;
+                continue;
+            }
+        };
+
+        match credentials {
+            Some((_, pass)) if password_matches(&pass, password) => {}
+            _ => {
+                send(&mut writer, "535 5.7.8 Authentication credentials invalid")?;
+                continue;
+            }
+        }
+
+        match smtp_upstream_login(route, login, &helo_name) {
+            Ok(upstream) => {
+                send(&mut writer, "235 2.7.0 Authentication successful")?;
+                return splice(reader, upstream);
+            }
+            Err(e) => {
+                send(&mut writer, "454 4.7.0 Upstream authentication failed")?;
+                return Err(e);
+            }
+        }
+    }
+}
+
+fn decode_b64(s: &str) -> Option<String> {
+    STANDARD
+        .decode(s.trim())
+        .ok()
+        .and_then(|d| String::from_utf8(d).ok())
+}
+
+/// Connect, EHLO and AUTH XOAUTH2. The client's session continues from the
+/// authenticated state, so it sends MAIL FROM next.
+fn smtp_upstream_login(
+    route: &Route,
+    login: &LoginSource,
+    helo_name: &str,
+) -> Result<BufReader<Upstream>> {
+    let mut upstream = BufReader::new(Upstream::connect(route)?);
+
+    let (code, text) = read_smtp_reply(&mut upstream)?;
+    if code != 220 {
+        anyhow::bail!("Unexpected upstream greeting: {} {}", code, text);
+    }
+
+    send(upstream.get_mut(), &format!("EHLO {}", helo_name))?;
+    let (code, text) = read_smtp_reply(&mut upstream)?;
+    if code != 250 {
+        anyhow::bail!("Upstream rejected EHLO: {} {}", code, text);
+    }
+
+    send(
+        upstream.get_mut(),
+        &format!("AUTH XOAUTH2 {}", xoauth2(login)?),
+    )?;
+    let (mut code, mut text) = read_smtp_reply(&mut upstream)?;
+    if code == 334 {
+        // Error details as a base64 JSON challenge; an empty reply ends it
+        eprintln!(
+            "[SMTP] upstream rejected token: {}",
+            decode_b64(&text).unwrap_or_default()
+        );
+        send(upstream.get_mut(), "")?;
+        (code, text) = read_smtp_reply(&mut upstream)?;
+    }
+    if code != 235 {
+        anyhow::bail!("Upstream XOAUTH2 login failed: {} {}", code, text);
+    }
+    Ok(upstream)
+}
+
+/// Read a (possibly multi-line) SMTP reply: its code and the last line's text.
+fn read_smtp_reply<R: BufRead>(reader: &mut R) -> Result<(u16, String)> {
+    loop {
+        let line = read_line(reader)?.context("Upstream closed the connection")?;
+        let code = line
+            .get(..3)
+            .and_then(|c| c.parse::<u16>().ok())
+            .with_context(|| format!("Malformed SMTP reply: {}", line))?;
+        // "250-..." continues, "250 ..." ends the reply
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok((code, line.get(4..).unwrap_or("").to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A free loopback port, released again for `serve` to bind.
+    fn free_addr() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    /// An IMAP server that accepts one XOAUTH2 login for alice@example.com
+    /// with token "tok", then answers any command with its tag and "OK
+    /// upstream".
+    fn fake_upstream() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            send(&mut writer, "* OK fake IMAP ready").unwrap();
+
+            let auth = read_line(&mut reader).unwrap().unwrap();
+            let b64 = auth.strip_prefix("T0 AUTHENTICATE XOAUTH2 ").unwrap();
+            let expected = "user=alice@example.com\x01auth=Bearer tok\x01\x01";
+            if STANDARD.decode(b64).unwrap() == expected.as_bytes() {
+                send(&mut writer, "T0 OK [CAPABILITY IMAP4rev1 IDLE] done").unwrap();
+            } else {
+                send(&mut writer, "T0 NO bad token").unwrap();
+                return;
+            }
+            while let Ok(Some(line)) = read_line(&mut reader) {
+                let tag = line.split(' ').next().unwrap_or("*");
+                send(&mut writer, &format!("{} OK upstream", tag)).unwrap();
+            }
+        });
+        addr
+    }
+
+    /// An SMTP server expecting `EHLO client.example` and one XOAUTH2 login
+    /// for alice@example.com with token "tok". With `accept` unset it
+    /// rejects the token through a 334 error challenge, as Gmail does.
+    /// After a login it answers every line with "250 upstream".
+    fn fake_smtp_upstream(accept: bool) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            send(&mut writer, "220 fake ESMTP").unwrap();
+
+            assert_eq!(
+                read_line(&mut reader).unwrap().unwrap(),
+                "EHLO client.example"
+            );
+            send(&mut writer, "250-fake").unwrap();
+            send(&mut writer, "250 AUTH XOAUTH2").unwrap();
+
+            let auth = read_line(&mut reader).unwrap().unwrap();
+            let b64 = auth.strip_prefix("AUTH XOAUTH2 ").unwrap();
+            let expected = "user=alice@example.com\x01auth=Bearer tok\x01\x01";
+            assert_eq!(STANDARD.decode(b64).unwrap(), expected.as_bytes());
+            if !accept {
+                let error =
+                    r#"{"status":"400","schemes":"Bearer","scope":"https://mail.google.com/"}"#;
+                send(&mut writer, &format!("334 {}", STANDARD.encode(error))).unwrap();
+                assert_eq!(read_line(&mut reader).unwrap().unwrap(), "");
+                send(&mut writer, "535 5.7.8 Username and Password not accepted").unwrap();
+                return;
+            }
+            send(&mut writer, "235 2.7.0 Accepted").unwrap();
+            while let Ok(Some(_)) = read_line(&mut reader) {
+                send(&mut writer, "250 upstream").unwrap();
+            }
+        });
+        addr
+    }
+
+    /// Start the proxy in front of a fake IMAP upstream and connect to it,
+    /// past the greeting.
+    fn connect() -> (BufReader<TcpStream>, TcpStream) {
+        let (mut reader, writer) = connect_to(Protocol::Imap, fake_upstream());
+        assert!(line(&mut reader).starts_with("* OK"));
+        (reader, writer)
+    }
+
+    /// Start the proxy in front of a fake SMTP upstream and connect to it,
+    /// past the greeting and EHLO.
+    fn connect_smtp(accept: bool) -> (BufReader<TcpStream>, TcpStream) {
+        let (mut reader, mut writer) = connect_to(Protocol::Smtp, fake_smtp_upstream(accept));
+        assert!(line(&mut reader).starts_with("220 "));
+        write(&mut writer, "EHLO client.example\r\n");
+        assert_eq!(line(&mut reader), "250-tokey mailproxy");
+        assert_eq!(line(&mut reader), "250 AUTH PLAIN LOGIN");
+        (reader, writer)
+    }
+
+    fn connect_to(protocol: Protocol, upstream: SocketAddr) -> (BufReader<TcpStream>, TcpStream) {
+        let listen = free_addr();
+        let route = Route {
+            protocol,
+            listen,
+            upstream: upstream.to_string(),
+            tls: false,
+        };
+        let login: LoginSource =
+            Arc::new(|| Ok(("alice@example.com".to_string(), "tok".to_string())));
+        thread::spawn(move || serve(vec![route], "secret".to_string(), login));
+
+        let stream = (0..100)
+            .find_map(|_| {
+                TcpStream::connect(listen)
+                    .inspect_err(|_| thread::sleep(Duration::from_millis(20)))
+                    .ok()
+            })
+            .unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        let writer = stream.try_clone().unwrap();
+        (BufReader::new(stream), writer)
+    }
+
+    fn line(reader: &mut BufReader<TcpStream>) -> String {
+        read_line(reader).unwrap().unwrap()
+    }
+
+    fn write(writer: &mut TcpStream, data: &str) {
+        writer.write_all(data.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn login_with_literals_reaches_upstream() {
+        let (mut reader, mut writer) = connect();
+        write(&mut writer, "a1 LOGIN {5}\r\n");
+        assert!(line(&mut reader).starts_with('+'));
+        write(&mut writer, "alice {6}\r\n");
+        assert!(line(&mut reader).starts_with('+'));
+        write(&mut writer, "secret\r\n");
+        assert_eq!(line(&mut reader), "a1 OK [CAPABILITY IMAP4rev1 IDLE] done");
+
+        // The rest of the session is relayed
+        write(&mut writer, "a2 SELECT INBOX\r\n");
+        assert_eq!(line(&mut reader), "a2 OK upstream");
+    }
+
+    #[test]
+    fn authenticate_plain_reaches_upstream() {
+        let (mut reader, mut writer) = connect();
+        write(&mut writer, "a1 AUTHENTICATE PLAIN\r\n");
+        assert!(line(&mut reader).starts_with('+'));
+        write(
+            &mut writer,
+            &format!("{}\r\n", STANDARD.encode("\0alice\0secret")),
+        );
+        assert_eq!(line(&mut reader), "a1 OK [CAPABILITY IMAP4rev1 IDLE] done");
+    }
+
+    #[test]
+    fn wrong_password_is_rejected_locally() {
+        let (mut reader, mut writer) = connect();
+        write(&mut writer, "a1 LOGIN alice wrong\r\n");
+        assert!(line(&mut reader).starts_with("a1 NO [AUTHENTICATIONFAILED]"));
+        write(&mut writer, "a2 LOGIN alice secret\r\n");
+        assert!(line(&mut reader).starts_with("a2 OK"));
+    }
+
+    #[test]
+    fn oversized_literals_are_refused() {
+        let (mut reader, mut writer) = connect();
+        write(&mut writer, "a1 LOGIN {100000}\r\n");
+        assert_eq!(line(&mut reader), "a1 BAD Literal too large");
+        // The connection is still usable
+        write(&mut writer, "a2 LOGIN alice secret\r\n");
+        assert!(line(&mut reader).starts_with("a2 OK"));
+    }
+
+    #[test]
+    fn oversized_non_synchronizing_literals_close_the_connection() {
+        let (mut reader, mut writer) = connect();
+        write(&mut writer, "a1 LOGIN {100000+}\r\n");
+        assert_eq!(line(&mut reader), "a1 BAD Literal too large");
+        assert_eq!(line(&mut reader), "* BYE Literal too large");
+        assert!(read_line(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn smtp_auth_plain_with_initial_response_reaches_upstream() {
+        let (mut reader, mut writer) = connect_smtp(true);
+        let response = STANDARD.encode("\0alice\0secret");
+        write(&mut writer, &format!("AUTH PLAIN {}\r\n", response));
+        assert_eq!(line(&mut reader), "235 2.7.0 Authentication successful");
+
+        // The rest of the session is relayed
+        write(&mut writer, "MAIL FROM:<alice@example.com>\r\n");
+        assert_eq!(line(&mut reader), "250 upstream");
+    }
+
+    #[test]
+    fn smtp_auth_login_reaches_upstream() {
+        let (mut reader, mut writer) = connect_smtp(true);
+        write(&mut writer, "AUTH LOGIN\r\n");
+        assert_eq!(line(&mut reader), "334 VXNlcm5hbWU6");
+        write(&mut writer, &format!("{}\r\n", STANDARD.encode("alice")));
+        assert_eq!(line(&mut reader), "334 UGFzc3dvcmQ6");
+        write(&mut writer, &format!("{}\r\n", STANDARD.encode("secret")));
+        assert_eq!(line(&mut reader), "235 2.7.0 Authentication successful");
+    }
+
+    #[test]
+    fn smtp_wrong_password_is_rejected_locally() {
+        let (mut reader, mut writer) = connect_smtp(true);
+        let response = STANDARD.encode("\0alice\0wrong");
+        write(&mut writer, &format!("AUTH PLAIN {}\r\n", response));
+        assert_eq!(
+            line(&mut reader),
+            "535 5.7.8 Authentication credentials invalid"
+        );
+        // Nothing was sent upstream, so the client can try again
+        write(&mut writer, "AUTH PLAIN\r\n");
+        assert_eq!(line(&mut reader), "334 ");
+        write(
+            &mut writer,
+            &format!("{}\r\n", STANDARD.encode("\0alice\0secret")),
+        );
+        assert_eq!(line(&mut reader), "235 2.7.0 Authentication successful");
+    }
+
+    #[test]
+    fn smtp_rejected_token_fails_the_login() {
+        let (mut reader, mut writer) = connect_smtp(false);
+        let response = STANDARD.encode("\0alice\0secret");
+        write(&mut writer, &format!("AUTH PLAIN {}\r\n", response));
+        assert_eq!(
+            line(&mut reader),
+            "454 4.7.0 Upstream authentication failed"
+        );
+        assert!(read_line(&mut reader).unwrap().is_none());
+    }
+}
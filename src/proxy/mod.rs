@@ -0,0 +1,30 @@
+pub mod http;
+pub mod mail;
+
+use std::net::ToSocketAddrs;
+
+use reqwest::Url;
+
+/// Whether every address `host:port` resolves to is loopback, i.e. traffic
+/// to it never leaves the machine.
+fn resolves_to_loopback(addr: &str) -> bool {
+    match addr.to_socket_addrs() {
+        Ok(mut addrs) => addrs.all(|a| a.ip().is_loopback()),
+        Err(_) => false,
+    }
+}
+
+/// Whether credentials may be sent to `url`: over TLS, or in plain http
+/// only to this machine (e.g. a local mock server).
+pub(crate) fn secure_transport(url: &Url) -> bool {
+    let addr = format!(
+        "{}:{}",
+        url.host_str().unwrap_or_default(),
+        url.port_or_known_default().unwrap_or(0)
+    );
+    match url.scheme() {
+        "https" => true,
+        "http" => resolves_to_loopback(&addr),
+        _ => false,
+    }
+}
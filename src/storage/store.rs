@@ -1,5 +1,6 @@
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Result};
@@ -8,6 +9,9 @@ use super::types::*;
 
 const CONFIG_FILE: &str = "config.toml";
 const CREDENTIALS_FILE: &str = "credentials.json";
+/// Fields that identify what a credential was granted. Down-scoped tokens
+/// minted from it are only reused while these stay the same.
+const GRANT_FIELDS: &[&str] = &["refresh_token", "scopes", "client_id"];
 
 pub struct CredentialStore {
     config_dir: PathBuf,
@@ -20,7 +24,11 @@ impl CredentialStore {
         let config_dir = dirs::config_dir()
             .context("Could not determine config directory")?
             .join("tokey");
+        Self::with_dir(config_dir)
+    }
 
+    /// A store kept in `config_dir` instead of the user's config directory.
+    pub fn with_dir(config_dir: PathBuf) -> Result<Self> {
         fs::create_dir_all(&config_dir)?;
 
         Ok(Self {
@@ -78,6 +86,15 @@ impl CredentialStore {
         Ok(())
     }
 
+    pub fn credentials_path(&self) -> &Path {
+        &self.credentials_path
+    }
+
+    /// Location for re-fetchable data such as signing keys; not secret.
+    pub fn cache_path(&self, name: &str) -> PathBuf {
+        self.config_dir.join("cache").join(name)
+    }
+
     // -- Credential key helper ------------------------------------------------
 
     fn cred_key(provider: &str, label: &str) -> String {
@@ -94,14 +111,9 @@ impl CredentialStore {
         credential: StoredCredential,
     ) -> Result<()> {
         let mut config = self.load_config()?;
-        let provider_config = config
-            .providers
-            .entry(provider.to_string())
-            .or_default();
+        let provider_config = config.providers.entry(provider.to_string()).or_default();
 
-        provider_config
-            .accounts
-            .insert(label.to_string(), account);
+        provider_config.accounts.insert(label.to_string(), account);
 
         // Set as default if it's the first account
         if provider_config.default_account.is_none() {
@@ -111,9 +123,10 @@ impl CredentialStore {
         self.save_config(&config)?;
 
         let mut creds = self.load_credentials()?;
-        creds
-            .credentials
-            .insert(Self::cred_key(provider, label), credential);
+        let key = Self::cred_key(provider, label);
+        // A new sign-in: nothing minted before it applies any more
+        creds.scoped_tokens.remove(&key);
+        creds.credentials.insert(key, credential);
         self.save_credentials(&creds)?;
 
         Ok(())
@@ -128,6 +141,8 @@ impl CredentialStore {
             .context(format!("No credentials found for {}/{}", provider, label))
     }
 
+    /// Replace an account's credential. Cached down-scoped tokens survive
+    /// only if the grant is unchanged, as after a plain access-token refresh.
     pub fn update_credential(
         &self,
         provider: &str,
@@ -135,9 +150,16 @@ impl CredentialStore {
         credential: StoredCredential,
     ) -> Result<()> {
         let mut creds = self.load_credentials()?;
-        creds
-            .credentials
-            .insert(Self::cred_key(provider, label), credential);
+        let key = Self::cred_key(provider, label);
+        let same_grant = creds.credentials.get(&key).is_some_and(|old| {
+            GRANT_FIELDS
+                .iter()
+                .all(|f| old.fields.get(*f) == credential.fields.get(*f))
+        });
+        if !same_grant {
+            creds.scoped_tokens.remove(&key);
+        }
+        creds.credentials.insert(key, credential);
         self.save_credentials(&creds)?;
         Ok(())
     }
@@ -151,14 +173,82 @@ impl CredentialStore {
         Ok(())
     }
 
+    pub fn get_secrets(&self, provider: &str, label: &str) -> Result<HashMap<String, String>> {
+        let creds = self.load_credentials()?;
+        Ok(creds
+            .secrets
+            .get(&Self::cred_key(provider, label))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    pub fn set_secret(&self, provider: &str, label: &str, name: &str, value: &str) -> Result<()> {
+        let mut creds = self.load_credentials()?;
+        creds
+            .secrets
+            .entry(Self::cred_key(provider, label))
+            .or_default()
+            .insert(name.to_string(), value.to_string());
+        self.save_credentials(&creds)?;
+        Ok(())
+    }
+
+    /// Returns false if no such secret existed.
+    pub fn remove_secret(&self, provider: &str, label: &str, name: &str) -> Result<bool> {
+        let mut creds = self.load_credentials()?;
+        let key = Self::cred_key(provider, label);
+        let removed = match creds.secrets.get_mut(&key) {
+            Some(map) => map.remove(name).is_some(),
+            None => false,
+        };
+        if creds.secrets.get(&key).is_some_and(|m| m.is_empty()) {
+            creds.secrets.remove(&key);
+        }
+        self.save_credentials(&creds)?;
+        Ok(removed)
+    }
+
+    pub fn get_scoped_token(
+        &self,
+        provider: &str,
+        label: &str,
+        scope_key: &str,
+    ) -> Result<Option<ScopedToken>> {
+        let creds = self.load_credentials()?;
+        Ok(creds
+            .scoped_tokens
+            .get(&Self::cred_key(provider, label))
+            .and_then(|m| m.get(scope_key))
+            .cloned())
+    }
+
+    pub fn set_scoped_token(
+        &self,
+        provider: &str,
+        label: &str,
+        scope_key: &str,
+        token: ScopedToken,
+    ) -> Result<()> {
+        let mut creds = self.load_credentials()?;
+        let now = Self::now();
+        let tokens = creds
+            .scoped_tokens
+            .entry(Self::cred_key(provider, label))
+            .or_default();
+        // Drop expired entries so the cache doesn't grow forever
+        tokens.retain(|_, t| t.expires_at > now);
+        tokens.insert(scope_key.to_string(), token);
+        self.save_credentials(&creds)?;
+        Ok(())
+    }
+
     pub fn remove_account(&self, provider: &str, label: &str) -> Result<()> {
         let mut config = self.load_config()?;
         if let Some(provider_config) = config.providers.get_mut(provider) {
             provider_config.accounts.remove(label);
 
             if provider_config.default_account.as_deref() == Some(label) {
-                provider_config.default_account =
-                    provider_config.accounts.keys().next().cloned();
+                provider_config.default_account = provider_config.accounts.keys().next().cloned();
             }
 
             // Remove provider entry if no accounts left
@@ -170,6 +260,8 @@ impl CredentialStore {
 
         let mut creds = self.load_credentials()?;
         creds.credentials.remove(&Self::cred_key(provider, label));
+        creds.secrets.remove(&Self::cred_key(provider, label));
+        creds.scoped_tokens.remove(&Self::cred_key(provider, label));
         self.save_credentials(&creds)?;
 
         // Remove chrome profile dir if it exists
@@ -189,7 +281,11 @@ impl CredentialStore {
             .context(format!("Provider '{}' not found", provider))?;
 
         if !provider_config.accounts.contains_key(label) {
-            anyhow::bail!("Account '{}' not found under provider '{}'", label, provider);
+            anyhow::bail!(
+                "Account '{}' not found under provider '{}'",
+                label,
+                provider
+            );
         }
 
         provider_config.default_account = Some(label.to_string());
@@ -209,16 +305,146 @@ impl CredentialStore {
                 if provider_config.accounts.contains_key(label) {
                     Ok(label.to_string())
                 } else {
-                    anyhow::bail!("Account '{}' not found under provider '{}'", label, provider)
+                    anyhow::bail!(
+                        "Account '{}' not found under provider '{}'",
+                        label,
+                        provider
+                    )
                 }
             }
-            None => provider_config
-                .default_account
-                .clone()
-                .context(format!("No default account set for provider '{}'", provider)),
+            None => provider_config.default_account.clone().context(format!(
+                "No default account set for provider '{}'",
+                provider
+            )),
         }
     }
 
+    pub fn get_account(&self, provider: &str, label: &str) -> Result<Account> {
+        let config = self.load_config()?;
+        config
+            .providers
+            .get(provider)
+            .and_then(|p| p.accounts.get(label))
+            .cloned()
+            .context(format!(
+                "Account '{}' not found under provider '{}'",
+                label, provider
+            ))
+    }
+
+    /// Effective Chrome settings for an account: provider section overlaid
+    /// with the account's own overrides.
+    pub fn chrome_config(&self, provider: &str, label: &str) -> Result<ChromeConfig> {
+        self.layered_config(
+            provider,
+            label,
+            |p| p.chrome.as_ref(),
+            |a| a.chrome.as_ref(),
+        )
+    }
+
+    /// Effective static-key settings for an account.
+    pub fn key_config(&self, provider: &str, label: &str) -> Result<StaticKeyConfig> {
+        self.layered_config(provider, label, |p| p.key.as_ref(), |a| a.key.as_ref())
+    }
+
+    /// Effective command settings for an `exec` account.
+    pub fn command_config(&self, provider: &str, label: &str) -> Result<CommandConfig> {
+        self.layered_config(
+            provider,
+            label,
+            |p| p.command.as_ref(),
+            |a| a.command.as_ref(),
+        )
+    }
+
+    /// Effective service-account settings for a `google-sa` account.
+    pub fn service_account_config(
+        &self,
+        provider: &str,
+        label: &str,
+    ) -> Result<ServiceAccountConfig> {
+        self.layered_config(
+            provider,
+            label,
+            |p| p.service_account.as_ref(),
+            |a| a.service_account.as_ref(),
+        )
+    }
+
+    /// One settings section for an account: the provider's section, if
+    /// any, with the account's own merged on top.
+    fn layered_config<T: Layered>(
+        &self,
+        provider: &str,
+        label: &str,
+        provider_section: impl Fn(&ProviderConfig) -> Option<&T>,
+        account_section: impl Fn(&Account) -> Option<&T>,
+    ) -> Result<T> {
+        let config = self.load_config()?;
+        let Some(provider_config) = config.providers.get(provider) else {
+            return Ok(T::default());
+        };
+        let account = provider_config.accounts.get(label);
+        Ok(provider_section(provider_config)
+            .into_iter()
+            .chain(account.and_then(account_section))
+            .fold(T::default(), |acc, section| acc.merge(section)))
+    }
+
+    /// Apply `f` to an existing account's metadata and save the config.
+    pub fn update_account(
+        &self,
+        provider: &str,
+        label: &str,
+        f: impl FnOnce(&mut Account),
+    ) -> Result<()> {
+        let mut config = self.load_config()?;
+        let account = config
+            .providers
+            .get_mut(provider)
+            .and_then(|p| p.accounts.get_mut(label))
+            .context(format!(
+                "Account '{}' not found under provider '{}'",
+                label, provider
+            ))?;
+        f(account);
+        self.save_config(&config)?;
+        Ok(())
+    }
+
+    /// Record a failed refresh. Once an account needs reauth it stays that
+    /// way until `clear_health`, even if later failures look transient.
+    pub fn record_refresh_failure(
+        &self,
+        provider: &str,
+        label: &str,
+        needs_reauth: bool,
+        error: &str,
+    ) -> Result<()> {
+        let now = Self::now();
+        self.update_account(provider, label, |acct| {
+            let health = acct.health.get_or_insert_with(AccountHealth::default);
+            health.state = if needs_reauth || health.state == HealthState::NeedsReauth {
+                HealthState::NeedsReauth
+            } else {
+                HealthState::RefreshFailing
+            };
+            health.last_error = Some(error.to_string());
+            health.failures += 1;
+            health.failing_since.get_or_insert(now);
+        })
+    }
+
+    /// Forget recorded failures after a successful refresh or reauth.
+    pub fn clear_health(&self, provider: &str, label: &str) -> Result<()> {
+        // Skip the config rewrite in the common case of nothing to clear
+        if self.get_account(provider, label)?.health.is_none() {
+            return Ok(());
+        }
+        self.update_account(provider, label, |acct| acct.health = None)
+    }
+
     pub fn is_expired(&self, provider: &str, label: &str, max_age_days: u64) -> Result<bool> {
         let cred = self.get_credential(provider, label)?;
         let age = Self::now() - cred.created_at;
@@ -238,3 +464,88 @@ impl CredentialStore {
         self.config_dir.join("chrome-profile-tmp")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::temp_store;
+
+    fn credential(refresh_token: &str, scopes: &str, access_token: &str) -> StoredCredential {
+        let fields = [
+            ("refresh_token", refresh_token),
+            ("scopes", scopes),
+            ("access_token", access_token),
+        ];
+        StoredCredential {
+            fields: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            created_at: CredentialStore::now(),
+            last_validated: None,
+        }
+    }
+
+    fn cache_token(store: &CredentialStore) {
+        let token = ScopedToken {
+            access_token: "scoped".to_string(),
+            expires_at: CredentialStore::now() + 3600,
+        };
+        store
+            .set_scoped_token("google", "work", "gmail", token)
+            .unwrap();
+    }
+
+    fn cached(store: &CredentialStore) -> bool {
+        store
+            .get_scoped_token("google", "work", "gmail")
+            .unwrap()
+            .is_some()
+    }
+
+    #[test]
+    fn scoped_tokens_follow_the_grant() {
+        let store = temp_store();
+        store
+            .save_account(
+                "google",
+                "work",
+                Account::default(),
+                credential("rt1", "a", "at1"),
+            )
+            .unwrap();
+        cache_token(&store);
+
+        // A refresh only replaces the access token
+        store
+            .update_credential("google", "work", credential("rt1", "a", "at2"))
+            .unwrap();
+        assert!(cached(&store));
+
+        // More scopes granted
+        store
+            .update_credential("google", "work", credential("rt1", "a b", "at3"))
+            .unwrap();
+        assert!(!cached(&store));
+
+        // A new refresh token, e.g. after reauth
+        cache_token(&store);
+        store
+            .update_credential("google", "work", credential("rt2", "a b", "at4"))
+            .unwrap();
+        assert!(!cached(&store));
+
+        // Re-adding the label
+        cache_token(&store);
+        store
+            .save_account(
+                "google",
+                "work",
+                Account::default(),
+                credential("rt2", "a b", "at4"),
+            )
+            .unwrap();
+        assert!(!cached(&store));
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
@@ -7,6 +8,71 @@ use serde::{Deserialize, Serialize};
 pub struct Config {
     #[serde(default)]
     pub providers: HashMap<String, ProviderConfig>,
+    /// Which account answers `tokey git-credential` for which remote; the
+    /// first matching rule wins.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub git_credentials: Vec<GitCredentialRule>,
+    /// Which account answers `tokey docker-credential` for which registry.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docker_credentials: Vec<DockerCredentialRule>,
+    /// Which account `tokey proxy` authenticates requests to which host as.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub http_proxy: Vec<HttpProxyRule>,
+}
+
+/// Maps git remotes to an account for the git credential helper.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GitCredentialRule {
+    /// Host as git reports it, including a non-default port.
+    pub host: String,
+    /// Protocol to match (default: https).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+    /// Only match repository paths under this prefix. git only sends the
+    /// path with `credential.useHttpPath` set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(flatten)]
+    pub login: HelperLogin,
+}
+
+/// Maps registry hosts to an account for the Docker credential helper.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DockerCredentialRule {
+    /// Registry host, with a non-default port (e.g. "registry.corp.example").
+    pub registry: String,
+    #[serde(flatten)]
+    pub login: HelperLogin,
+}
+
+/// Maps a host requested through the HTTP proxy to an account.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HttpProxyRule {
+    /// Host as clients address it, with a non-default port (e.g. "slack.com").
+    pub host: String,
+    /// Account as provider/label (label defaults to the provider's default).
+    pub account: String,
+    /// Base URL requests are forwarded to (default: https://<host>). Plain
+    /// http:// is only allowed for loopback hosts, e.g. a local mock.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<String>,
+}
+
+/// The account and fields a credential helper answers with.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HelperLogin {
+    /// Account as provider/label (label defaults to the provider's default).
+    pub account: String,
+    /// Fixed username to send (e.g. "oauth2" or "x-access-token").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Credential field holding the username, when not fixed (default: "username").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username_field: Option<String>,
+    /// Credential field sent as the password (default: the first of
+    /// "password", "token", "access_token").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_field: Option<String>,
 }
 
 /// Per-provider config section.
@@ -15,15 +81,405 @@ pub struct ProviderConfig {
     pub default_account: Option<String>,
     #[serde(default)]
     pub accounts: HashMap<String, Account>,
+    /// Chrome launch settings shared by every account of this provider.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chrome: Option<ChromeConfig>,
+    /// Defaults for manually entered secrets (`static` provider).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<StaticKeyConfig>,
+    /// Defaults for command-backed credentials (`exec` provider).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<CommandConfig>,
+    /// OAuth client used by every account of this provider.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oauth_client: Option<OAuthClientConfig>,
+    /// Defaults for service-account token minting (`google-sa` provider).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_account: Option<ServiceAccountConfig>,
+    /// Variable names used by `tokey env` for credential fields, in place
+    /// of the upper-cased field name (e.g. `access_token = "TOKEN"`).
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
 }
 
-/// Metadata for a single account (no secrets).
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Settings that can be given for a whole provider and overridden per
+/// account.
+pub trait Layered: Clone + Default {
+    /// Layer `other` on top of `self`.
+    fn merge(&self, other: &Self) -> Self;
+}
+
+/// Metadata for a single account (no secrets). Metadata fields default so
+/// an account's settings can be written to config.toml before `tokey add`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Account {
+    #[serde(default)]
     pub display_name: String,
+    #[serde(default)]
     pub provider_id: String,
+    #[serde(default)]
     pub user_id: String,
+    #[serde(default)]
     pub created_at: u64,
+    /// Per-account Chrome overrides (take precedence over the provider section).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chrome: Option<ChromeConfig>,
+    /// Scripted sign-in, replayed by headless refresh when the session has expired.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub login: Vec<LoginStep>,
+    /// Per-account settings for manually entered secrets.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<StaticKeyConfig>,
+    /// Command that produces this account's credentials.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<CommandConfig>,
+    /// OAuth client for this account (overrides the provider's).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oauth_client: Option<OAuthClientConfig>,
+    /// Service-account settings for this account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_account: Option<ServiceAccountConfig>,
+    /// Scopes chosen with `add --scopes` and extended by `scopes add`,
+    /// requested again by `rotate` and re-adds. Empty means all supported.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+    /// When the credential was last replaced with `tokey rotate`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotated_at: Option<u64>,
+    /// ADC files written by `tokey export-adc --keep-updated`, rewritten
+    /// whenever the refresh token changes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub adc_exports: Vec<PathBuf>,
+    /// Verified identity claims from the provider's OpenID Connect id_token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claims: Option<IdentityClaims>,
+    /// Outcome of recent refreshes. Absent while refreshes succeed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health: Option<AccountHealth>,
+}
+
+/// Claims from a verified OpenID Connect id_token.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IdentityClaims {
+    /// Stable, never-reused account id (unlike the email address).
+    pub sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    /// Google Workspace domain; absent for consumer accounts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hd: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Coarse credential health, as shown by `status` and `list`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HealthState {
+    #[default]
+    Ok,
+    /// Still works, but will need attention soon (e.g. a static key's expiry).
+    Expiring,
+    /// Refresh is failing, possibly transiently (network, provider outage).
+    RefreshFailing,
+    /// The grant or session is gone; only `tokey reauth` will fix it.
+    NeedsReauth,
+}
+
+impl std::fmt::Display for HealthState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            HealthState::Ok => "ok",
+            HealthState::Expiring => "expiring",
+            HealthState::RefreshFailing => "refresh-failing",
+            HealthState::NeedsReauth => "needs-reauth",
+        })
+    }
+}
+
+/// Persisted record of failed refreshes for one account.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccountHealth {
+    pub state: HealthState,
+    /// Error message from the most recent failed refresh.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Consecutive failed refreshes.
+    #[serde(default)]
+    pub failures: u32,
+    /// When the current run of failures started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failing_since: Option<u64>,
+}
+
+/// Settings for API keys and other secrets entered by hand.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StaticKeyConfig {
+    /// Field names prompted for on add/rotate (default: ["token"]).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<String>,
+    /// Expiry date of the current key, as YYYY-MM-DD.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+    /// Start warning this many days before expiry (default 14).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warn_days: Option<u64>,
+    /// HTTP request used to check that the key still works.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probe: Option<HttpProbe>,
+}
+
+impl Layered for StaticKeyConfig {
+    /// Layer `other` on top of `self`; set values in `other` win.
+    fn merge(&self, other: &Self) -> Self {
+        StaticKeyConfig {
+            fields: if other.fields.is_empty() {
+                self.fields.clone()
+            } else {
+                other.fields.clone()
+            },
+            expires: other.expires.clone().or_else(|| self.expires.clone()),
+            warn_days: other.warn_days.or(self.warn_days),
+            probe: other.probe.clone().or_else(|| self.probe.clone()),
+        }
+    }
+}
+
+/// A bring-your-own OAuth client. Either `client_id` + `client_secret`, or
+/// `client_secret_file` pointing at a downloaded client_secret.json.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OAuthClientConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_secret_file: Option<PathBuf>,
+    /// Loopback redirect URI (default http://localhost:8484/callback).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect_uri: Option<String>,
+    /// Token endpoint override.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_uri: Option<String>,
+    /// Revocation endpoint override.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoke_uri: Option<String>,
+    /// Signing keys used to verify id_tokens (override for tests).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwks_uri: Option<String>,
+}
+
+impl Layered for OAuthClientConfig {
+    /// Layer `other` on top of `self`; set values in `other` win. A client
+    /// id in `other` replaces the whole client identity of `self`.
+    fn merge(&self, other: &Self) -> Self {
+        let identity = if other.client_id.is_some() || other.client_secret_file.is_some() {
+            other
+        } else {
+            self
+        };
+        OAuthClientConfig {
+            client_id: identity.client_id.clone(),
+            client_secret: identity.client_secret.clone(),
+            client_secret_file: identity.client_secret_file.clone(),
+            redirect_uri: other
+                .redirect_uri
+                .clone()
+                .or_else(|| self.redirect_uri.clone()),
+            token_uri: other.token_uri.clone().or_else(|| self.token_uri.clone()),
+            revoke_uri: other.revoke_uri.clone().or_else(|| self.revoke_uri.clone()),
+            jwks_uri: other.jwks_uri.clone().or_else(|| self.jwks_uri.clone()),
+        }
+    }
+}
+
+impl OAuthClientConfig {
+    pub fn has_client(&self) -> bool {
+        self.client_id.is_some() || self.client_secret_file.is_some()
+    }
+}
+
+/// How a `google-sa` account mints tokens. The key itself is imported into
+/// credentials.json; these settings can be changed without re-importing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServiceAccountConfig {
+    /// JSON key file to import on `tokey add` (prompted for if unset).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_file: Option<PathBuf>,
+    /// Scopes (aliases or URLs) to request (default: cloud-platform).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+    /// Workspace user to impersonate via domain-wide delegation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    /// Token endpoint override (also used as the JWT audience).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_uri: Option<String>,
+}
+
+impl Layered for ServiceAccountConfig {
+    /// Layer `other` on top of `self`; set values in `other` win.
+    fn merge(&self, other: &Self) -> Self {
+        ServiceAccountConfig {
+            key_file: other.key_file.clone().or_else(|| self.key_file.clone()),
+            scopes: if other.scopes.is_empty() {
+                self.scopes.clone()
+            } else {
+                other.scopes.clone()
+            },
+            subject: other.subject.clone().or_else(|| self.subject.clone()),
+            token_uri: other.token_uri.clone().or_else(|| self.token_uri.clone()),
+        }
+    }
+}
+
+/// How the `exec` provider obtains credentials from another CLI.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CommandConfig {
+    /// Shell command to run (via `sh -c`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<String>,
+    /// How to parse stdout (default: value).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<CommandOutputFormat>,
+    /// Field name for `value` output (default: "token").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// JSON pointer selecting the object to read, e.g. "/Credentials".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json_pointer: Option<String>,
+    /// Treat the output as valid for this many seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u64>,
+    /// Output field holding the expiry (unix seconds, RFC 3339, or a lifetime
+    /// such as "3600" or "1h").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiry_field: Option<String>,
+}
+
+impl Layered for CommandConfig {
+    /// Layer `other` on top of `self`; set values in `other` win.
+    fn merge(&self, other: &Self) -> Self {
+        CommandConfig {
+            run: other.run.clone().or_else(|| self.run.clone()),
+            format: other.format.or(self.format),
+            field: other.field.clone().or_else(|| self.field.clone()),
+            json_pointer: other
+                .json_pointer
+                .clone()
+                .or_else(|| self.json_pointer.clone()),
+            ttl_secs: other.ttl_secs.or(self.ttl_secs),
+            expiry_field: other
+                .expiry_field
+                .clone()
+                .or_else(|| self.expiry_field.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandOutputFormat {
+    /// A JSON object; each top-level key becomes a field.
+    Json,
+    /// KEY=VALUE lines (an optional `export ` prefix is ignored).
+    Env,
+    /// The whole of stdout, trimmed, as a single field.
+    #[default]
+    Value,
+}
+
+/// A validation request. `{{field}}` placeholders in the URL and header
+/// values are replaced with credential fields.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpProbe {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    /// Expected status code (default: any 2xx).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect_status: Option<u16>,
+}
+
+/// One step of a scripted browser login. `secret` names refer to values
+/// stored with `tokey secret set`, never to literal passwords.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum LoginStep {
+    /// Load a URL and wait for navigation to finish.
+    Navigate { url: String },
+    /// Type into an input, either a literal `value` or a stored `secret`.
+    Fill {
+        selector: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        value: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        secret: Option<String>,
+    },
+    /// Click an element.
+    Click { selector: String },
+    /// Wait until an element appears.
+    WaitFor {
+        selector: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_secs: Option<u64>,
+    },
+    /// Type the current TOTP code, from either a stored `secret` (base32
+    /// seed or otpauth:// URI) or a `tokey add totp` account.
+    Totp {
+        selector: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        secret: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        account: Option<String>,
+    },
+    /// Pause for a fixed number of seconds.
+    Sleep { secs: u64 },
+}
+
+/// Chrome/Chromium launch settings for browser-based providers.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChromeConfig {
+    /// Path to the Chrome/Chromium binary (auto-detected if unset).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub executable: Option<PathBuf>,
+    /// Extra command-line flags, e.g. "--no-sandbox" or "--lang=en-US".
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Proxy server passed as --proxy-server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// User agent override (some IdPs block "HeadlessChrome").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Seconds to keep an idle browser connection open (default 30).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_secs: Option<u64>,
+    /// Extra environment variables for the Chrome process.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+}
+
+impl Layered for ChromeConfig {
+    /// Layer `other` on top of `self`. Scalars in `other` win, args are
+    /// appended and env vars are merged.
+    fn merge(&self, other: &Self) -> Self {
+        let mut env = self.env.clone();
+        env.extend(other.env.clone());
+
+        ChromeConfig {
+            executable: other.executable.clone().or_else(|| self.executable.clone()),
+            args: self.args.iter().chain(&other.args).cloned().collect(),
+            proxy: other.proxy.clone().or_else(|| self.proxy.clone()),
+            user_agent: other.user_agent.clone().or_else(|| self.user_agent.clone()),
+            idle_timeout_secs: other.idle_timeout_secs.or(self.idle_timeout_secs),
+            env,
+        }
+    }
 }
 
 /// Top-level credentials file. Stored as credentials.json with 0600 perms.
@@ -31,6 +487,21 @@ pub struct Account {
 pub struct CredentialsFile {
     #[serde(default)]
     pub credentials: HashMap<String, StoredCredential>,
+    /// Named secrets used by login scripts, keyed as "provider/label".
+    /// Kept apart from `credentials` so refreshes never drop them.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub secrets: HashMap<String, HashMap<String, String>>,
+    /// Down-scoped access tokens keyed as "provider/label", then by the
+    /// space-joined, sorted scope set.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub scoped_tokens: HashMap<String, HashMap<String, ScopedToken>>,
+}
+
+/// An access token limited to a subset of an account's granted scopes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScopedToken {
+    pub access_token: String,
+    pub expires_at: u64,
 }
 
 /// A single stored credential keyed as "provider/label".
@@ -48,4 +519,6 @@ pub struct AuthResult {
     pub provider_id: String,
     pub user_id: String,
     pub credential: StoredCredential,
+    /// Verified identity claims, for providers that sign in with OpenID Connect.
+    pub claims: Option<IdentityClaims>,
 }
@@ -0,0 +1,40 @@
+//! Helpers shared by unit tests.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::storage::CredentialStore;
+
+/// A fresh, empty directory under the system temp dir.
+pub fn temp_dir() -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "tokey-test-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A credential store in a fresh directory.
+pub fn temp_store() -> CredentialStore {
+    CredentialStore::with_dir(temp_dir()).unwrap()
+}
+
+/// Serve one canned response per request, in order, on a loopback port,
+/// then stop listening. Returns the base URL.
+pub fn mock_http(responses: Vec<(u16, String)>) -> String {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+    thread::spawn(move || {
+        for (status, body) in responses {
+            let Ok(request) = server.recv() else { return };
+            let _ =
+                request.respond(tiny_http::Response::from_string(body).with_status_code(status));
+        }
+    });
+    url
+}
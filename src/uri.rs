@@ -0,0 +1,265 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+
+use crate::storage::CredentialStore;
+
+pub const SCHEME: &str = "tokey://";
+
+/// A post-processing step named in a reference's query string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transform {
+    /// Standard base64 with padding.
+    Base64,
+    /// URL-safe base64 without padding.
+    Base64Url,
+    /// Percent-encode for use inside a URL.
+    UrlEncode,
+    /// A JSON string literal, quotes included.
+    Json,
+    /// Strip leading and trailing whitespace.
+    Trim,
+}
+
+impl Transform {
+    pub const NAMES: &'static [&'static str] =
+        &["base64", "base64url", "urlencode", "json", "trim"];
+
+    pub fn apply(&self, value: &str) -> String {
+        match self {
+            Transform::Base64 => STANDARD.encode(value),
+            Transform::Base64Url => URL_SAFE_NO_PAD.encode(value),
+            Transform::UrlEncode => urlencoding::encode(value).into_owned(),
+            Transform::Json => serde_json::Value::from(value).to_string(),
+            Transform::Trim => value.trim().to_string(),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Transform::Base64 => "base64",
+            Transform::Base64Url => "base64url",
+            Transform::UrlEncode => "urlencode",
+            Transform::Json => "json",
+            Transform::Trim => "trim",
+        }
+    }
+}
+
+impl FromStr for Transform {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "base64" => Ok(Self::Base64),
+            "base64url" => Ok(Self::Base64Url),
+            "urlencode" => Ok(Self::UrlEncode),
+            "json" => Ok(Self::Json),
+            "trim" => Ok(Self::Trim),
+            _ => anyhow::bail!("Unknown transform '{}' ({})", s, Self::NAMES.join(", ")),
+        }
+    }
+}
+
+/// A reference to one credential field:
+///
+/// ```text
+/// tokey://<provider>/<account>/<field>[?<transform>[&<transform>...]]
+/// tokey://<provider>/<field>[?...]          (the provider's default account)
+/// ```
+///
+/// Segments are percent-decoded (`%2F`, `%20`). The account `default` means
+/// the provider's default account unless an account is actually labelled
+/// `default`. Transforms are applied left to right to the field's value.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SecretRef {
+    pub provider: String,
+    /// None for the provider's default account.
+    pub account: Option<String>,
+    pub field: String,
+    pub transforms: Vec<Transform>,
+}
+
+impl SecretRef {
+    pub fn new(provider: &str, account: Option<&str>, field: &str) -> Self {
+        SecretRef {
+            provider: provider.to_string(),
+            account: account.map(str::to_string),
+            field: field.to_string(),
+            transforms: Vec::new(),
+        }
+    }
+
+    /// Whether `s` is meant as a reference (whether or not it is valid).
+    pub fn is_reference(s: &str) -> bool {
+        s.starts_with(SCHEME)
+    }
+
+    /// The label of the account this reference points at in `store`.
+    pub fn label(&self, store: &CredentialStore) -> Result<String> {
+        match self.account.as_deref() {
+            // "default" falls back to the default account unless it's a real label
+            Some("default") => store
+                .resolve_account(&self.provider, Some("default"))
+                .or_else(|_| store.resolve_account(&self.provider, None)),
+            account => store.resolve_account(&self.provider, account),
+        }
+    }
+
+    /// The field's value with this reference's transforms applied.
+    pub fn transform(&self, value: &str) -> String {
+        self.transforms
+            .iter()
+            .fold(value.to_string(), |v, t| t.apply(&v))
+    }
+}
+
+impl FromStr for SecretRef {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let rest = s
+            .strip_prefix(SCHEME)
+            .with_context(|| format!("'{}' does not start with {}", s, SCHEME))?;
+        let (path, query) = match rest.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (rest, None),
+        };
+
+        let segments = path
+            .split('/')
+            .map(|seg| urlencoding::decode(seg).map(|d| d.into_owned()))
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("'{}' has invalid percent-encoding", s))?;
+        if segments.iter().any(String::is_empty) {
+            anyhow::bail!("'{}' has an empty segment", s);
+        }
+        let (provider, account, field) = match segments.as_slice() {
+            [provider, field] => (provider, None, field),
+            [provider, account, field] => (provider, Some(account.as_str()), field),
+            _ => anyhow::bail!("Expected {}provider[/account]/field, got '{}'", SCHEME, s),
+        };
+
+        let mut reference = SecretRef::new(provider, account, field);
+        if let Some(query) = query.filter(|q| !q.is_empty()) {
+            for name in query.split('&') {
+                reference
+                    .transforms
+                    .push(name.parse().with_context(|| format!("In '{}'", s))?);
+            }
+        }
+        Ok(reference)
+    }
+}
+
+impl fmt::Display for SecretRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", SCHEME, urlencoding::encode(&self.provider))?;
+        if let Some(account) = &self.account {
+            write!(f, "/{}", urlencoding::encode(account))?;
+        }
+        write!(f, "/{}", urlencoding::encode(&self.field))?;
+        for (i, t) in self.transforms.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { '?' } else { '&' }, t.name())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::storage::{Account, StoredCredential};
+    use crate::testing::temp_store;
+
+    fn parse(s: &str) -> SecretRef {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_both_forms() {
+        assert_eq!(
+            parse("tokey://slack/work/token"),
+            SecretRef::new("slack", Some("work"), "token")
+        );
+        assert_eq!(
+            parse("tokey://slack/token"),
+            SecretRef::new("slack", None, "token")
+        );
+    }
+
+    #[test]
+    fn percent_decodes_segments() {
+        assert_eq!(
+            parse("tokey://static/team%2Fci/api%20key"),
+            SecretRef::new("static", Some("team/ci"), "api key")
+        );
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        for s in [
+            "tokey://slack/work/token",
+            "tokey://google/access_token?trim",
+            "tokey://static/team%2Fci/api%20key?trim&base64url&json",
+        ] {
+            let reference = parse(s);
+            assert_eq!(reference.to_string(), s);
+            assert_eq!(parse(&reference.to_string()), reference);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_references() {
+        for s in [
+            "slack/work/token",
+            "tokey://slack",
+            "tokey://slack/a/b/c",
+            "tokey://slack//token",
+            "tokey://slack/work/token?rot13",
+            "tokey://slack/%FF/token",
+        ] {
+            assert!(s.parse::<SecretRef>().is_err(), "{} should not parse", s);
+        }
+    }
+
+    #[test]
+    fn applies_transforms_in_order() {
+        let reference = parse("tokey://static/k/token?trim&base64");
+        assert_eq!(reference.transform("  hi \n"), "aGk=");
+        let reference = parse("tokey://static/k/token?base64url&json");
+        assert_eq!(reference.transform("\u{fb}\u{ff}"), r#""w7vDvw""#);
+        assert_eq!(
+            parse("tokey://static/k/token?urlencode").transform("a b&c"),
+            "a%20b%26c"
+        );
+    }
+
+    #[test]
+    fn default_account_falls_back_unless_labelled() {
+        let store = temp_store();
+        let save = |label: &str| {
+            let credential = StoredCredential {
+                fields: Default::default(),
+                created_at: 0,
+                last_validated: None,
+            };
+            store
+                .save_account("static", label, Account::default(), credential)
+                .unwrap();
+        };
+
+        save("work");
+        let reference = parse("tokey://static/default/token");
+        assert_eq!(reference.label(&store).unwrap(), "work");
+        assert_eq!(parse("tokey://static/token").label(&store).unwrap(), "work");
+        assert!(parse("tokey://static/other/token").label(&store).is_err());
+
+        save("default");
+        assert_eq!(reference.label(&store).unwrap(), "default");
+    }
+}